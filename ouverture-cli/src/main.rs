@@ -1,7 +1,11 @@
 use color_eyre::eyre::eyre;
 use color_eyre::{eyre::Report, Result, Section};
+use ouverture_core::audio::{AbLoop, RepeatMode, ShuffleMode, SleepTimer};
+use ouverture_core::download::{Download, DownloadState};
 use ouverture_core::music::song::Song;
-use ouverture_core::server::{Command, Reply, Server};
+use ouverture_core::podcast::{opml, Episode, Podcast};
+use ouverture_core::schedule::{Recurrence, Schedule, ScheduleTarget};
+use ouverture_core::server::{Command, Reply, Server, ServerEvent};
 use std::error::Error;
 use std::path::PathBuf;
 use std::time::Duration;
@@ -18,7 +22,7 @@ use futures_util::stream::StreamExt;
     about = "The command-line interface to the ouverture music player"
 )]
 struct Opt {
-    /// Resume playing the current song, or play the specified file
+    /// Resume playing the current song, or play the specified file, YouTube URL or stream URL
     #[structopt(long)]
     play: Option<Option<String>>,
 
@@ -34,22 +38,181 @@ struct Opt {
     #[structopt(long)]
     next: bool,
 
-    ///Play the next song
+    ///Add songs at the end of the queue
+    #[structopt(long)]
+    enqueue: Vec<String>,
+
+    ///Play a song after the current one
+    #[structopt(long)]
+    play_next: Option<String>,
+
+    ///Insert a song in the queue at a position (0 being the next song)
+    #[structopt(long, number_of_values = 2, value_names = &["position", "path"])]
+    insert: Vec<String>,
+
+    ///Move the song at a position in the queue to another
+    #[structopt(long = "move", number_of_values = 2, value_names = &["from", "to"])]
+    move_song: Vec<usize>,
+
+    ///Remove the song at a position in the queue
+    #[structopt(long)]
+    remove: Option<usize>,
+
+    ///Play the song at a position in the queue, skipping the ones before it
+    #[structopt(long)]
+    jump: Option<usize>,
+
+    ///Empty the queue, the current song keeps playing
     #[structopt(long)]
-    enqueue: Option<String>,
+    clear_queue: bool,
+
+    ///Show the queue: the songs played, the current one and the upcoming ones with their position
+    #[structopt(long)]
+    queue: bool,
 
     ///Play the previous song
     #[structopt(long)]
     previous: bool,
 
-    ///Seek the current song to the given %
+    ///Seek the current song: to a % ('50%'), to a time ('1:23', '83s'),
+    ///or relatively to the current position ('+10s', '-1:00')
+    #[structopt(long, parse(try_from_str = parse_seek), allow_hyphen_values = true)]
+    seek: Option<Command>,
+
+    ///Set the playback speed, between 0.5 and 3 (the pitch is kept)
+    #[structopt(long)]
+    speed: Option<f32>,
+
+    ///Set the shuffle mode: 'off', 'random', or 'smart' (spreads out the songs of each artist)
+    #[structopt(long)]
+    shuffle: Option<ShuffleMode>,
+
+    ///Set the repeat mode: 'off', 'one' (the current song) or 'all' (the whole queue)
+    #[structopt(long)]
+    repeat: Option<RepeatMode>,
+
+    ///Pause after some minutes, fading out
+    #[structopt(long)]
+    sleep: Option<f64>,
+
+    ///Stop once the current song is done
     #[structopt(long)]
-    seek: Option<u64>,
+    stop_after_current: bool,
+
+    ///Stop once some songs are done, counting the current one
+    #[structopt(long)]
+    stop_after: Option<u32>,
+
+    ///Cancel the sleep timer
+    #[structopt(long)]
+    cancel_sleep: bool,
+
+    ///Set the start of the A-B loop, at some seconds into the song or at the current position
+    #[structopt(long)]
+    loop_a: Option<Option<f64>>,
+
+    ///Set the end of the A-B loop, at some seconds into the song or at the current position.
+    ///Playback goes back to A when reaching B
+    #[structopt(long)]
+    loop_b: Option<Option<f64>>,
+
+    ///Clear the A-B loop
+    #[structopt(long)]
+    clear_loop: bool,
+
+    ///Show the playback status: paused, shuffle and repeat modes, speed, sleep timer, A-B loop
+    #[structopt(long)]
+    status: bool,
+
+    ///Schedule playback: at a time ('07:30', '2024-05-01 07:30') or on a cron
+    ///expression ('30 7 * * 1-5'), of --schedule-query or --schedule-playlist
+    #[structopt(long)]
+    schedule: Option<Recurrence>,
+
+    ///Songs of the library to play at the scheduled time, as for --list
+    #[structopt(long)]
+    schedule_query: Option<String>,
+
+    ///.m3u playlist to play at the scheduled time
+    #[structopt(long)]
+    schedule_playlist: Option<PathBuf>,
+
+    ///Seconds for the volume to ramp up from silence when a schedule starts
+    #[structopt(long, default_value = "30")]
+    ramp_up: u64,
+
+    ///List the schedules
+    #[structopt(long)]
+    schedules: bool,
+
+    ///Remove a schedule, by id
+    #[structopt(long)]
+    unschedule: Option<i32>,
 
     /// Scan the library
     #[structopt(long)]
     scan: bool,
 
+    ///Add an internet radio, or any HTTP(S) audio stream, to the library
+    #[structopt(long, number_of_values = 2, value_names = &["url", "name"])]
+    add_station: Vec<String>,
+
+    ///Download YouTube songs into the library, in the background
+    #[structopt(long, value_name = "url")]
+    download: Vec<String>,
+
+    ///List the downloads: queued, running and the last ones over
+    #[structopt(long)]
+    downloads: bool,
+
+    ///Cancel a download, by id
+    #[structopt(long, value_name = "id")]
+    cancel_download: Option<u32>,
+
+    ///Subscribe to a podcast, by the URL or path of its RSS or Atom feed
+    #[structopt(long, value_name = "feed")]
+    subscribe: Vec<String>,
+
+    ///Download the new episodes of the podcasts subscribed to
+    #[structopt(long)]
+    auto_download: bool,
+
+    ///Unsubscribe from a podcast, by the URL or path of its feed
+    #[structopt(long, value_name = "feed")]
+    unsubscribe: Option<String>,
+
+    ///List the podcasts subscribed to
+    #[structopt(long)]
+    podcasts: bool,
+
+    ///List the last episodes, of all the podcasts or of one of them (by feed)
+    #[structopt(long, value_name = "feed")]
+    episodes: Option<Option<String>>,
+
+    ///Check the podcasts for new episodes now
+    #[structopt(long)]
+    refresh_podcasts: bool,
+
+    ///Download podcast episodes, by the URL of their audio
+    #[structopt(long, value_name = "url")]
+    download_episode: Vec<String>,
+
+    ///Mark a podcast episode played, by the URL of its audio
+    #[structopt(long, value_name = "url")]
+    mark_played: Option<String>,
+
+    ///Mark a podcast episode unplayed, by the URL of its audio
+    #[structopt(long, value_name = "url")]
+    mark_unplayed: Option<String>,
+
+    ///Subscribe to the podcasts listed in an OPML file
+    #[structopt(long, value_name = "file")]
+    import_opml: Option<PathBuf>,
+
+    ///Write the podcasts subscribed to in an OPML file
+    #[structopt(long, value_name = "file")]
+    export_opml: Option<PathBuf>,
+
     /// List the songs (matching an optionnal criteria)
     #[structopt(long)]
     list: Option<Option<String>>,
@@ -69,6 +232,10 @@ struct Opt {
     ///Ping the server
     #[structopt(long)]
     ping: bool,
+
+    ///Keep listening to the server, and print what happens (e.g. songs failing to play)
+    #[structopt(long)]
+    watch: bool,
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -95,13 +262,20 @@ fn check_unique_command(opt: &Opt) -> Result<()> {
         opt.ping,
         opt.list.is_some(),
         opt.scan,
+        opt.status,
+        opt.queue,
+        opt.schedules,
+        opt.downloads,
+        opt.podcasts,
+        opt.episodes.is_some(),
+        opt.watch,
     ]
     .into_iter()
     .filter(|b| *b)
     .count();
     if command_count > 1 {
         return Err(Report::msg("More than one command provided!").suggestion(
-            "Provide only one of --play, --pause, --ping, --scan, --list ,--toggle, --next or --previous as argument",
+            "Provide only one of --play, --pause, --ping, --scan, --list ,--toggle, --next, --previous, --status, --queue, --schedules, --downloads, --podcasts, --episodes or --watch as argument",
         ));
     }
     Ok(())
@@ -121,19 +295,47 @@ async fn launch_command(opt: &Opt) -> Result<(), Box<dyn Error + Send + Sync>> {
     }
 
     if let Some(optionnal_path) = opt.play.as_ref() {
-        let opt_song = if let Some(path) = optionnal_path {
-            let path = PathBuf::from(path);
-            Some(Song::from_path(&path))
-        } else {
-            None
-        };
+        let opt_song = optionnal_path.as_deref().map(song_from_arg);
         handle(Server::send(&Command::Play(opt_song.clone()), &server_addr).await).await;
     }
 
-    if let Some(path) = opt.enqueue.as_ref() {
-        let path = PathBuf::from(path);
-        let song = Song::from_path(&path);
-        handle(Server::send(&Command::Enqueue(song.clone()), &server_addr).await).await;
+    match opt.enqueue.as_slice() {
+        [] => (),
+        [path] => {
+            let song = song_from_arg(path);
+            handle(Server::send(&Command::Enqueue(song), &server_addr).await).await;
+        }
+        paths => {
+            let songs = paths.iter().map(|path| song_from_arg(path)).collect();
+            handle(Server::send(&Command::EnqueueMany(songs), &server_addr).await).await;
+        }
+    }
+
+    if let Some(path) = opt.play_next.as_ref() {
+        let song = song_from_arg(path);
+        handle(Server::send(&Command::PlayNext(song), &server_addr).await).await;
+    }
+    if let [position, path] = opt.insert.as_slice() {
+        let position = position
+            .parse::<usize>()
+            .map_err(|e| format!("invalid queue position '{position}': {e}"))?;
+        let song = song_from_arg(path);
+        handle(Server::send(&Command::Insert(position, song), &server_addr).await).await;
+    }
+    if let [from, to] = opt.move_song.as_slice() {
+        handle(Server::send(&Command::Move(*from, *to), &server_addr).await).await;
+    }
+    if let Some(position) = opt.remove {
+        handle(Server::send(&Command::Remove(position), &server_addr).await).await;
+    }
+    if let Some(position) = opt.jump {
+        handle(Server::send(&Command::JumpTo(position), &server_addr).await).await;
+    }
+    if opt.clear_queue {
+        handle(Server::send(&Command::ClearQueue, &server_addr).await).await;
+    }
+    if opt.queue {
+        handle(Server::send(&Command::GetQueue, &server_addr).await).await;
     }
 
     if opt.pause {
@@ -151,22 +353,166 @@ async fn launch_command(opt: &Opt) -> Result<(), Box<dyn Error + Send + Sync>> {
     if opt.scan {
         handle(Server::send(&Command::Scan, &server_addr).await).await;
     }
+    if let [url, name] = opt.add_station.as_slice() {
+        if !Song::is_stream_url(url) {
+            return Err(format!("invalid stream URL '{url}', expected http:// or https://").into());
+        }
+        let song = Song::from_stream_url(url, Some(name.clone()));
+        handle(Server::send(&Command::AddSong(song), &server_addr).await).await;
+    }
 
-    if let Some(seek) = opt.seek {
-        handle(
-            Server::send(
-                &Command::Seek(std::cmp::min(seek, 100) as f32 / 100f32),
-                &server_addr,
-            )
-            .await,
-        )
-        .await;
+    for url in &opt.download {
+        if !Song::is_youtube_url(url) {
+            return Err(format!("invalid YouTube URL '{url}'").into());
+        }
+        let song = Song::from_youtube_url(url);
+        handle(Server::send(&Command::Download(song), &server_addr).await).await;
+    }
+    if let Some(id) = opt.cancel_download {
+        handle(Server::send(&Command::CancelDownload(id), &server_addr).await).await;
+    }
+    if opt.downloads {
+        handle(Server::send(&Command::GetDownloads, &server_addr).await).await;
+    }
+
+    let mut subscriptions: Vec<Podcast> = (opt.subscribe.iter())
+        .map(|feed| Podcast {
+            url: feed_location(feed),
+            ..Default::default()
+        })
+        .collect();
+    if let Some(path) = opt.import_opml.as_ref() {
+        let text = std::fs::read_to_string(path)?;
+        subscriptions.extend(opml::parse(&text)?);
+    }
+    for podcast in subscriptions {
+        let podcast = Podcast {
+            auto_download: opt.auto_download,
+            ..podcast
+        };
+        handle(Server::send(&Command::AddPodcast(podcast), &server_addr).await).await;
+    }
+    if let Some(feed) = opt.unsubscribe.as_ref() {
+        let command = Command::RemovePodcast(feed_location(feed));
+        handle(Server::send(&command, &server_addr).await).await;
+    }
+    if opt.refresh_podcasts {
+        handle(Server::send(&Command::RefreshPodcasts, &server_addr).await).await;
+    }
+    for url in &opt.download_episode {
+        let song = Song::from_enclosure_url(url);
+        handle(Server::send(&Command::Download(song), &server_addr).await).await;
+    }
+    for (url, played) in [(&opt.mark_played, true), (&opt.mark_unplayed, false)] {
+        if let Some(url) = url {
+            let command = Command::SetPlayed(Song::from_enclosure_url(url), played);
+            handle(Server::send(&command, &server_addr).await).await;
+        }
+    }
+    if opt.podcasts {
+        handle(Server::send(&Command::GetPodcasts, &server_addr).await).await;
+    }
+    if let Some(feed) = opt.episodes.as_ref() {
+        let command = Command::GetEpisodes(feed.as_deref().map(feed_location));
+        handle(Server::send(&command, &server_addr).await).await;
+    }
+    if let Some(path) = opt.export_opml.as_ref() {
+        match Server::send_wait(&Command::GetPodcasts, &server_addr).await? {
+            Reply::Podcasts(podcasts) => {
+                std::fs::write(path, opml::to_opml(&podcasts))?;
+                println!("{} podcasts written to {path:?}", podcasts.len());
+            }
+            reply => return Err(format!("unexpected reply to the podcasts: {reply:?}").into()),
+        }
+    }
+
+    if let Some(seek) = opt.seek.as_ref() {
+        handle(Server::send(seek, &server_addr).await).await;
+    }
+
+    if let Some(speed) = opt.speed {
+        handle(Server::send(&Command::SetSpeed(speed), &server_addr).await).await;
+    }
+
+    if let Some(shuffle) = opt.shuffle {
+        handle(Server::send(&Command::SetShuffle(shuffle), &server_addr).await).await;
+    }
+    if let Some(repeat) = opt.repeat {
+        handle(Server::send(&Command::SetRepeat(repeat), &server_addr).await).await;
+    }
+    let sleep_timer = if let Some(minutes) = opt.sleep {
+        if !(minutes >= 0.0 && minutes.is_finite()) {
+            return Err(format!("invalid sleep time '{minutes}' minutes").into());
+        }
+        Some(SleepTimer::After(Duration::from_secs_f64(minutes * 60.0)))
+    } else if opt.stop_after_current {
+        Some(SleepTimer::AfterSongs(1))
+    } else {
+        opt.stop_after.map(SleepTimer::AfterSongs)
+    };
+    if let Some(timer) = sleep_timer {
+        handle(Server::send(&Command::SetSleepTimer(timer), &server_addr).await).await;
+    }
+    if opt.cancel_sleep {
+        handle(Server::send(&Command::CancelSleepTimer, &server_addr).await).await;
+    }
+
+    let to_ms = |seconds: Option<f64>| match seconds {
+        Some(seconds) if !(seconds >= 0.0 && seconds.is_finite()) => {
+            Err(format!("invalid loop point '{seconds}' seconds"))
+        }
+        seconds => Ok(seconds.map(|seconds| (seconds * 1000.0) as u64)),
+    };
+    if let Some(seconds) = opt.loop_a {
+        let command = Command::SetLoopStart(to_ms(seconds)?);
+        handle(Server::send(&command, &server_addr).await).await;
+    }
+    if let Some(seconds) = opt.loop_b {
+        let command = Command::SetLoopEnd(to_ms(seconds)?);
+        handle(Server::send(&command, &server_addr).await).await;
+    }
+    if opt.clear_loop {
+        handle(Server::send(&Command::ClearLoop, &server_addr).await).await;
+    }
+
+    if opt.status {
+        handle(Server::send(&Command::GetStatus, &server_addr).await).await;
+    }
+
+    if let Some(when) = opt.schedule.as_ref() {
+        let what = match (&opt.schedule_query, &opt.schedule_playlist) {
+            (Some(query), None) => ScheduleTarget::Query(query.clone()),
+            // the server may not run from the same directory
+            (None, Some(path)) => ScheduleTarget::Playlist(std::fs::canonicalize(path)?),
+            _ => {
+                return Err(
+                    "--schedule needs one of --schedule-query or --schedule-playlist".into(),
+                )
+            }
+        };
+        let schedule = Schedule {
+            id: 0,
+            when: when.clone(),
+            what,
+            ramp_up: Duration::from_secs(opt.ramp_up),
+        };
+        handle(Server::send(&Command::AddSchedule(schedule), &server_addr).await).await;
+    }
+    if opt.schedules {
+        handle(Server::send(&Command::GetSchedules, &server_addr).await).await;
+    }
+    if let Some(id) = opt.unschedule {
+        handle(Server::send(&Command::RemoveSchedule(id), &server_addr).await).await;
     }
 
     if let Some(optionnal_str) = opt.list.as_ref() {
         handle(Server::send(&Command::GetList(optionnal_str.clone()), &server_addr).await).await;
     }
 
+    if opt.watch {
+        handle(Server::send(&Command::Subscribe, &server_addr).await).await;
+    }
+
     if opt.ping {
         loop {
             let start = std::time::Instant::now();
@@ -195,6 +541,65 @@ async fn launch_command(opt: &Opt) -> Result<(), Box<dyn Error + Send + Sync>> {
     Ok(())
 }
 
+/// Parse a seek argument into the matching seek command.
+/// A bare number is a percentage, as in '50%'
+fn parse_seek(arg: &str) -> Result<Command, String> {
+    let arg = arg.trim();
+    let invalid = || format!("invalid seek '{arg}', expected e.g. 50%, 1:23, 83s, +10s or -10s");
+
+    if let Some(time) = arg.strip_prefix('+') {
+        let ms = parse_time_ms(time).ok_or_else(invalid)?;
+        return Ok(Command::SeekBy(ms as i64));
+    }
+    if let Some(time) = arg.strip_prefix('-') {
+        let ms = parse_time_ms(time).ok_or_else(invalid)?;
+        return Ok(Command::SeekBy(-(ms as i64)));
+    }
+
+    let percent = arg.strip_suffix('%').unwrap_or(arg);
+    if starts_with_digit(percent) {
+        if let Ok(percent) = percent.parse::<f32>() {
+            if percent.is_finite() {
+                return Ok(Command::Seek(percent.min(100.0) / 100.0));
+            }
+        }
+    }
+
+    let ms = parse_time_ms(arg).ok_or_else(invalid)?;
+    Ok(Command::SeekTo(ms))
+}
+
+/// Parse a time such as '83s', '83.5s', '1:23' or '1:01:23' into milliseconds
+fn parse_time_ms(time: &str) -> Option<u64> {
+    if !starts_with_digit(time) || time.contains(['+', '-']) {
+        return None;
+    }
+    let seconds = if let Some(seconds) = time.strip_suffix('s') {
+        seconds.parse::<f64>().ok()?
+    } else if time.contains(':') {
+        let mut parts = time.rsplit(':');
+        let seconds = parts.next()?.parse::<f64>().ok()?;
+        let mut multiplier = 60.0;
+        let mut total = seconds;
+        for part in parts {
+            total += part.parse::<u64>().ok()? as f64 * multiplier;
+            multiplier *= 60.0;
+        }
+        total
+    } else {
+        return None;
+    };
+    if seconds.is_sign_negative() || !seconds.is_finite() {
+        return None;
+    }
+    Some((seconds * 1000.0) as u64)
+}
+
+// numbers with a sign, or such as 'inf' and 'nan', aren't positions
+fn starts_with_digit(number: &str) -> bool {
+    number.starts_with(|c: char| c.is_ascii_digit() || c == '.')
+}
+
 async fn handle<T>(stream: T)
 where
     T: Stream<Item = Result<Reply, Box<dyn Error + Send + Sync>>>,
@@ -205,8 +610,213 @@ where
             Ok(Reply::Done) => println!("Done!"),
             Ok(Reply::Received(s)) => println!("Server received '{}' command", s),
             Ok(Reply::List(l)) => println!("Result: {:?}", l),
+            Ok(Reply::Queue(queue)) => {
+                for song in queue.past {
+                    println!("   {}", song_title(&song));
+                }
+                match queue.current {
+                    Some(song) => println!(" > {}", song_title(&song)),
+                    None => println!(" > (nothing playing)"),
+                }
+                for (position, song) in queue.future.iter().enumerate() {
+                    println!("{position:2} {}", song_title(song));
+                }
+            }
+            Ok(Reply::Schedules(schedules)) => {
+                for schedule in schedules {
+                    println!(
+                        "{:3} {}: {} (ramp up {}s)",
+                        schedule.id,
+                        schedule.when,
+                        schedule.what,
+                        schedule.ramp_up.as_secs()
+                    );
+                }
+            }
+            Ok(Reply::Downloads(downloads)) => {
+                for download in downloads {
+                    println!("{:3} {}", download.id, download_line(&download));
+                }
+            }
+            Ok(Reply::Podcasts(podcasts)) => {
+                for podcast in podcasts {
+                    let title = podcast.title.as_deref().unwrap_or("<untitled>");
+                    let auto = if podcast.auto_download {
+                        " (auto-download)"
+                    } else {
+                        ""
+                    };
+                    println!("{title}: {}{auto}", podcast.url);
+                }
+            }
+            Ok(Reply::Episodes(episodes)) => {
+                for episode in episodes {
+                    println!("{}", episode_line(&episode));
+                }
+            }
+            Ok(Reply::Status(status)) => {
+                println!(
+                    "{}, shuffle: {}, repeat: {}, speed: {}",
+                    if status.paused { "paused" } else { "playing" },
+                    status.shuffle,
+                    status.repeat,
+                    status.speed
+                );
+                match status.sleep_timer {
+                    Some(SleepTimer::After(left)) => {
+                        let seconds = left.as_secs();
+                        println!("pausing in {}:{:02}", seconds / 60, seconds % 60)
+                    }
+                    Some(SleepTimer::AfterSongs(1)) => println!("stopping after the current song"),
+                    Some(SleepTimer::AfterSongs(left)) => println!("stopping after {left} songs"),
+                    None => (),
+                }
+                let loop_point = |ms: Option<u64>| match ms {
+                    Some(ms) => format!("{}:{:02}.{:03}", ms / 60_000, ms / 1000 % 60, ms % 1000),
+                    None => "-".to_string(),
+                };
+                if status.ab_loop != AbLoop::default() {
+                    println!(
+                        "loop from {} to {}",
+                        loop_point(status.ab_loop.a),
+                        loop_point(status.ab_loop.b)
+                    );
+                }
+                if let Some(title) = status.stream_title {
+                    println!("the stream plays '{title}'");
+                }
+            }
+            Ok(Reply::Event(ServerEvent::PlaybackFailed(song, reason))) => {
+                let title = song
+                    .and_then(|s| s.title)
+                    .unwrap_or("<unknown>".to_string());
+                println!("Failed to play '{title}': {reason}, skipped it")
+            }
+            Ok(Reply::Event(ServerEvent::StreamTitle(title))) => {
+                println!("The stream now plays '{title}'")
+            }
+            Ok(Reply::Event(ServerEvent::Download(download))) => {
+                println!("Download {}: {}", download.id, download_line(&download))
+            }
+            // too many to print, they are for visualizers
+            Ok(Reply::Event(ServerEvent::Levels(_))) => (),
+            Ok(Reply::Error(reason)) => println!("Failed: {reason}"),
             Err(e) => println!("Error: {:?}", e),
             _ => println!("unamagned reply yet"),
         }
     }
 }
+
+/// A song from a file path, a YouTube URL, or an HTTP(S) stream URL
+fn song_from_arg(arg: &str) -> Song {
+    if Song::is_youtube_url(arg) {
+        Song::from_youtube_url(arg)
+    } else if Song::is_stream_url(arg) {
+        Song::from_stream_url(arg, None)
+    } else {
+        Song::from_path(&PathBuf::from(arg))
+    }
+}
+
+/// 'title: state', the state with its progress or why it failed
+fn download_line(download: &Download) -> String {
+    let state = match &download.state {
+        DownloadState::Queued => "queued".to_string(),
+        DownloadState::Running => format!(
+            "{:.0}% (attempt {})",
+            download.progress * 100.0,
+            download.attempts
+        ),
+        DownloadState::Done(song) => format!("done, as '{}'", song_title(song)),
+        DownloadState::Failed(reason) => format!("failed: {reason}"),
+        DownloadState::Cancelled => "cancelled".to_string(),
+    };
+    format!("{}: {state}", song_title(&download.song))
+}
+
+/// The feed as given if it's a URL, else as an absolute path, since the server
+/// may not run from the same directory
+fn feed_location(feed: &str) -> String {
+    if Song::is_stream_url(feed) {
+        return feed.to_string();
+    }
+    match std::fs::canonicalize(feed.strip_prefix("file://").unwrap_or(feed)) {
+        Ok(path) => path.to_string_lossy().to_string(),
+        Err(_) => feed.to_string(),
+    }
+}
+
+/// 'date [played] title (downloaded): url'
+fn episode_line(episode: &Episode) -> String {
+    let date = (episode.published)
+        .map(|date| date.format("%Y-%m-%d").to_string())
+        .unwrap_or("----------".to_string());
+    let played = if episode.played { "x" } else { " " };
+    let downloaded = if episode.downloaded {
+        " (downloaded)"
+    } else {
+        ""
+    };
+    let url: String = episode
+        .song
+        .source
+        .clone()
+        .map(Into::into)
+        .unwrap_or_default();
+    let url = url.strip_prefix("enclosure:").unwrap_or(&url);
+    format!(
+        "{date} [{played}] {}{downloaded}: {url}",
+        song_title(&episode.song)
+    )
+}
+
+/// 'artist - title', or what is known of it
+fn song_title(song: &Song) -> String {
+    match (&song.artist, &song.title) {
+        (Some(artist), Some(title)) => format!("{artist} - {title}"),
+        (None, Some(title)) => title.clone(),
+        _ => song
+            .source
+            .clone()
+            .map(Into::<String>::into)
+            .unwrap_or("<unknown>".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn times() {
+        assert_eq!(parse_time_ms("83s"), Some(83_000));
+        assert_eq!(parse_time_ms("83.5s"), Some(83_500));
+        assert_eq!(parse_time_ms("1:23"), Some(83_000));
+        assert_eq!(parse_time_ms("1:01:23"), Some(3_683_000));
+        assert_eq!(parse_time_ms("0:00.250"), Some(250));
+        for invalid in ["83", "", "s", "-5s", "+5s", "1:-5", "a:23", "infs", "nans"] {
+            assert_eq!(parse_time_ms(invalid), None, "{invalid}");
+        }
+    }
+
+    #[test]
+    fn seeks() {
+        assert!(matches!(parse_seek("1:23"), Ok(Command::SeekTo(83_000))));
+        assert!(matches!(parse_seek("83s"), Ok(Command::SeekTo(83_000))));
+        assert!(matches!(parse_seek("+10s"), Ok(Command::SeekBy(10_000))));
+        assert!(matches!(parse_seek("-10s"), Ok(Command::SeekBy(-10_000))));
+        assert!(matches!(parse_seek("-1:30"), Ok(Command::SeekBy(-90_000))));
+        assert!(matches!(parse_seek("50%"), Ok(Command::Seek(seek)) if seek == 0.5));
+        assert!(matches!(parse_seek(" 25 "), Ok(Command::Seek(seek)) if seek == 0.25));
+        assert!(matches!(parse_seek("150%"), Ok(Command::Seek(seek)) if seek == 1.0));
+    }
+
+    #[test]
+    fn invalid_seeks() {
+        for invalid in [
+            "-10", "+10", "-10%", "+10%", "nan", "inf", "-inf", "1:2x", "", "%",
+        ] {
+            assert!(parse_seek(invalid).is_err(), "{invalid}");
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use log::debug;
+use log::{debug, warn};
 use std::net::SocketAddr;
 use tokio::{
     net::{TcpListener, TcpStream},
@@ -9,24 +9,29 @@ use crate::server::Server;
 use std::sync::{Arc, Mutex};
 
 use axum::{
+    extract::{Query, State},
     http::StatusCode,
     routing::{get, post},
     Json, Router,
 };
 use serde::{Deserialize, Serialize};
 
+use crate::audio::{waveform, Waveform};
+use crate::config::Config;
+use crate::music::song::{Song, SongSource};
+
 pub struct RouterTask {
     pub addr: SocketAddr,
     handle: JoinHandle<()>,
     stop: Arc<Mutex<bool>>,
 }
 
-pub async fn start_router() -> RouterTask {
+pub async fn start_router(config: Config) -> RouterTask {
     let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
 
     let addr = listener.local_addr().unwrap().clone();
 
-    let handle = tokio::spawn(async move { router(listener).await });
+    let handle = tokio::spawn(async move { router(listener, config).await });
 
     RouterTask {
         addr,
@@ -41,13 +46,39 @@ pub async fn stop_router(router: RouterTask) {
     router.handle.await.unwrap()
 }
 
-async fn router(listener: TcpListener) -> () {
+async fn router(listener: TcpListener, config: Config) -> () {
     debug!("launched API router");
 
-    let app = Router::new().route("/", get(root));
+    let app = Router::new()
+        .route("/", get(root))
+        .route("/waveform", get(get_waveform))
+        .with_state(config);
     axum::serve(listener, app).await.unwrap();
 }
 
 async fn root() -> &'static str {
     "Hello, World!"
 }
+
+/// A song, by its source as stored in the database ('path:/music/song.mp3')
+#[derive(Deserialize)]
+struct SongQuery {
+    source: String,
+}
+
+async fn get_waveform(
+    State(config): State<Config>,
+    Query(query): Query<SongQuery>,
+) -> Result<Json<Waveform>, StatusCode> {
+    let song = Song {
+        source: Some(SongSource::from(query.source)),
+        ..Default::default()
+    };
+    match waveform(&config, song).await {
+        Ok(waveform) => Ok(Json(waveform)),
+        Err(e) => {
+            warn!("no waveform for the song: {e}");
+            Err(StatusCode::NOT_FOUND)
+        }
+    }
+}
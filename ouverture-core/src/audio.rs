@@ -1,63 +1,237 @@
+mod capture;
+mod dsp;
+mod equalizer;
+mod format;
+mod meter;
 mod output;
 mod player;
+mod queue;
+#[cfg(any(feature = "cpal", not(target_os = "linux")))]
+mod resampler;
+mod resume;
+mod stream;
+mod stretch;
+mod waveform;
 
-use tokio::task::spawn;
+use tokio::task::{spawn, spawn_blocking};
 
+use color_eyre::eyre::eyre;
 use log::{debug, warn};
-use std::collections::VecDeque;
-use std::sync::{Arc, Mutex};
+use serde::{Deserialize, Serialize};
+use std::sync::{mpsc, Arc, Mutex};
 
-use crate::music::song::Song;
+use crate::config::Config;
+use crate::music::song::{Song, SongSource};
+use crate::resolver::Resolvers;
+use crate::server::ServerEvent;
 
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::broadcast::{channel, Receiver, Sender};
-use tokio::task::JoinHandle;
-use tokio::time::{self, Duration, Instant};
+use tokio::task::{AbortHandle, JoinHandle};
+use tokio::time::{Duration, Instant};
 
 pub use player::{start_audio_thread, stop_audio_thread, AudioThread};
 
-pub use player::{audio_thread_send_cmd, AudioCommand, AudioEvent};
+pub use player::{audio_thread_send_cmd, probe_duration, AudioCommand, AudioEvent};
 
-pub fn audio_thread_play_song(tx: &Sender<AudioCommand>, song: Song) {
-    audio_thread_send_cmd(AudioCommand::PlayNew(song), tx);
+pub use capture::{Capture, CapturedOutput};
+
+pub use equalizer::{Equalizer, EqualizerBand, FilterKind, GRAPHIC_FREQUENCIES};
+
+pub use format::OutputFormat;
+
+pub use meter::{Levels, SILENCE_DB, SPECTRUM_BANDS, SPECTRUM_MAX_HZ, SPECTRUM_MIN_HZ};
+
+use queue::Queue;
+pub use queue::{QueueListing, RepeatMode, ShuffleMode};
+
+use resume::{load_positions, ResumePositions};
+
+pub use stretch::{MAX_SPEED, MIN_SPEED};
+
+pub use waveform::{compute_waveform, waveform, Waveform, WAVEFORM_BUCKETS};
+
+pub fn audio_thread_play_song(tx: &mpsc::Sender<AudioCommand>, song: Song, source: SongSource) {
+    audio_thread_send_cmd(AudioCommand::PlayNew(song, source), tx);
 }
 
-pub fn audio_thread_play(tx: &Sender<AudioCommand>) {
+pub fn audio_thread_play(tx: &mpsc::Sender<AudioCommand>) {
     audio_thread_send_cmd(AudioCommand::Play, tx);
 }
 
-pub fn audio_thread_pause(tx: &Sender<AudioCommand>) {
+pub fn audio_thread_pause(tx: &mpsc::Sender<AudioCommand>) {
     audio_thread_send_cmd(AudioCommand::Pause, tx);
 }
-pub fn audio_thread_stop(tx: &Sender<AudioCommand>) {
+pub fn audio_thread_stop(tx: &mpsc::Sender<AudioCommand>) {
     audio_thread_send_cmd(AudioCommand::Stop, tx);
 }
 
+pub fn audio_thread_set_next(tx: &mpsc::Sender<AudioCommand>, opt_song: Option<Song>) {
+    audio_thread_send_cmd(AudioCommand::SetNext(opt_song), tx);
+}
+
+/// What the clients show of the player, besides the current song
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PlaybackStatus {
+    pub paused: bool,
+    pub shuffle: ShuffleMode,
+    pub repeat: RepeatMode,
+    pub speed: f32,
+    pub sleep_timer: Option<SleepTimer>, // what's left of it
+    pub ab_loop: AbLoop,
+    pub stream_title: Option<String>, // what the stream being played plays, if it tells
+}
+
+/// Loop points within the current song, in milliseconds. Once both are
+/// set, playback goes back to A whenever it reaches B
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AbLoop {
+    pub a: Option<u64>,
+    pub b: Option<u64>,
+}
+
+impl AbLoop {
+    /// The points to loop between, if both are set
+    fn active(&self) -> Option<(u64, u64)> {
+        match (self.a, self.b) {
+            (Some(a), Some(b)) => Some((a, b)),
+            _ => None,
+        }
+    }
+}
+
+/// When to stop playing
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum SleepTimer {
+    /// After some time, fading out over the last seconds. Playback is paused
+    After(Duration),
+    /// Once some songs are done playing, 1 being the current one.
+    /// Playback is stopped, and resumes with the next song
+    AfterSongs(u32),
+}
+
+// needed by the EnumString and EnumIter derives of server::Command
+impl Default for SleepTimer {
+    fn default() -> Self {
+        SleepTimer::AfterSongs(1)
+    }
+}
+
+/// A sleep timer counting down
+#[derive(Clone)]
+enum ArmedSleepTimer {
+    Until(Instant, AbortHandle), // the task that fades out and pauses
+    Songs(u32),
+}
+
+// how long the sleep timer fades out for, at most
+const SLEEP_FADE_OUT_MS: u64 = 10_000;
+// how fast the volume comes back when a sleep timer is cancelled while fading out
+const SLEEP_FADE_CANCEL_MS: u64 = 500;
+
 #[derive(Clone)]
 pub struct AudioState {
-    pub cmd_tx: Sender<AudioCommand>,
-    pub current_song: Option<Song>,
+    pub cmd_tx: mpsc::Sender<AudioCommand>,
     pub current_seek: f32,
+    pub current_seek_ms: u64,
+    pub equalizer: Equalizer,
+    pub speed: f32,
+    pub capture: Capture, // what was written to the audio output, when capturing
+    server_events: Sender<ServerEvent>,
+    audio_events: Sender<AudioEvent>, // for the songs resolved off the audio thread
+    resolvers: Resolvers,
+    resolving: u64, // counts the songs played, what's resolved for an earlier one is dropped
     paused: bool,
+    stopped: bool, // the audio thread forgot the current song, it has to be played anew
+    sleep_timer: Option<ArmedSleepTimer>,
     events_received: u64, //count the number of event received
-    queue_future: VecDeque<Song>,
-    queue_past: VecDeque<Song>,
+    queue: Queue,
+    resume: ResumePositions,
+    ab_loop: AbLoop,
+    stream_title: Option<String>,
 }
 
 const AUDIO_GET_SEEK_POLL_FREQ_MS: u64 = 20;
+const AUDIO_GET_SEEK_TIMEOUT_MS: u64 = 400;
 
 impl AudioState {
     pub fn play(&mut self, opt_song: Option<Song>) {
         if let Some(song) = opt_song {
-            audio_thread_play_song(&self.cmd_tx, song.clone());
-            self.current_song = Some(song);
+            self.play_song(song.clone());
+            self.queue.set_current(song);
             self.paused = false;
+            self.stopped = false;
+            self.send_next();
         } else {
-            if self.current_song.is_some() {
-                audio_thread_play(&self.cmd_tx);
+            if let Some(song) = self.queue.current().cloned() {
+                if self.stopped {
+                    self.play_song(song);
+                    self.stopped = false;
+                } else {
+                    audio_thread_play(&self.cmd_tx);
+                }
                 self.paused = false;
             }
         }
     }
+
+    /// Have the audio thread play a song, from where it was left if it's resumable.
+    /// The loop points of the song played before are dropped
+    fn play_song(&mut self, song: Song) {
+        self.ab_loop = AbLoop::default();
+        self.stream_title = None;
+        self.resolving += 1;
+        match song.source.clone().filter(Resolvers::is_playable) {
+            Some(source) => self.send_play(song, source),
+            None => self.resolve_then_play(song),
+        }
+    }
+
+    fn send_play(&mut self, song: Song, source: SongSource) {
+        match self.resume.position(&song) {
+            Some(seek_ms) => {
+                debug!("resuming {:?} at {seek_ms} ms", song.title);
+                let cmd = AudioCommand::PlayNewFrom(song, source, seek_ms);
+                audio_thread_send_cmd(cmd, &self.cmd_tx);
+            }
+            None => audio_thread_play_song(&self.cmd_tx, song, source),
+        }
+    }
+
+    /// Resolve an online song on a blocking task, as it may take long (e.g. downloading it),
+    /// and play it once it's done. The audio thread stays free to take other commands
+    fn resolve_then_play(&mut self, song: Song) {
+        // the song played before would go on, and the queue with it once it finishes
+        audio_thread_stop(&self.cmd_tx);
+        let resolvers = self.resolvers.clone();
+        let audio_events = self.audio_events.clone();
+        let id = self.resolving;
+        spawn(async move {
+            let source = song.source.clone();
+            let resolved = spawn_blocking(move || match source {
+                Some(source) => resolvers.resolve(&source),
+                None => Err("the song has no source to play".to_string()),
+            })
+            .await
+            .unwrap_or_else(|e| Err(format!("failed to resolve the song: {e}")));
+            let _ = audio_events.send(AudioEvent::Resolved(id, song, resolved));
+        });
+    }
+
+    /// An online song was resolved, play it if it's still the one to play
+    fn resolved(&mut self, id: u64, song: Song, resolved: Result<SongSource, String>) {
+        if id != self.resolving {
+            debug!("{:?} resolved too late, something else plays", song.title);
+            return;
+        }
+        match resolved {
+            // it's resolved again on play
+            Ok(_) if self.paused => self.stopped = true,
+            Ok(source) => self.send_play(song, source),
+            Err(reason) => self.failed(reason),
+        }
+    }
+
     pub fn pause(&mut self) {
         audio_thread_pause(&self.cmd_tx);
         self.paused = true;
@@ -70,63 +244,376 @@ impl AudioState {
         }
     }
 
+    pub fn current_song(&self) -> Option<Song> {
+        self.queue.current().cloned()
+    }
+
     pub fn enqueue(&mut self, song: Song) {
-        self.queue_future.push_back(song);
+        self.queue.enqueue(song);
+        self.send_next();
     }
 
-    pub fn next(&mut self) {
-        let opt_song = self.queue_future.pop_front();
-        if opt_song.is_some() {
-            if let Some(song) = &self.current_song {
-                self.queue_past.push_back(song.clone());
-            }
-            self.play(opt_song);
+    /// Insert a song among the upcoming ones, 0 being the next song
+    pub fn insert(&mut self, position: usize, song: Song) {
+        self.queue.insert(position, song);
+        self.send_next();
+    }
+
+    pub fn play_next(&mut self, song: Song) {
+        self.insert(0, song);
+    }
+
+    pub fn enqueue_many(&mut self, songs: Vec<Song>) {
+        self.queue.enqueue_many(songs);
+        self.send_next();
+    }
+
+    pub fn move_song(&mut self, from: usize, to: usize) {
+        if self.queue.move_song(from, to) {
+            self.send_next();
+        } else {
+            warn!("no song at position {from} in the queue to move");
+        }
+    }
+
+    pub fn remove(&mut self, position: usize) {
+        match self.queue.remove(position) {
+            Some(_) => self.send_next(),
+            None => warn!("no song at position {position} in the queue to remove"),
+        }
+    }
+
+    /// Empty the queue, the current song keeps playing
+    pub fn clear_queue(&mut self) {
+        self.queue.clear();
+        self.send_next();
+    }
+
+    /// Play an upcoming song right away, skipping the ones before it
+    pub fn jump_to(&mut self, position: usize) {
+        match self.queue.jump_to(position).cloned() {
+            Some(song) => self.play_from_queue(Some(song)),
+            None => warn!("no song at position {position} in the queue to jump to"),
+        }
+    }
+
+    pub fn queue(&self) -> QueueListing {
+        self.queue.listing()
+    }
+
+    /// Replace the queue with these songs and play them, the volume
+    /// ramping up from silence, as an alarm clock does
+    pub fn play_all_fading_in(&mut self, songs: Vec<Song>, ramp_up: Duration) {
+        let mut songs = songs.into_iter();
+        if let Some(first) = songs.next() {
+            self.queue.clear();
+            audio_thread_send_cmd(AudioCommand::SetVolume(0.0, 0), &self.cmd_tx);
+            self.play(Some(first));
+            audio_thread_send_cmd(
+                AudioCommand::SetVolume(1.0, ramp_up.as_millis() as u64),
+                &self.cmd_tx,
+            );
+            self.enqueue_many(songs.collect());
+        }
+    }
+
+    /// Let the audio thread know which song comes next, so that it can
+    /// be opened in advance and chained without gap
+    fn send_next(&self) {
+        let upcoming = match self.sleep_timer {
+            // the current song is the last one
+            Some(ArmedSleepTimer::Songs(1)) => None,
+            // chained songs start from the beginning: let it be played anew instead.
+            // Online songs are resolved when they play, the audio thread doesn't resolve them
+            _ => self
+                .queue
+                .upcoming()
+                .filter(|song| self.resume.position(song).is_none())
+                .filter(|song| song.source.as_ref().is_some_and(Resolvers::is_playable))
+                .cloned(),
+        };
+        audio_thread_set_next(&self.cmd_tx, upcoming);
+    }
+
+    /// The audio thread already went on with the upcoming song
+    fn next_started(&mut self) {
+        let sleep = self.song_done();
+        self.forget_current_position();
+        self.ab_loop = AbLoop::default();
+        self.stream_title = None;
+        self.queue.finished();
+        self.paused = false;
+        if sleep {
+            self.stop();
+        }
+        self.send_next();
+    }
+
+    /// The current song is done, and the audio thread had nothing to go on with
+    fn finished(&mut self) {
+        let sleep = self.song_done();
+        self.forget_current_position();
+        let opt_song = self.queue.finished().cloned();
+        if sleep && opt_song.is_some() {
+            self.stop();
         } else {
-            if let Some(song) = &self.current_song {
-                self.queue_past.push_back(song.clone());
+            self.play_from_queue(opt_song);
+        }
+    }
+
+    /// The current song was played to its end, it starts over next time
+    fn forget_current_position(&mut self) {
+        if let Some(song) = self.queue.current().cloned() {
+            self.resume.done(&song);
+        }
+    }
+
+    /// Count a song played to its end for the sleep timer, returns whether it's time to stop
+    fn song_done(&mut self) -> bool {
+        match &mut self.sleep_timer {
+            Some(ArmedSleepTimer::Songs(left)) if *left > 1 => {
+                *left -= 1;
+                false
+            }
+            Some(ArmedSleepTimer::Songs(_)) => {
+                debug!("sleep timer elapsed");
+                self.sleep_timer = None;
+                true
             }
-            audio_thread_stop(&self.cmd_tx);
-            self.current_song = None;
-            self.paused = true;
+            _ => false,
         }
     }
+
+    /// Stop playing, the current song starts over on play
+    fn stop(&mut self) {
+        audio_thread_stop(&self.cmd_tx);
+        self.paused = true;
+        self.stopped = true;
+    }
+
+    pub fn next(&mut self) {
+        let opt_song = self.queue.next().cloned();
+        self.play_from_queue(opt_song);
+    }
+
     pub fn previous(&mut self) {
         // TODO resume from start if seek < 5s
-        let opt_song = self.queue_past.pop_back();
-        if opt_song.is_some() {
-            if let Some(current_song) = &self.current_song {
-                self.queue_future.push_front(current_song.clone());
+        if let Some(song) = self.queue.previous().cloned() {
+            self.play_from_queue(Some(song));
+        }
+    }
+
+    /// Play the song the queue moved to, or stop at the end of the queue
+    fn play_from_queue(&mut self, opt_song: Option<Song>) {
+        match opt_song {
+            Some(song) => {
+                self.play_song(song);
+                self.paused = false;
+                self.stopped = false;
+                self.send_next();
+            }
+            None => {
+                audio_thread_stop(&self.cmd_tx);
+                self.paused = true;
+            }
+        }
+    }
+
+    pub fn set_shuffle(&mut self, shuffle: ShuffleMode) {
+        self.queue.set_shuffle(shuffle);
+        self.send_next();
+    }
+
+    pub fn set_repeat(&mut self, repeat: RepeatMode) {
+        self.queue.repeat = repeat;
+        self.send_next();
+    }
+
+    pub fn status(&self) -> PlaybackStatus {
+        PlaybackStatus {
+            paused: self.paused,
+            shuffle: self.queue.shuffle,
+            repeat: self.queue.repeat,
+            speed: self.speed,
+            sleep_timer: self.sleep_timer.as_ref().map(|timer| match timer {
+                ArmedSleepTimer::Until(deadline, _) => {
+                    SleepTimer::After(deadline.saturating_duration_since(Instant::now()))
+                }
+                ArmedSleepTimer::Songs(left) => SleepTimer::AfterSongs(*left),
+            }),
+            ab_loop: self.ab_loop,
+            stream_title: self.stream_title.clone(),
+        }
+    }
+
+    /// Set the start of the loop, at a position in milliseconds or the current one.
+    /// An end that would come before it is dropped.
+    /// Fails, leaving the loop as it was, if the current position is unknown
+    pub async fn set_loop_start(
+        audio_state: Arc<Mutex<AudioState>>,
+        at_ms: Option<u64>,
+    ) -> color_eyre::Result<()> {
+        let a = AudioState::loop_point(audio_state.clone(), at_ms).await?;
+        let mut state = audio_state.lock().unwrap();
+        state.ab_loop.a = Some(a);
+        state.ab_loop.b = state.ab_loop.b.filter(|&b| b > a);
+        state.send_loop();
+        Ok(())
+    }
+
+    /// Set the end of the loop, at a position in milliseconds or the current one.
+    /// A start that would come after it is dropped.
+    /// Fails, leaving the loop as it was, if the current position is unknown
+    pub async fn set_loop_end(
+        audio_state: Arc<Mutex<AudioState>>,
+        at_ms: Option<u64>,
+    ) -> color_eyre::Result<()> {
+        let b = AudioState::loop_point(audio_state.clone(), at_ms).await?;
+        let mut state = audio_state.lock().unwrap();
+        state.ab_loop.b = Some(b);
+        state.ab_loop.a = state.ab_loop.a.filter(|&a| a < b);
+        state.send_loop();
+        Ok(())
+    }
+
+    async fn loop_point(
+        audio_state: Arc<Mutex<AudioState>>,
+        at_ms: Option<u64>,
+    ) -> color_eyre::Result<u64> {
+        match at_ms {
+            Some(at_ms) => Ok(at_ms),
+            None => match AudioState::get_seek(audio_state).await {
+                Some((_, at_ms)) => Ok(at_ms),
+                None => Err(eyre!(
+                    "the position in the song is unknown, the audio thread didn't answer"
+                )),
+            },
+        }
+    }
+
+    pub fn clear_loop(&mut self) {
+        self.ab_loop = AbLoop::default();
+        self.send_loop();
+    }
+
+    fn send_loop(&self) {
+        audio_thread_send_cmd(AudioCommand::SetLoop(self.ab_loop.active()), &self.cmd_tx);
+    }
+
+    /// Set the sleep timer, replacing the one running if any
+    pub fn set_sleep_timer(audio_state: Arc<Mutex<AudioState>>, timer: SleepTimer) {
+        let mut state = audio_state.lock().unwrap();
+        state.cancel_sleep_timer();
+        state.sleep_timer = Some(match timer {
+            SleepTimer::After(duration) => {
+                let deadline = Instant::now() + duration;
+                let fade_out = duration.min(Duration::from_millis(SLEEP_FADE_OUT_MS));
+                let task = spawn(sleep_then_pause(
+                    audio_state.clone(),
+                    deadline - fade_out,
+                    deadline,
+                ));
+                ArmedSleepTimer::Until(deadline, task.abort_handle())
             }
-            self.play(opt_song);
+            SleepTimer::AfterSongs(songs) => ArmedSleepTimer::Songs(songs.max(1)),
+        });
+        state.send_next();
+    }
+
+    pub fn cancel_sleep_timer(&mut self) {
+        if let Some(ArmedSleepTimer::Until(_, task)) = self.sleep_timer.take() {
+            task.abort();
+            // it may have been fading out already
+            audio_thread_send_cmd(
+                AudioCommand::SetVolume(1.0, SLEEP_FADE_CANCEL_MS),
+                &self.cmd_tx,
+            );
         }
+        self.send_next();
     }
 
-    pub async fn get_seek(audio_state: Arc<Mutex<AudioState>>) -> f32 {
+    /// Ask the audio thread for the current seek, as a fraction of the song and in milliseconds.
+    /// None if it didn't answer in time
+    pub async fn get_seek(audio_state: Arc<Mutex<AudioState>>) -> Option<(f32, u64)> {
         let event_count_before = audio_state.lock().unwrap().events_received;
         audio_thread_send_cmd(AudioCommand::GetSeek, &audio_state.lock().unwrap().cmd_tx);
-        let max_time_wait_for_seek = Duration::from_millis(AUDIO_TASK_POLL_FREQ_MS * 2);
+        let max_time_wait_for_seek = Duration::from_millis(AUDIO_GET_SEEK_TIMEOUT_MS);
         let start = Instant::now();
         while audio_state.lock().unwrap().events_received == event_count_before {
             tokio::time::sleep(Duration::from_millis(AUDIO_GET_SEEK_POLL_FREQ_MS)).await;
 
             if start.elapsed() >= max_time_wait_for_seek {
                 warn!("Timed out waiting for audio thread getting 'seek' !");
-                return 0.0;
+                return None;
             }
         }
         debug!("received one audioevent: assuming it's the SeekIs response to our GetSeek and that current_seek got updated");
-        return audio_state.lock().unwrap().current_seek;
+        let state = audio_state.lock().unwrap();
+        Some((state.current_seek, state.current_seek_ms))
     }
 
-    pub fn set_seek(&mut self, seek: f32) {
-        let was_paused = self.paused;
-        if !was_paused {
-            audio_thread_send_cmd(AudioCommand::Pause, &self.cmd_tx);
+    /// Save where the current song is, if it's resumable, as playback won't be interrupted
+    /// before the server exits. Returns once it's in the database
+    pub async fn save_position(audio_state: Arc<Mutex<AudioState>>) {
+        let playing = {
+            let state = audio_state.lock().unwrap();
+            state.queue.current().cloned().filter(|_| !state.paused)
+        };
+        let Some(song) = playing else { return };
+        // better keep the position saved before than forget it
+        let Some((_, seek_ms)) = AudioState::get_seek(audio_state.clone()).await else {
+            warn!("the position in the current song is unknown, it's not saved");
+            return;
+        };
+        let write = (audio_state.lock().unwrap().resume).leave(&song, seek_ms, None);
+        if let Some(write) = write {
+            write.await;
         }
+    }
+
+    pub fn set_seek(&mut self, seek: f32) {
         audio_thread_send_cmd(AudioCommand::Seek(seek), &self.cmd_tx);
-        if !was_paused {
-            audio_thread_send_cmd(AudioCommand::Play, &self.cmd_tx);
-        }
+    }
+
+    /// Seek to an absolute position in milliseconds
+    pub fn set_seek_ms(&mut self, seek_ms: u64) {
+        audio_thread_send_cmd(AudioCommand::SeekTo(seek_ms), &self.cmd_tx);
+    }
+
+    pub fn set_equalizer(&mut self, equalizer: Equalizer) {
+        self.equalizer = equalizer.clone();
+        audio_thread_send_cmd(AudioCommand::SetEqualizer(equalizer), &self.cmd_tx);
+    }
+
+    /// Change the playback rate, keeping the pitch
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+        audio_thread_send_cmd(AudioCommand::SetSpeed(self.speed), &self.cmd_tx);
+    }
+
+    /// The current song couldn't be played: let clients know, and go on with the queue.
+    /// It is taken out of the queue, not to be tried again by repeat or previous
+    fn failed(&mut self, reason: String) {
+        let _ = self
+            .server_events
+            .send(ServerEvent::PlaybackFailed(self.current_song(), reason));
+        self.queue.drop_current();
+        self.next();
+    }
+
+    /// The audio thread started over from its initial settings, give it the current ones
+    fn audio_thread_restarted(&mut self) {
+        audio_thread_send_cmd(
+            AudioCommand::SetEqualizer(self.equalizer.clone()),
+            &self.cmd_tx,
+        );
+        audio_thread_send_cmd(AudioCommand::SetSpeed(self.speed), &self.cmd_tx);
+        self.send_next();
+    }
+
+    /// Seek forward (or backward, if negative) by some milliseconds
+    pub fn seek_by(&mut self, delta_ms: i64) {
+        audio_thread_send_cmd(AudioCommand::SeekBy(delta_ms), &self.cmd_tx);
     }
 }
 
@@ -137,27 +624,40 @@ pub struct AudioTask {
     pub state: Arc<Mutex<AudioState>>,
 }
 
-const AUDIO_TASK_POLL_FREQ_MS: u64 = 200;
-
 impl AudioTask {
-    pub fn run() -> Self {
-        let cmd_queue = channel(10);
-        let event_queue = channel(10);
+    pub fn run(config: &Config, server_events: Sender<ServerEvent>) -> Self {
+        // every command must reach the audio thread, however many are sent at once
+        let cmd_queue = mpsc::channel();
+        // levels come in a steady flow, the other events must not be lost among them
+        let event_queue = channel(100);
 
         let (cmd_tx, cmd_rx) = cmd_queue;
         let (event_tx, event_rx) = event_queue;
 
+        let capture = Capture::default();
         let state = Arc::new(Mutex::new(AudioState {
-            current_song: None,
             paused: true,
             current_seek: 0.0,
+            current_seek_ms: 0,
+            equalizer: config.startup_equalizer(),
+            speed: 1.0,
+            capture: capture.clone(),
+            server_events,
+            audio_events: event_tx.clone(),
+            resolvers: Resolvers::new(config),
+            resolving: 0,
+            stopped: false,
+            sleep_timer: None,
             events_received: 0,
             cmd_tx,
-            queue_future: VecDeque::new(),
-            queue_past: VecDeque::new(),
+            queue: Queue::default(),
+            resume: ResumePositions::new(config),
+            ab_loop: AbLoop::default(),
+            stream_title: None,
         }));
+        spawn(load_positions(config.clone(), state.clone()));
 
-        let audio_thread = start_audio_thread(cmd_rx, event_tx);
+        let audio_thread = start_audio_thread(cmd_rx, event_tx, config, capture);
         let queue_task_handle = spawn(handle_audio_event(event_rx, state.clone()));
 
         Self {
@@ -183,23 +683,94 @@ impl AudioTask {
     }
 }
 
-async fn handle_audio_event(mut rx: Receiver<AudioEvent>, state: Arc<Mutex<AudioState>>) {
-    let mut interval = time::interval(Duration::from_millis(AUDIO_TASK_POLL_FREQ_MS));
+/// Fade out from the start time, and pause at the deadline
+async fn sleep_then_pause(
+    audio_state: Arc<Mutex<AudioState>>,
+    fade_start: Instant,
+    deadline: Instant,
+) {
+    tokio::time::sleep_until(fade_start).await;
+    let fade_out_ms = deadline
+        .saturating_duration_since(Instant::now())
+        .as_millis() as u64;
+    audio_thread_send_cmd(
+        AudioCommand::SetVolume(0.0, fade_out_ms),
+        &audio_state.lock().unwrap().cmd_tx,
+    );
+
+    tokio::time::sleep_until(deadline).await;
+    debug!("sleep timer elapsed");
+    let mut state = audio_state.lock().unwrap();
+    state.sleep_timer = None;
+    state.pause();
+    // for when playback resumes
+    audio_thread_send_cmd(AudioCommand::SetVolume(1.0, 0), &state.cmd_tx);
+}
 
+async fn handle_audio_event(mut rx: Receiver<AudioEvent>, state: Arc<Mutex<AudioState>>) {
     loop {
-        interval.tick().await;
-        if let Ok(event) = rx.try_recv() {
+        let event = match rx.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(n)) => {
+                warn!("audio event handler lagged, {n} events lost");
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        // levels, titles and resolved songs aren't the answer to anything
+        if !matches!(
+            event,
+            AudioEvent::Levels(_) | AudioEvent::StreamTitle(_) | AudioEvent::Resolved(..)
+        ) {
             state.lock().unwrap().events_received += 1;
-            match event {
-                AudioEvent::Finished => {
-                    debug!("finished song");
-                    state.lock().unwrap().next()
-                }
-                AudioEvent::SeekIs(value) => {
-                    debug!("set audiostate current seek to {value}");
-                    state.lock().unwrap().current_seek = value
-                }
-                _ => debug!("event ??"),
+        }
+        match event {
+            AudioEvent::Finished => {
+                debug!("finished song");
+                state.lock().unwrap().finished()
+            }
+            AudioEvent::NextStarted => {
+                debug!("next song started");
+                state.lock().unwrap().next_started()
+            }
+            AudioEvent::Failed(reason) => {
+                debug!("failed to play song: {reason}");
+                state.lock().unwrap().failed(reason)
+            }
+            AudioEvent::Interrupted(song, seek_ms, duration_ms) => {
+                debug!("song interrupted at {seek_ms} ms");
+                state
+                    .lock()
+                    .unwrap()
+                    .resume
+                    .left_at(&song, seek_ms, duration_ms)
+            }
+            AudioEvent::Restarted => {
+                debug!("audio thread restarted");
+                state.lock().unwrap().audio_thread_restarted()
+            }
+            AudioEvent::Levels(levels) => {
+                let _ = state
+                    .lock()
+                    .unwrap()
+                    .server_events
+                    .send(ServerEvent::Levels(levels));
+            }
+            AudioEvent::StreamTitle(title) => {
+                debug!("the stream now plays {title:?}");
+                let mut state = state.lock().unwrap();
+                state.stream_title = Some(title.clone());
+                let _ = state.server_events.send(ServerEvent::StreamTitle(title));
+            }
+            AudioEvent::Resolved(id, song, resolved) => {
+                debug!("resolved {:?}: {resolved:?}", song.title);
+                state.lock().unwrap().resolved(id, song, resolved)
+            }
+            AudioEvent::SeekIs(value, value_ms) => {
+                debug!("set audiostate current seek to {value} ({value_ms} ms)");
+                let mut state = state.lock().unwrap();
+                state.current_seek = value;
+                state.current_seek_ms = value_ms;
             }
         }
     }
@@ -0,0 +1,71 @@
+//! Record of what the capture output was given, to check the audio path without a sound server
+
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+
+use symphonia::core::audio::SignalSpec;
+
+/// What was written to one opened capture output
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct CapturedOutput {
+    pub rate: u32,
+    pub channels: usize,
+    pub writes: u64,  // number of non-empty buffers
+    pub frames: u64,  // total, over all the buffers
+    pub flushes: u64, // flushes happen at the end of the queue, and before closing
+    /// Interleaved samples, if asked to keep them
+    pub samples: Option<Vec<f32>>,
+}
+
+impl CapturedOutput {
+    /// Duration of the audio written, in milliseconds
+    pub fn duration_ms(&self) -> u64 {
+        if self.rate == 0 {
+            return 0;
+        }
+        self.frames * 1000 / self.rate as u64
+    }
+}
+
+/// Shared between the audio thread, that records, and the server, that reports.
+/// One entry per time the output was opened
+#[derive(Clone, Debug, Default)]
+pub struct Capture(Arc<Mutex<Vec<CapturedOutput>>>);
+
+impl Capture {
+    /// Start recording a newly opened output, returns its index
+    pub fn open(&self, spec: SignalSpec, keep_samples: bool) -> usize {
+        let mut outputs = self.0.lock().unwrap();
+        outputs.push(CapturedOutput {
+            rate: spec.rate,
+            channels: spec.channels.count(),
+            samples: keep_samples.then(Vec::new),
+            ..Default::default()
+        });
+        outputs.len() - 1
+    }
+
+    pub fn record_write(&self, output: usize, frames: usize, samples: &[f32]) {
+        if let Some(captured) = self.0.lock().unwrap().get_mut(output) {
+            captured.writes += 1;
+            captured.frames += frames as u64;
+            if let Some(kept) = &mut captured.samples {
+                kept.extend_from_slice(samples);
+            }
+        }
+    }
+
+    pub fn record_flush(&self, output: usize) {
+        if let Some(captured) = self.0.lock().unwrap().get_mut(output) {
+            captured.flushes += 1;
+        }
+    }
+
+    pub fn report(&self) -> Vec<CapturedOutput> {
+        self.0.lock().unwrap().clone()
+    }
+
+    pub fn clear(&self) {
+        self.0.lock().unwrap().clear();
+    }
+}
@@ -0,0 +1,179 @@
+//! Processing stages between the decoder and the audio output
+
+use symphonia::core::audio::{AsAudioBufferRef, AudioBuffer, AudioBufferRef, Signal};
+
+use super::equalizer::{Equalizer, EqualizerFilter};
+use super::format::{FormatConverter, OutputFormat};
+use super::stretch::TimeStretcher;
+
+pub(super) struct Dsp {
+    equalizer: EqualizerFilter,
+    fader: Fader,
+    stretcher: TimeStretcher,
+    converter: FormatConverter,
+    buffer: Option<AudioBuffer<f32>>, // re-used between packets to avoid allocations
+}
+
+impl Dsp {
+    pub fn new(equalizer: Equalizer, format: OutputFormat) -> Self {
+        Dsp {
+            equalizer: EqualizerFilter::new(equalizer),
+            fader: Fader::new(),
+            stretcher: TimeStretcher::new(),
+            converter: FormatConverter::new(format),
+            buffer: None,
+        }
+    }
+
+    pub fn set_equalizer(&mut self, equalizer: Equalizer) {
+        self.equalizer.set(equalizer);
+    }
+
+    pub fn set_speed(&mut self, speed: f32) {
+        self.stretcher.set_speed(speed);
+    }
+
+    pub fn set_volume(&mut self, volume: f32, ramp_ms: u64) {
+        self.fader.set(volume, ramp_ms);
+    }
+
+    /// Forget the audio buffered by the stages, as after a seek
+    pub fn reset(&mut self) {
+        self.stretcher.reset();
+        self.converter.reset();
+    }
+
+    /// Run the decoded audio through the enabled stages.
+    /// The buffer is passed as-is when no stage would modify it
+    pub fn process<'a>(&'a mut self, decoded: AudioBufferRef<'a>) -> AudioBufferRef<'a> {
+        let Dsp {
+            equalizer,
+            fader,
+            stretcher,
+            converter,
+            buffer,
+        } = self;
+        if equalizer.is_bypassed()
+            && fader.is_bypassed()
+            && stretcher.is_bypassed()
+            && converter.is_bypassed(decoded.spec())
+        {
+            return decoded;
+        }
+
+        let reusable = matches!(buffer, Some(buffer)
+            if buffer.spec() == decoded.spec() && buffer.capacity() >= decoded.capacity());
+        if !reusable {
+            *buffer = Some(decoded.make_equivalent());
+        }
+        let buffer = buffer.as_mut().unwrap();
+        buffer.clear();
+        decoded.convert(buffer);
+
+        if !equalizer.is_bypassed() {
+            equalizer.process(buffer);
+        }
+        if !fader.is_bypassed() {
+            fader.process(buffer);
+        }
+        let stretched = if !stretcher.is_bypassed() {
+            stretcher.process(buffer)
+        } else {
+            buffer
+        };
+        if !converter.is_bypassed(stretched.spec()) {
+            return converter.process(stretched).as_audio_buffer_ref();
+        }
+
+        stretched.as_audio_buffer_ref()
+    }
+}
+
+/// Volume, changed along a linear ramp rather than at once, which would click
+struct Fader {
+    volume: f32,
+    target: f32,
+    ramp_ms: Option<u64>, // of the last change, until the step is computed for the sample rate
+    step: f32,            // change of the volume per frame
+}
+
+impl Fader {
+    fn new() -> Self {
+        Fader {
+            volume: 1.0,
+            target: 1.0,
+            ramp_ms: None,
+            step: 0.0,
+        }
+    }
+
+    fn set(&mut self, volume: f32, ramp_ms: u64) {
+        self.target = volume.clamp(0.0, 1.0);
+        self.ramp_ms = Some(ramp_ms);
+    }
+
+    fn is_bypassed(&self) -> bool {
+        self.volume == 1.0 && self.target == 1.0
+    }
+
+    fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
+        if let Some(ramp_ms) = self.ramp_ms.take() {
+            let ramp_frames = (ramp_ms * buffer.spec().rate as u64 / 1000).max(1);
+            self.step = (self.target - self.volume) / ramp_frames as f32;
+        }
+
+        let start = self.volume;
+        for c in 0..buffer.spec().channels.count() {
+            let mut volume = start;
+            for sample in buffer.chan_mut(c) {
+                if volume != self.target {
+                    volume += self.step;
+                    // don't overshoot the target
+                    if (self.step > 0.0) == (volume > self.target) {
+                        volume = self.target;
+                    }
+                }
+                *sample *= volume;
+            }
+            self.volume = volume;
+        }
+    }
+}
+
+/// Signals to test the stages with
+#[cfg(test)]
+pub(super) mod signal {
+    use symphonia::core::audio::{AudioBuffer, Channels, Signal, SignalSpec};
+
+    /// A sine at that frequency and amplitude
+    pub fn sine(rate: u32, frequency: f32, amplitude: f32, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .map(|i| {
+                let phase = std::f32::consts::TAU * frequency * i as f32 / rate as f32;
+                amplitude * phase.sin()
+            })
+            .collect()
+    }
+
+    /// A mono or stereo buffer of these samples, per channel
+    pub fn buffer(rate: u32, channels: &[Vec<f32>]) -> AudioBuffer<f32> {
+        let layout = match channels.len() {
+            1 => Channels::FRONT_LEFT,
+            _ => Channels::FRONT_LEFT | Channels::FRONT_RIGHT,
+        };
+        let frames = channels[0].len();
+        let mut buffer = AudioBuffer::new(frames as u64, SignalSpec::new(rate, layout));
+        buffer.render_reserved(Some(frames));
+        for (c, samples) in channels.iter().enumerate() {
+            buffer.chan_mut(c).copy_from_slice(samples);
+        }
+        buffer
+    }
+
+    /// The highest amplitude
+    pub fn peak(samples: &[f32]) -> f32 {
+        samples
+            .iter()
+            .fold(0.0, |peak, sample| sample.abs().max(peak))
+    }
+}
@@ -0,0 +1,299 @@
+//! Biquad-based parametric equalizer
+//!
+//! Filter coefficients follow the "Audio EQ Cookbook" by Robert Bristow-Johnson
+
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+
+use symphonia::core::audio::{AudioBuffer, Signal};
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum FilterKind {
+    Peak,
+    LowShelf,
+    HighShelf,
+    LowPass,
+    HighPass,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct EqualizerBand {
+    pub kind: FilterKind,
+    /// Center (peak) or corner (shelves, passes) frequency in Hz
+    pub frequency: f32,
+    /// Gain in dB, unused by low and high pass filters
+    pub gain_db: f32,
+    /// Quality factor: the higher, the narrower the band
+    pub q: f32,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct Equalizer {
+    /// Gain applied before the bands, to leave headroom for boosts
+    pub preamp_db: f32,
+    pub bands: Vec<EqualizerBand>,
+}
+
+/// Center frequencies of the graphic equalizer, one octave apart
+pub const GRAPHIC_FREQUENCIES: [f32; 10] = [
+    31.0, 62.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0,
+];
+
+// Q of a one-octave wide peak filter
+const GRAPHIC_Q: f32 = 1.41;
+
+impl Equalizer {
+    /// A flat 10-band graphic equalizer
+    pub fn graphic() -> Self {
+        Equalizer {
+            preamp_db: 0.0,
+            bands: GRAPHIC_FREQUENCIES
+                .iter()
+                .map(|&frequency| EqualizerBand {
+                    kind: FilterKind::Peak,
+                    frequency,
+                    gain_db: 0.0,
+                    q: GRAPHIC_Q,
+                })
+                .collect(),
+        }
+    }
+
+    /// Whether the equalizer leaves the signal untouched
+    pub fn is_flat(&self) -> bool {
+        self.preamp_db == 0.0
+            && self.bands.iter().all(|b| match b.kind {
+                FilterKind::Peak | FilterKind::LowShelf | FilterKind::HighShelf => b.gain_db == 0.0,
+                FilterKind::LowPass | FilterKind::HighPass => false,
+            })
+    }
+}
+
+/// Normalized biquad coefficients (a0 = 1)
+#[derive(Clone, Copy, Debug, Default)]
+struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+}
+
+impl Biquad {
+    fn new(band: &EqualizerBand, rate: u32) -> Self {
+        let rate = rate as f64;
+        // keep the frequency below Nyquist and the Q positive, whatever the config says
+        let frequency = (band.frequency as f64).clamp(1.0, rate * 0.49);
+        let q = (band.q as f64).max(0.01);
+
+        let a = 10f64.powf(band.gain_db as f64 / 40.0);
+        let w0 = 2.0 * PI * frequency / rate;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * q);
+        let sqrt_a_alpha = 2.0 * a.sqrt() * alpha;
+
+        let (b0, b1, b2, a0, a1, a2) = match band.kind {
+            FilterKind::Peak => (
+                1.0 + alpha * a,
+                -2.0 * cos,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos,
+                1.0 - alpha / a,
+            ),
+            FilterKind::LowShelf => (
+                a * ((a + 1.0) - (a - 1.0) * cos + sqrt_a_alpha),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - sqrt_a_alpha),
+                (a + 1.0) + (a - 1.0) * cos + sqrt_a_alpha,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - sqrt_a_alpha,
+            ),
+            FilterKind::HighShelf => (
+                a * ((a + 1.0) + (a - 1.0) * cos + sqrt_a_alpha),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - sqrt_a_alpha),
+                (a + 1.0) - (a - 1.0) * cos + sqrt_a_alpha,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - sqrt_a_alpha,
+            ),
+            FilterKind::LowPass => (
+                (1.0 - cos) / 2.0,
+                1.0 - cos,
+                (1.0 - cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+            FilterKind::HighPass => (
+                (1.0 + cos) / 2.0,
+                -(1.0 + cos),
+                (1.0 + cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+        };
+
+        Biquad {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+
+    /// Filter one sample (transposed direct form II)
+    #[inline]
+    fn process(&self, x: f64, state: &mut [f64; 2]) -> f64 {
+        let y = self.b0 * x + state[0];
+        state[0] = self.b1 * x - self.a1 * y + state[1];
+        state[1] = self.b2 * x - self.a2 * y;
+        y
+    }
+}
+
+/// Applies an `Equalizer` to decoded audio, keeping the filters' state between buffers
+pub(super) struct EqualizerFilter {
+    equalizer: Equalizer,
+    rate: u32,
+    preamp: f64,
+    biquads: Vec<Biquad>,
+    states: Vec<Vec<[f64; 2]>>, // per channel, per band
+}
+
+impl EqualizerFilter {
+    pub fn new(equalizer: Equalizer) -> Self {
+        let mut filter = EqualizerFilter {
+            equalizer,
+            rate: 0,
+            preamp: 1.0,
+            biquads: vec![],
+            states: vec![],
+        };
+        filter.configure(44100, 2);
+        filter
+    }
+
+    pub fn set(&mut self, equalizer: Equalizer) {
+        self.equalizer = equalizer;
+        let channels = self.states.len();
+        self.configure(self.rate, channels);
+    }
+
+    pub fn is_bypassed(&self) -> bool {
+        self.equalizer.is_flat()
+    }
+
+    fn configure(&mut self, rate: u32, channels: usize) {
+        self.rate = rate;
+        self.preamp = 10f64.powf(self.equalizer.preamp_db as f64 / 20.0);
+        self.biquads = self
+            .equalizer
+            .bands
+            .iter()
+            .map(|band| Biquad::new(band, rate))
+            .collect();
+        self.states = vec![vec![[0.0; 2]; self.biquads.len()]; channels];
+    }
+
+    pub fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
+        let rate = buffer.spec().rate;
+        let channels = buffer.spec().channels.count();
+        if rate != self.rate || channels != self.states.len() {
+            self.configure(rate, channels);
+        }
+
+        for (c, states) in self.states.iter_mut().enumerate() {
+            for sample in buffer.chan_mut(c) {
+                let mut x = *sample as f64 * self.preamp;
+                for (biquad, state) in self.biquads.iter().zip(states.iter_mut()) {
+                    x = biquad.process(x, state);
+                }
+                *sample = x.clamp(-1.0, 1.0) as f32;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::dsp::signal::{buffer, peak, sine};
+
+    const RATE: u32 = 44100;
+
+    /// The amplitude of a sine through the equalizer, once the filters settled
+    fn through(equalizer: Equalizer, frequency: f32) -> f32 {
+        let input = sine(RATE, frequency, 0.5, RATE as usize / 2);
+        let mut buffer = buffer(RATE, &[input.clone(), input]);
+        EqualizerFilter::new(equalizer).process(&mut buffer);
+        peak(&buffer.chan(0)[RATE as usize / 4..]) / 0.5
+    }
+
+    fn band(kind: FilterKind, frequency: f32, gain_db: f32) -> EqualizerBand {
+        EqualizerBand {
+            kind,
+            frequency,
+            gain_db,
+            q: GRAPHIC_Q,
+        }
+    }
+
+    #[test]
+    fn flat_equalizer_changes_nothing() {
+        let flat = Equalizer::graphic();
+        assert!(flat.is_flat());
+        assert!(EqualizerFilter::new(flat.clone()).is_bypassed());
+
+        let input = [sine(RATE, 440.0, 0.8, 4410), sine(RATE, 6000.0, 0.3, 4410)];
+        let mut buffer = buffer(RATE, &input);
+        EqualizerFilter::new(flat).process(&mut buffer);
+        for (c, input) in input.iter().enumerate() {
+            for (output, input) in buffer.chan(c).iter().zip(input) {
+                assert!((output - input).abs() < 1e-5, "{output} instead of {input}");
+            }
+        }
+    }
+
+    #[test]
+    fn peak_band() {
+        // -6 dB, halving the amplitude at its center
+        let cut = Equalizer {
+            preamp_db: 0.0,
+            bands: vec![band(FilterKind::Peak, 1000.0, -6.02)],
+        };
+        assert!(!cut.is_flat());
+        assert!((through(cut.clone(), 1000.0) - 0.5).abs() < 0.01);
+        // far from it, the signal is left alone
+        assert!((through(cut.clone(), 60.0) - 1.0).abs() < 0.02);
+        assert!((through(cut, 16000.0) - 1.0).abs() < 0.02);
+    }
+
+    #[test]
+    fn preamp() {
+        let quieter = Equalizer {
+            preamp_db: -6.02,
+            ..Equalizer::graphic()
+        };
+        assert!((through(quieter, 440.0) - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn passes() {
+        let low_pass = Equalizer {
+            preamp_db: 0.0,
+            bands: vec![band(FilterKind::LowPass, 1000.0, 0.0)],
+        };
+        assert!((through(low_pass.clone(), 100.0) - 1.0).abs() < 0.02);
+        assert!(through(low_pass, 8000.0) < 0.05);
+
+        let high_pass = Equalizer {
+            preamp_db: 0.0,
+            bands: vec![band(FilterKind::HighPass, 1000.0, 0.0)],
+        };
+        assert!((through(high_pass.clone(), 8000.0) - 1.0).abs() < 0.02);
+        assert!(through(high_pass, 100.0) < 0.05);
+    }
+}
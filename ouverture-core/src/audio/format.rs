@@ -0,0 +1,379 @@
+//! Conversion to a fixed output format: channel mixing and resampling
+//!
+//! With a fixed format, the audio output is opened once and the sound device doesn't have
+//! to renegotiate between, say, a 44.1 kHz MP3 and a 96 kHz FLAC.
+
+use rubato::{FftFixedIn, Resampler};
+use serde::{Deserialize, Serialize};
+
+use symphonia::core::audio::{AudioBuffer, Channels, Signal, SignalSpec};
+
+use log::{info, warn};
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct OutputFormat {
+    /// Sample rate in Hz, the song's own if None
+    pub rate: Option<u32>,
+    /// 1 (mono downmix) or 2 (stereo), the song's own if None
+    pub channels: Option<usize>,
+}
+
+// input frames given to the resampler at once
+const RESAMPLER_CHUNK: usize = 1024;
+
+pub(super) struct FormatConverter {
+    format: OutputFormat,
+    spec: Option<SignalSpec>, // of the input the stages are configured for
+
+    mix: Option<Vec<Vec<f32>>>, // gains, per output channel then per input channel
+    mixed: Option<AudioBuffer<f32>>,
+
+    resampler: Option<FftFixedIn<f32>>,
+    pending: Vec<Vec<f32>>, // per channel, input waiting for a full resampler chunk
+    resampled: Vec<Vec<f32>>, // per channel, output of one resampler chunk
+    collected: Vec<Vec<f32>>, // per channel, output of all the chunks of a call
+    output: Option<AudioBuffer<f32>>,
+}
+
+impl FormatConverter {
+    pub fn new(format: OutputFormat) -> Self {
+        FormatConverter {
+            format,
+            spec: None,
+            mix: None,
+            mixed: None,
+            resampler: None,
+            pending: vec![],
+            resampled: vec![],
+            collected: vec![],
+            output: None,
+        }
+    }
+
+    /// Whether audio of this spec is already in the output format
+    pub fn is_bypassed(&self, spec: &SignalSpec) -> bool {
+        self.format.rate.is_none_or(|rate| rate == spec.rate)
+            && self
+                .format
+                .channels
+                .is_none_or(|channels| channels == spec.channels.count())
+    }
+
+    /// Forget the buffered input, as after a seek
+    pub fn reset(&mut self) {
+        if let Some(spec) = self.spec {
+            self.configure(spec);
+        }
+    }
+
+    fn configure(&mut self, spec: SignalSpec) {
+        self.spec = Some(spec);
+        let channels = spec.channels.count();
+
+        self.mix = match self.format.channels {
+            Some(to) if to != channels => Some(mix_matrix(spec.channels, to)),
+            _ => None,
+        };
+        let mixed_channels = self.mix.as_ref().map_or(channels, |mix| mix.len());
+
+        self.resampler = match self.format.rate {
+            Some(rate) if rate != spec.rate => {
+                match FftFixedIn::new(
+                    spec.rate as usize,
+                    rate as usize,
+                    RESAMPLER_CHUNK,
+                    2,
+                    mixed_channels,
+                ) {
+                    Ok(resampler) => {
+                        info!("resampling {} Hz to {} Hz", spec.rate, rate);
+                        Some(resampler)
+                    }
+                    Err(e) => {
+                        warn!("can't resample {} Hz to {rate} Hz: {e}", spec.rate);
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+        self.pending = vec![vec![]; mixed_channels];
+        self.resampled = vec![vec![]; mixed_channels];
+        self.collected = vec![vec![]; mixed_channels];
+    }
+
+    /// Convert the buffer to the output format. When resampling, the output
+    /// lags behind the input by a chunk, and may be empty
+    pub fn process<'a>(&'a mut self, buffer: &'a AudioBuffer<f32>) -> &'a AudioBuffer<f32> {
+        if self.spec != Some(*buffer.spec()) {
+            self.configure(*buffer.spec());
+        }
+        let FormatConverter {
+            format,
+            mix,
+            mixed,
+            resampler,
+            pending,
+            resampled,
+            collected,
+            output,
+            ..
+        } = self;
+
+        let buffer = match mix {
+            Some(mix) => {
+                let spec = SignalSpec::new(buffer.spec().rate, output_channels(mix.len()));
+                let reusable = matches!(mixed, Some(mixed)
+                    if *mixed.spec() == spec && mixed.capacity() >= buffer.frames());
+                if !reusable {
+                    *mixed = Some(AudioBuffer::new(buffer.capacity() as u64, spec));
+                }
+                let mixed = mixed.as_mut().unwrap();
+                mixed.clear();
+                mixed.render_reserved(Some(buffer.frames()));
+                for (o, gains) in mix.iter().enumerate() {
+                    let out = mixed.chan_mut(o);
+                    out.fill(0.0);
+                    for (i, gain) in gains.iter().enumerate() {
+                        if *gain != 0.0 {
+                            for (y, x) in out.iter_mut().zip(buffer.chan(i)) {
+                                *y += gain * x;
+                            }
+                        }
+                    }
+                }
+                &*mixed
+            }
+            None => buffer,
+        };
+
+        let resampler = match resampler {
+            Some(resampler) => resampler,
+            None => return buffer,
+        };
+
+        for (c, pending) in pending.iter_mut().enumerate() {
+            pending.extend_from_slice(buffer.chan(c));
+        }
+        for collected in collected.iter_mut() {
+            collected.clear();
+        }
+        while pending[0].len() >= resampler.input_frames_next() {
+            let needed = resampler.input_frames_next();
+            let input: Vec<&[f32]> = pending.iter().map(|p| &p[..needed]).collect();
+            if let Err(e) = resampler.process_into_buffer(&input, resampled, None) {
+                warn!("failed to resample: {e}");
+            }
+            for p in pending.iter_mut() {
+                p.drain(..needed);
+            }
+            for (collected, resampled) in collected.iter_mut().zip(resampled.iter()) {
+                collected.extend_from_slice(resampled);
+            }
+        }
+
+        // copy the resampled samples in an audio buffer
+        let len = collected[0].len();
+        let spec = SignalSpec::new(format.rate.unwrap_or(0), buffer.spec().channels);
+        let reusable = matches!(output, Some(output)
+            if *output.spec() == spec && output.capacity() >= len);
+        if !reusable {
+            let capacity = std::cmp::max(len, resampler.output_frames_max() * 2);
+            *output = Some(AudioBuffer::new(capacity as u64, spec));
+        }
+        let output = output.as_mut().unwrap();
+        output.clear();
+        output.render_reserved(Some(len));
+        for (c, collected) in collected.iter().enumerate() {
+            output.chan_mut(c).copy_from_slice(collected);
+        }
+        output
+    }
+}
+
+/// Channel layout of the output, following symphonia's convention for mono
+fn output_channels(count: usize) -> Channels {
+    match count {
+        1 => Channels::FRONT_LEFT,
+        _ => Channels::FRONT_LEFT | Channels::FRONT_RIGHT,
+    }
+}
+
+/// Gains to mix the input channels into a mono or stereo output.
+/// Left channels go to the left, right ones to the right, centre ones to both,
+/// and the low frequency effects are dropped
+fn mix_matrix(from: Channels, to: usize) -> Vec<Vec<f32>> {
+    let to = to.clamp(1, 2);
+    let inputs: Vec<Channels> = from.iter().collect();
+
+    let mut mix: Vec<Vec<f32>> = (0..to)
+        .map(|o| {
+            inputs
+                .iter()
+                .map(|&channel| {
+                    if inputs.len() == 1 {
+                        return 1.0; // a mono source plays as-is on every output channel
+                    }
+                    let (left, right) = match channel {
+                        Channels::LFE1 | Channels::LFE2 => (0.0, 0.0),
+                        c if is_left(c) => (1.0, 0.0),
+                        c if is_right(c) => (0.0, 1.0),
+                        _ => (
+                            std::f32::consts::FRAC_1_SQRT_2,
+                            std::f32::consts::FRAC_1_SQRT_2,
+                        ),
+                    };
+                    match (to, o) {
+                        (1, _) => f32::max(left, right),
+                        (_, 0) => left,
+                        _ => right,
+                    }
+                })
+                .collect()
+        })
+        .collect();
+
+    // keep the sum of the gains at most 1 on each output, so that mixing can't clip
+    for gains in mix.iter_mut() {
+        let total: f32 = gains.iter().sum();
+        if total > 1.0 {
+            gains.iter_mut().for_each(|g| *g /= total);
+        }
+    }
+    mix
+}
+
+fn is_left(channel: Channels) -> bool {
+    (Channels::FRONT_LEFT
+        | Channels::REAR_LEFT
+        | Channels::FRONT_LEFT_CENTRE
+        | Channels::SIDE_LEFT
+        | Channels::TOP_FRONT_LEFT
+        | Channels::TOP_REAR_LEFT
+        | Channels::REAR_LEFT_CENTRE
+        | Channels::FRONT_LEFT_WIDE
+        | Channels::FRONT_LEFT_HIGH)
+        .contains(channel)
+}
+
+fn is_right(channel: Channels) -> bool {
+    (Channels::FRONT_RIGHT
+        | Channels::REAR_RIGHT
+        | Channels::FRONT_RIGHT_CENTRE
+        | Channels::SIDE_RIGHT
+        | Channels::TOP_FRONT_RIGHT
+        | Channels::TOP_REAR_RIGHT
+        | Channels::REAR_RIGHT_CENTRE
+        | Channels::FRONT_RIGHT_WIDE
+        | Channels::FRONT_RIGHT_HIGH)
+        .contains(channel)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::dsp::signal::{buffer, sine};
+
+    fn format(rate: Option<u32>, channels: Option<usize>) -> OutputFormat {
+        OutputFormat { rate, channels }
+    }
+
+    #[test]
+    fn bypassed_when_already_in_format() {
+        let stereo = SignalSpec::new(44100, output_channels(2));
+        assert!(FormatConverter::new(OutputFormat::default()).is_bypassed(&stereo));
+        assert!(FormatConverter::new(format(Some(44100), Some(2))).is_bypassed(&stereo));
+        assert!(!FormatConverter::new(format(Some(48000), None)).is_bypassed(&stereo));
+        assert!(!FormatConverter::new(format(None, Some(1))).is_bypassed(&stereo));
+    }
+
+    #[test]
+    fn mono_downmix() {
+        let left = sine(44100, 440.0, 0.8, 1000);
+        let right = sine(44100, 660.0, 0.4, 1000);
+        let input = buffer(44100, &[left.clone(), right.clone()]);
+        let mut converter = FormatConverter::new(format(None, Some(1)));
+        let output = converter.process(&input);
+        assert_eq!(output.spec().channels, Channels::FRONT_LEFT);
+        assert_eq!(output.spec().rate, 44100);
+        assert_eq!(output.frames(), 1000);
+        for (i, mono) in output.chan(0).iter().enumerate() {
+            let expected = (left[i] + right[i]) / 2.0;
+            assert!(
+                (mono - expected).abs() < 1e-6,
+                "{mono} instead of {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn mono_to_stereo() {
+        let input = buffer(44100, &[sine(44100, 440.0, 0.8, 1000)]);
+        let mut converter = FormatConverter::new(format(None, Some(2)));
+        let output = converter.process(&input);
+        assert_eq!(output.spec().channels.count(), 2);
+        assert_eq!(output.chan(0), input.chan(0));
+        assert_eq!(output.chan(1), input.chan(0));
+    }
+
+    #[test]
+    fn surround_mix() {
+        let surround = Channels::FRONT_LEFT
+            | Channels::FRONT_RIGHT
+            | Channels::FRONT_CENTRE
+            | Channels::LFE1
+            | Channels::REAR_LEFT
+            | Channels::REAR_RIGHT;
+        let centre = std::f32::consts::FRAC_1_SQRT_2;
+        let total = 2.0 + centre;
+        let mix = mix_matrix(surround, 2);
+        let expected = [
+            [1.0 / total, 0.0, centre / total, 0.0, 1.0 / total, 0.0],
+            [0.0, 1.0 / total, centre / total, 0.0, 0.0, 1.0 / total],
+        ];
+        for (gains, expected) in mix.iter().zip(expected) {
+            for (gain, expected) in gains.iter().zip(expected) {
+                assert!((gain - expected).abs() < 1e-6, "{mix:?}");
+            }
+        }
+        // nothing can clip
+        assert!(mix
+            .iter()
+            .all(|gains| gains.iter().sum::<f32>() <= 1.0 + 1e-6));
+    }
+
+    #[test]
+    fn resampling() {
+        let (from, to) = (44100, 48000);
+        let input = sine(from, 1000.0, 0.5, from as usize);
+        let mut converter = FormatConverter::new(format(Some(to), None));
+        let mut output = vec![];
+        for chunk in input.chunks(1000) {
+            let chunk = buffer(from, &[chunk.to_vec(), chunk.to_vec()]);
+            let resampled = converter.process(&chunk);
+            assert_eq!(resampled.spec().rate, to);
+            assert_eq!(resampled.chan(0), resampled.chan(1));
+            output.extend_from_slice(resampled.chan(0));
+        }
+        // all the input was resampled, but for less than a chunk, and the resampler may
+        // hold back up to an output chunk
+        let resampler = converter.resampler.as_ref().unwrap();
+        let pending = converter.pending[0].len();
+        assert!(pending < resampler.input_frames_next());
+        let expected = (input.len() - pending) * to as usize / from as usize;
+        assert!(output.len() <= expected);
+        assert!(
+            expected - output.len() <= resampler.output_frames_max(),
+            "{} frames instead of about {expected}",
+            output.len()
+        );
+
+        // at the same pitch, once the resampler's delay is past
+        let steady = &output[to as usize / 10..];
+        let crossings = (steady.windows(2))
+            .filter(|pair| (pair[0] < 0.0) != (pair[1] < 0.0))
+            .count();
+        let frequency = crossings as f32 / 2.0 / (steady.len() as f32 / to as f32);
+        assert!((frequency - 1000.0).abs() < 10.0, "{frequency} Hz");
+    }
+}
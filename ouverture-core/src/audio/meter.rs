@@ -0,0 +1,243 @@
+//! Level metering and spectrum of the audio being played, for meters and visualizers
+//!
+//! Peak and RMS levels are taken over all the audio since the last report. The spectrum
+//! is the FFT of the last `FFT_SIZE` frames (mixed down to mono, Hann-windowed), reduced to
+//! `SPECTRUM_BANDS` log-spaced bands, which keeps the events small.
+
+use std::sync::Arc;
+
+use realfft::num_complex::Complex;
+use realfft::{RealFftPlanner, RealToComplex};
+use serde::{Deserialize, Serialize};
+use symphonia::core::audio::{AudioBuffer, AudioBufferRef, Signal};
+
+pub const SPECTRUM_BANDS: usize = 32;
+pub const SPECTRUM_MIN_HZ: f32 = 20.0;
+pub const SPECTRUM_MAX_HZ: f32 = 20_000.0;
+// below this, levels are reported as silence
+pub const SILENCE_DB: f32 = -120.0;
+
+const FFT_SIZE: usize = 2048;
+
+/// Levels of the audio being played, in dBFS
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Levels {
+    pub peak: Vec<f32>, // per channel
+    pub rms: Vec<f32>,  // per channel
+    /// Per band of log-spaced frequencies from `SPECTRUM_MIN_HZ` up to `SPECTRUM_MAX_HZ`
+    /// or the highest frequency of the audio, if lower
+    pub spectrum: Vec<f32>,
+}
+
+pub(super) struct Meter {
+    rate: u32, // reports per second
+    peak: Vec<f32>,
+    sum_squares: Vec<f64>,
+    frames: usize, // since the last report
+
+    history: Vec<f32>, // the last FFT_SIZE frames mixed down to mono, circular
+    next: usize,       // where the next frame goes in history
+    window: Vec<f32>,
+    fft: Arc<dyn RealToComplex<f32>>,
+    fft_input: Vec<f32>,
+    fft_output: Vec<Complex<f32>>,
+
+    converted: Option<AudioBuffer<f32>>, // re-used between packets to avoid allocations
+    levels: Option<Levels>,              // report not taken yet
+}
+
+impl Meter {
+    pub fn new(rate: u32) -> Self {
+        let fft = RealFftPlanner::<f32>::new().plan_fft_forward(FFT_SIZE);
+        let window = (0..FFT_SIZE)
+            .map(|i| {
+                let phase = std::f32::consts::TAU * i as f32 / FFT_SIZE as f32;
+                0.5 - 0.5 * phase.cos()
+            })
+            .collect();
+        Meter {
+            rate: rate.max(1),
+            peak: vec![],
+            sum_squares: vec![],
+            frames: 0,
+            history: vec![0.0; FFT_SIZE],
+            next: 0,
+            window,
+            fft_input: fft.make_input_vec(),
+            fft_output: fft.make_output_vec(),
+            fft,
+            converted: None,
+            levels: None,
+        }
+    }
+
+    /// Account for audio about to be played, a report is ready every 1/rate second
+    pub fn process(&mut self, buffer: &AudioBufferRef<'_>) {
+        let spec = *buffer.spec();
+        let channels = spec.channels.count();
+        let reusable = matches!(&self.converted, Some(converted)
+            if *converted.spec() == spec && converted.capacity() >= buffer.capacity());
+        if !reusable {
+            self.converted = Some(buffer.make_equivalent());
+        }
+        let converted = self.converted.as_mut().unwrap();
+        converted.clear();
+        buffer.convert(converted);
+
+        if self.peak.len() != channels {
+            self.peak = vec![0.0; channels];
+            self.sum_squares = vec![0.0; channels];
+        }
+        for c in 0..channels {
+            for &sample in converted.chan(c) {
+                self.peak[c] = self.peak[c].max(sample.abs());
+                self.sum_squares[c] += (sample * sample) as f64;
+            }
+        }
+        for i in 0..converted.frames() {
+            let mono = (0..channels).map(|c| converted.chan(c)[i]).sum::<f32>() / channels as f32;
+            self.history[self.next] = mono;
+            self.next = (self.next + 1) % FFT_SIZE;
+        }
+
+        self.frames += converted.frames();
+        if self.frames >= (spec.rate / self.rate) as usize {
+            self.levels = Some(self.report(spec.rate));
+        }
+    }
+
+    /// The last report, if not taken already
+    pub fn take_levels(&mut self) -> Option<Levels> {
+        self.levels.take()
+    }
+
+    fn report(&mut self, sample_rate: u32) -> Levels {
+        let frames = self.frames.max(1) as f64;
+        let levels = Levels {
+            peak: self.peak.iter().map(|&peak| to_db(peak)).collect(),
+            rms: (self.sum_squares.iter())
+                .map(|&sum| to_db((sum / frames).sqrt() as f32))
+                .collect(),
+            spectrum: self.spectrum(sample_rate),
+        };
+        self.peak.iter_mut().for_each(|peak| *peak = 0.0);
+        self.sum_squares.iter_mut().for_each(|sum| *sum = 0.0);
+        self.frames = 0;
+        levels
+    }
+
+    fn spectrum(&mut self, sample_rate: u32) -> Vec<f32> {
+        // oldest frame first
+        for i in 0..FFT_SIZE {
+            self.fft_input[i] = self.history[(self.next + i) % FFT_SIZE] * self.window[i];
+        }
+        if self
+            .fft
+            .process(&mut self.fft_input, &mut self.fft_output)
+            .is_err()
+        {
+            return vec![SILENCE_DB; SPECTRUM_BANDS];
+        }
+
+        // a full-scale sine reads 0 dB
+        let scale = 2.0 / self.window.iter().sum::<f32>();
+        let bin_hz = sample_rate as f32 / FFT_SIZE as f32;
+        let bins = self.fft_output.len();
+        let max_hz = SPECTRUM_MAX_HZ.min(sample_rate as f32 / 2.0);
+        let ratio = (max_hz / SPECTRUM_MIN_HZ).powf(1.0 / SPECTRUM_BANDS as f32);
+        (0..SPECTRUM_BANDS)
+            .map(|band| {
+                let low = SPECTRUM_MIN_HZ * ratio.powi(band as i32);
+                let first = ((low / bin_hz).round() as usize).min(bins - 1);
+                // bands narrower than a bin still get one
+                let last = (((low * ratio) / bin_hz).round() as usize).clamp(first + 1, bins);
+                let magnitude = self.fft_output[first..last]
+                    .iter()
+                    .map(|bin| bin.norm())
+                    .fold(0.0, f32::max);
+                to_db(magnitude * scale)
+            })
+            .collect()
+    }
+}
+
+fn to_db(amplitude: f32) -> f32 {
+    if amplitude > 0.0 {
+        (20.0 * amplitude.log10()).max(SILENCE_DB)
+    } else {
+        SILENCE_DB
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::dsp::signal::{buffer, sine};
+    use symphonia::core::audio::AsAudioBufferRef;
+
+    const RATE: u32 = 44100;
+    const REPORTS: u32 = 10; // per second
+
+    fn measure(meter: &mut Meter, channels: &[Vec<f32>]) {
+        meter.process(&buffer(RATE, channels).as_audio_buffer_ref());
+    }
+
+    /// The band holding a frequency
+    fn band(frequency: f32) -> usize {
+        let ratio = (SPECTRUM_MAX_HZ / SPECTRUM_MIN_HZ).powf(1.0 / SPECTRUM_BANDS as f32);
+        ((frequency / SPECTRUM_MIN_HZ).ln() / ratio.ln()) as usize
+    }
+
+    #[test]
+    fn full_scale_sine() {
+        let mut meter = Meter::new(REPORTS);
+        // a report's worth, 100 periods
+        let frames = (RATE / REPORTS) as usize;
+        let full = sine(RATE, 1000.0, 1.0, frames);
+        let half = sine(RATE, 1000.0, 0.5, frames);
+        measure(&mut meter, &[full, half]);
+        let levels = meter.take_levels().unwrap();
+
+        assert!(levels.peak[0].abs() < 0.01, "{:?}", levels.peak);
+        assert!((levels.rms[0] + 3.01).abs() < 0.05, "{:?}", levels.rms);
+        assert!((levels.peak[1] + 6.02).abs() < 0.01, "{:?}", levels.peak);
+        assert!((levels.rms[1] + 9.03).abs() < 0.05, "{:?}", levels.rms);
+
+        // mixed down to mono, at three quarters of full scale
+        assert_eq!(levels.spectrum.len(), SPECTRUM_BANDS);
+        let at_1k = levels.spectrum[band(1000.0)];
+        assert!((at_1k + 2.5).abs() < 1.5, "{at_1k} dB");
+        for far in [50.0, 10_000.0] {
+            assert!(levels.spectrum[band(far)] < -40.0, "{:?}", levels.spectrum);
+        }
+    }
+
+    #[test]
+    fn silence() {
+        let mut meter = Meter::new(REPORTS);
+        let frames = (RATE / REPORTS) as usize;
+        measure(&mut meter, &[vec![0.0; frames]]);
+        let levels = meter.take_levels().unwrap();
+        assert_eq!(levels.peak, [SILENCE_DB]);
+        assert_eq!(levels.rms, [SILENCE_DB]);
+        assert!(levels.spectrum.iter().all(|&band| band == SILENCE_DB));
+    }
+
+    #[test]
+    fn reports_every_period() {
+        let mut meter = Meter::new(REPORTS);
+        let frames = (RATE / REPORTS) as usize;
+        let loud = [sine(RATE, 440.0, 1.0, frames / 2)];
+        measure(&mut meter, &loud);
+        assert!(meter.take_levels().is_none());
+        measure(&mut meter, &loud);
+        assert!(meter.take_levels().is_some());
+        // taken once
+        assert!(meter.take_levels().is_none());
+
+        // the levels start over from each report
+        measure(&mut meter, &[sine(RATE, 440.0, 0.1, frames)]);
+        let levels = meter.take_levels().unwrap();
+        assert!((levels.peak[0] + 20.0).abs() < 0.01, "{:?}", levels.peak);
+    }
+}
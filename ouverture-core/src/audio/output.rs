@@ -5,13 +5,18 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-//! Platform-dependant Audio Outputs
+//! Audio Outputs, each behind its own cargo feature
 
 use std::result;
 
 use symphonia::core::audio::{AudioBufferRef, SignalSpec};
 use symphonia::core::units::Duration;
 
+use log::error;
+
+use super::Capture;
+use crate::config::AudioBackend;
+
 pub trait AudioOutput {
     fn write(&mut self, decoded: AudioBufferRef<'_>) -> Result<()>;
     fn flush(&mut self);
@@ -24,11 +29,12 @@ pub enum AudioOutputError {
     OpenStreamError,
     PlayStreamError,
     StreamClosedError,
+    BackendUnavailableError,
 }
 
 pub type Result<T> = result::Result<T, AudioOutputError>;
 
-#[cfg(target_os = "linux")]
+#[cfg(all(feature = "pulse", target_os = "linux"))]
 mod pulseaudio {
     use super::{AudioOutput, AudioOutputError, Result};
 
@@ -103,6 +109,12 @@ mod pulseaudio {
                 return Ok(());
             }
 
+            // Buffers may be bigger than when the output was opened (e.g. time-stretched ones)
+            if decoded.frames() * decoded.spec().channels.count() > self.sample_buf.capacity() {
+                self.sample_buf =
+                    RawSampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
+            }
+
             // Interleave samples from the audio buffer into the sample buffer.
             self.sample_buf.copy_interleaved_ref(decoded);
 
@@ -165,9 +177,9 @@ mod pulseaudio {
     }
 }
 
-#[cfg(not(target_os = "linux"))]
+#[cfg(any(feature = "cpal", not(target_os = "linux")))]
 mod cpal {
-    use crate::resampler::Resampler;
+    use crate::audio::resampler::Resampler;
 
     use super::{AudioOutput, AudioOutputError, Result};
 
@@ -180,6 +192,8 @@ mod cpal {
 
     use log::{error, info};
 
+    use std::time;
+
     pub struct CpalAudioOutput;
 
     trait AudioOutputSample:
@@ -232,9 +246,11 @@ mod cpal {
     where
         T: AudioOutputSample,
     {
+        ring_buf: SpscRb<T>,
         ring_buf_producer: rb::Producer<T>,
         sample_buf: SampleBuffer<T>,
-        stream: cpal::Stream,
+        #[allow(dead_code)]
+        stream: cpal::Stream, // plays as long as it is alive
         resampler: Option<Resampler<T>>,
     }
 
@@ -309,6 +325,7 @@ mod cpal {
             };
 
             Ok(Box::new(CpalAudioOutputImpl {
+                ring_buf,
                 ring_buf_producer,
                 sample_buf,
                 stream,
@@ -333,6 +350,10 @@ mod cpal {
                 }
             } else {
                 // Resampling is not required. Interleave the sample for cpal using a sample buffer.
+                if decoded.frames() * decoded.spec().channels.count() > self.sample_buf.capacity() {
+                    self.sample_buf =
+                        SampleBuffer::<T>::new(decoded.capacity() as u64, *decoded.spec());
+                }
                 self.sample_buf.copy_interleaved_ref(decoded);
 
                 self.sample_buf.samples()
@@ -357,18 +378,547 @@ mod cpal {
                 }
             }
 
+            // Wait for the stream to play what remains in the ring buffer. The stream is not
+            // paused, as the output may be re-used for the next song.
+            while !self.ring_buf.is_empty() {
+                std::thread::sleep(time::Duration::from_millis(10));
+            }
+        }
+    }
+}
+
+#[cfg(feature = "wav")]
+mod wav {
+    use super::{AudioOutput, AudioOutputError, Result};
+
+    use symphonia::core::audio::{AudioBufferRef, SampleBuffer, SignalSpec};
+    use symphonia::core::units::Duration;
+
+    use std::collections::HashMap;
+    use std::fs::{File, OpenOptions};
+    use std::io::{BufWriter, Seek, SeekFrom, Write};
+    use std::path::{Path, PathBuf};
+    use std::sync::Mutex;
+
+    use log::{error, info, warn};
+
+    const HEADER_LEN: u32 = 44;
+    const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+
+    /// The file written for a configured path during this run, by the outputs opened so far
+    struct Written {
+        path: PathBuf, // the configured one, or a numbered one after format changes
+        spec: SignalSpec,
+        number: u32,
+    }
+
+    // the output is re-opened between songs, the files are only created once per run
+    static WRITTEN: Mutex<Option<HashMap<PathBuf, Written>>> = Mutex::new(None);
+
+    /// Writes 32-bit float samples to a WAV file. The sizes in the header are
+    /// updated at each flush, and when the output is closed.
+    ///
+    /// The file is created when the output is first opened, and written on by the outputs
+    /// opened after it, for the following songs. When the format changes, a WAV file can't
+    /// hold both: the audio goes on in `<name>-1.wav`, `<name>-2.wav` and so on
+    pub struct WavOutput {
+        file: BufWriter<File>,
+        sample_buf: SampleBuffer<f32>,
+        bytes: Vec<u8>,
+        data_len: u32,
+    }
+
+    impl WavOutput {
+        pub fn try_open(
+            path: &Path,
+            spec: SignalSpec,
+            duration: Duration,
+        ) -> Result<Box<dyn AudioOutput>> {
+            let mut written = WRITTEN.lock().unwrap();
+            let written = written.get_or_insert_with(HashMap::new);
+            let (file_path, number) = match written.get(path) {
+                Some(file) if file.spec == spec => {
+                    match WavOutput::reopen(&file.path, spec, duration) {
+                        Ok(output) => return Ok(Box::new(output)),
+                        Err(err) => {
+                            // e.g. removed meanwhile
+                            warn!("failed to re-open {:?} ({err}), starting over", file.path);
+                            (file.path.clone(), file.number)
+                        }
+                    }
+                }
+                Some(file) => {
+                    let number = file.number + 1;
+                    let file_path = numbered(path, number);
+                    warn!("the audio format changed, going on in {file_path:?}");
+                    (file_path, number)
+                }
+                None => (path.to_path_buf(), 0),
+            };
+            let output = WavOutput::create(&file_path, spec, duration)?;
+            written.insert(
+                path.to_path_buf(),
+                Written {
+                    path: file_path,
+                    spec,
+                    number,
+                },
+            );
+            Ok(Box::new(output))
+        }
+
+        fn create(path: &Path, spec: SignalSpec, duration: Duration) -> Result<WavOutput> {
+            let file = match File::create(path) {
+                Ok(file) => file,
+                Err(err) => {
+                    error!("failed to create the wav file {path:?}: {err}");
+                    return Err(AudioOutputError::OpenStreamError);
+                }
+            };
+            info!("writing the audio to {path:?}");
+
+            let mut output = WavOutput {
+                file: BufWriter::new(file),
+                sample_buf: SampleBuffer::<f32>::new(duration, spec),
+                bytes: vec![],
+                data_len: 0,
+            };
+            if let Err(err) = output.write_header(spec) {
+                error!("failed to write the wav header to {path:?}: {err}");
+                return Err(AudioOutputError::OpenStreamError);
+            }
+            Ok(output)
+        }
+
+        /// Go on writing after the samples already in the file
+        fn reopen(path: &Path, spec: SignalSpec, duration: Duration) -> std::io::Result<WavOutput> {
+            let mut file = OpenOptions::new().write(true).open(path)?;
+            let len = file.seek(SeekFrom::End(0))?;
+            Ok(WavOutput {
+                file: BufWriter::new(file),
+                sample_buf: SampleBuffer::<f32>::new(duration, spec),
+                bytes: vec![],
+                data_len: len.saturating_sub(HEADER_LEN as u64) as u32,
+            })
+        }
+
+        fn write_header(&mut self, spec: SignalSpec) -> std::io::Result<()> {
+            let channels = spec.channels.count() as u16;
+            let block_align = channels * 4;
+
+            let mut header = Vec::with_capacity(HEADER_LEN as usize);
+            header.extend_from_slice(b"RIFF");
+            header.extend_from_slice(&(HEADER_LEN - 8).to_le_bytes()); // patched later
+            header.extend_from_slice(b"WAVEfmt ");
+            header.extend_from_slice(&16u32.to_le_bytes());
+            header.extend_from_slice(&WAVE_FORMAT_IEEE_FLOAT.to_le_bytes());
+            header.extend_from_slice(&channels.to_le_bytes());
+            header.extend_from_slice(&spec.rate.to_le_bytes());
+            header.extend_from_slice(&(spec.rate * block_align as u32).to_le_bytes());
+            header.extend_from_slice(&block_align.to_le_bytes());
+            header.extend_from_slice(&32u16.to_le_bytes());
+            header.extend_from_slice(b"data");
+            header.extend_from_slice(&0u32.to_le_bytes()); // patched later
+            self.file.write_all(&header)
+        }
+
+        fn update_header(&mut self) -> std::io::Result<()> {
+            self.file.flush()?;
+            let file = self.file.get_mut();
+            file.seek(SeekFrom::Start(4))?;
+            file.write_all(&(HEADER_LEN - 8 + self.data_len).to_le_bytes())?;
+            file.seek(SeekFrom::Start(HEADER_LEN as u64 - 4))?;
+            file.write_all(&self.data_len.to_le_bytes())?;
+            file.seek(SeekFrom::End(0))?;
+            Ok(())
+        }
+    }
+
+    /// `<name>-<number>.<extension>`, for the files following the configured one
+    fn numbered(path: &Path, number: u32) -> PathBuf {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let name = match path.extension() {
+            Some(extension) => format!("{stem}-{number}.{}", extension.to_string_lossy()),
+            None => format!("{stem}-{number}"),
+        };
+        path.with_file_name(name)
+    }
+
+    impl AudioOutput for WavOutput {
+        fn write(&mut self, decoded: AudioBufferRef<'_>) -> Result<()> {
+            if decoded.frames() == 0 {
+                return Ok(());
+            }
+
+            if decoded.frames() * decoded.spec().channels.count() > self.sample_buf.capacity() {
+                self.sample_buf =
+                    SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
+            }
+            self.sample_buf.copy_interleaved_ref(decoded);
+
+            // WAV files are little-endian, whatever the platform
+            self.bytes.clear();
+            for sample in self.sample_buf.samples() {
+                self.bytes.extend_from_slice(&sample.to_le_bytes());
+            }
+
+            match self.file.write_all(&self.bytes) {
+                Err(err) => {
+                    error!("wav file write error: {err}");
+                    Err(AudioOutputError::StreamClosedError)
+                }
+                _ => {
+                    self.data_len = self.data_len.saturating_add(self.bytes.len() as u32);
+                    Ok(())
+                }
+            }
+        }
+
+        fn flush(&mut self) {
+            if let Err(err) = self.update_header() {
+                error!("failed to update the wav header: {err}");
+            }
+        }
+    }
+
+    impl Drop for WavOutput {
+        fn drop(&mut self) {
+            self.flush();
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use symphonia::core::audio::{AsAudioBufferRef, AudioBuffer, Channels, Signal};
+
+        const FRAMES: usize = 100;
+
+        fn write_song(path: &Path, rate: u32) {
+            let spec = SignalSpec::new(rate, Channels::FRONT_LEFT | Channels::FRONT_RIGHT);
+            let mut buffer = AudioBuffer::<f32>::new(FRAMES as u64, spec);
+            buffer.render_reserved(Some(FRAMES));
+            let mut output = WavOutput::try_open(path, spec, FRAMES as u64).unwrap();
+            output.write(buffer.as_audio_buffer_ref()).unwrap();
+        }
+
+        fn data_len(path: &Path) -> u32 {
+            let bytes = std::fs::read(path).unwrap();
+            assert_eq!(bytes.len() as u32, HEADER_LEN + FRAMES as u32 * 2 * 4 * 2);
+            u32::from_le_bytes(bytes[40..44].try_into().unwrap())
+        }
+
+        #[test]
+        fn songs_follow_each_other_in_the_file() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("out.wav");
+            std::fs::write(&path, b"from an earlier run").unwrap();
+            write_song(&path, 44100);
+            write_song(&path, 44100);
+            assert_eq!(data_len(&path), FRAMES as u32 * 2 * 4 * 2);
+        }
+
+        #[test]
+        fn format_change_goes_on_in_a_numbered_file() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("out.wav");
+            write_song(&path, 44100);
+            write_song(&path, 44100);
+            write_song(&path, 48000);
+            write_song(&path, 48000);
+            write_song(&path, 44100);
+            assert_eq!(data_len(&path), FRAMES as u32 * 2 * 4 * 2);
+            assert_eq!(
+                data_len(&dir.path().join("out-1.wav")),
+                FRAMES as u32 * 2 * 4 * 2
+            );
+            assert!(dir.path().join("out-2.wav").is_file());
+        }
+    }
+}
+
+#[cfg(feature = "pipe")]
+mod pipe {
+    use super::{AudioOutput, AudioOutputError, Result};
+
+    use symphonia::core::audio::{AudioBufferRef, SampleBuffer, SignalSpec};
+    use symphonia::core::units::Duration;
+
+    use std::collections::HashSet;
+    use std::fs::OpenOptions;
+    use std::io::{BufWriter, Write};
+    use std::path::{Path, PathBuf};
+    use std::sync::Mutex;
+
+    use log::{error, info};
+
+    // the output is re-opened between songs, a regular file is only truncated once per run
+    static OPENED: Mutex<Option<HashSet<PathBuf>>> = Mutex::new(None);
+
+    /// Writes raw interleaved s16le samples, for another program to play or record
+    pub struct PipeOutput {
+        pipe: BufWriter<Box<dyn Write>>,
+        sample_buf: SampleBuffer<i16>,
+        bytes: Vec<u8>,
+    }
+
+    impl PipeOutput {
+        /// Write to the file or FIFO at `path`, or to stdout if `None`.
+        /// Opening a FIFO blocks until a reader opens it.
+        ///
+        /// A regular file is truncated when the output is first opened, the outputs opened
+        /// after it, for the following songs, append to it
+        pub fn try_open(
+            path: Option<&Path>,
+            spec: SignalSpec,
+            duration: Duration,
+        ) -> Result<Box<dyn AudioOutput>> {
+            let pipe: Box<dyn Write> = match path {
+                Some(path) => match PipeOutput::open(path) {
+                    Ok(file) => Box::new(file),
+                    Err(err) => {
+                        error!("failed to open the audio pipe {path:?}: {err}");
+                        return Err(AudioOutputError::OpenStreamError);
+                    }
+                },
+                None => Box::new(std::io::stdout()),
+            };
+            info!(
+                "writing s16le audio, {} channels at {} Hz, to {}",
+                spec.channels.count(),
+                spec.rate,
+                path.map(|p| p.display().to_string())
+                    .unwrap_or("stdout".to_string())
+            );
+
+            Ok(Box::new(PipeOutput {
+                pipe: BufWriter::new(pipe),
+                sample_buf: SampleBuffer::<i16>::new(duration, spec),
+                bytes: vec![],
+            }))
+        }
+
+        fn open(path: &Path) -> std::io::Result<std::fs::File> {
+            let mut opened = OPENED.lock().unwrap();
+            let opened = opened.get_or_insert_with(HashSet::new);
+            let first = !opened.contains(path);
+            let file = OpenOptions::new()
+                .create(true)
+                .truncate(first)
+                .append(!first)
+                .write(true)
+                .open(path)?;
+            opened.insert(path.to_path_buf());
+            Ok(file)
+        }
+    }
+
+    impl AudioOutput for PipeOutput {
+        fn write(&mut self, decoded: AudioBufferRef<'_>) -> Result<()> {
+            if decoded.frames() == 0 {
+                return Ok(());
+            }
+
+            if decoded.frames() * decoded.spec().channels.count() > self.sample_buf.capacity() {
+                self.sample_buf =
+                    SampleBuffer::<i16>::new(decoded.capacity() as u64, *decoded.spec());
+            }
+            self.sample_buf.copy_interleaved_ref(decoded);
+
+            self.bytes.clear();
+            for sample in self.sample_buf.samples() {
+                self.bytes.extend_from_slice(&sample.to_le_bytes());
+            }
+
+            match self.pipe.write_all(&self.bytes) {
+                Err(err) => {
+                    error!("audio pipe write error: {err}");
+                    Err(AudioOutputError::StreamClosedError)
+                }
+                _ => Ok(()),
+            }
+        }
+
+        fn flush(&mut self) {
             // Flush is best-effort, ignore the returned result.
-            let _ = self.stream.pause();
+            let _ = self.pipe.flush();
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use symphonia::core::audio::{AsAudioBufferRef, AudioBuffer, Channels, Signal};
+
+        const FRAMES: usize = 100;
+
+        fn write_song(path: &Path) {
+            let spec = SignalSpec::new(44100, Channels::FRONT_LEFT | Channels::FRONT_RIGHT);
+            let mut buffer = AudioBuffer::<f32>::new(FRAMES as u64, spec);
+            buffer.render_reserved(Some(FRAMES));
+            let mut output = PipeOutput::try_open(Some(path), spec, FRAMES as u64).unwrap();
+            output.write(buffer.as_audio_buffer_ref()).unwrap();
+            output.flush();
+        }
+
+        #[test]
+        fn songs_follow_each_other_in_a_file() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("out.raw");
+            std::fs::write(&path, b"from an earlier run").unwrap();
+            write_song(&path);
+            write_song(&path);
+            let len = std::fs::metadata(&path).unwrap().len();
+            assert_eq!(len, FRAMES as u64 * 2 * 2 * 2);
         }
     }
 }
 
-#[cfg(target_os = "linux")]
-pub fn try_open(spec: SignalSpec, duration: Duration) -> Result<Box<dyn AudioOutput>> {
-    pulseaudio::PulseAudioOutput::try_open(spec, duration)
+#[cfg(feature = "null")]
+mod null {
+    use super::{AudioOutput, Result};
+
+    use crate::audio::Capture;
+
+    use symphonia::core::audio::{AudioBufferRef, SampleBuffer, SignalSpec};
+    use symphonia::core::units::Duration;
+
+    use std::time::{self, Instant};
+
+    /// Takes as long to consume audio as playing it `pace` times faster would
+    struct Pacer {
+        pace: f64,             // 0 for no waiting at all
+        played_until: Instant, // when the audio written so far would be done playing
+    }
+
+    impl Pacer {
+        fn new(pace: f64) -> Self {
+            Pacer {
+                pace,
+                played_until: Instant::now(),
+            }
+        }
+
+        fn consume(&mut self, frames: usize, rate: u32) {
+            if self.pace <= 0.0 || rate == 0 {
+                return;
+            }
+            let now = Instant::now();
+            // nothing was written for a while (paused), don't catch up
+            if self.played_until < now {
+                self.played_until = now;
+            }
+            self.played_until +=
+                time::Duration::from_secs_f64(frames as f64 / rate as f64 / self.pace);
+            std::thread::sleep(self.played_until - now);
+        }
+    }
+
+    /// Discards the audio, taking as long as playing it would
+    pub struct NullOutput {
+        pacer: Pacer,
+    }
+
+    impl NullOutput {
+        pub fn try_open(_spec: SignalSpec) -> Result<Box<dyn AudioOutput>> {
+            Ok(Box::new(NullOutput {
+                pacer: Pacer::new(1.0),
+            }))
+        }
+    }
+
+    impl AudioOutput for NullOutput {
+        fn write(&mut self, decoded: AudioBufferRef<'_>) -> Result<()> {
+            self.pacer.consume(decoded.frames(), decoded.spec().rate);
+            Ok(())
+        }
+
+        fn flush(&mut self) {}
+    }
+
+    /// Discards the audio like `NullOutput`, and records what was written in a `Capture`
+    pub struct CaptureOutput {
+        pacer: Pacer,
+        capture: Capture,
+        index: usize,                          // of this output in the capture
+        sample_buf: Option<SampleBuffer<f32>>, // only when keeping the samples
+    }
+
+    impl CaptureOutput {
+        pub fn try_open(
+            capture: &Capture,
+            pace: f32,
+            keep_samples: bool,
+            spec: SignalSpec,
+            duration: Duration,
+        ) -> Result<Box<dyn AudioOutput>> {
+            Ok(Box::new(CaptureOutput {
+                pacer: Pacer::new(pace as f64),
+                capture: capture.clone(),
+                index: capture.open(spec, keep_samples),
+                sample_buf: keep_samples.then(|| SampleBuffer::<f32>::new(duration, spec)),
+            }))
+        }
+    }
+
+    impl AudioOutput for CaptureOutput {
+        fn write(&mut self, decoded: AudioBufferRef<'_>) -> Result<()> {
+            let frames = decoded.frames();
+            if frames == 0 {
+                return Ok(());
+            }
+            let rate = decoded.spec().rate;
+
+            match &mut self.sample_buf {
+                Some(sample_buf) => {
+                    if frames * decoded.spec().channels.count() > sample_buf.capacity() {
+                        *sample_buf =
+                            SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
+                    }
+                    sample_buf.copy_interleaved_ref(decoded);
+                    self.capture
+                        .record_write(self.index, frames, sample_buf.samples());
+                }
+                None => self.capture.record_write(self.index, frames, &[]),
+            }
+
+            self.pacer.consume(frames, rate);
+            Ok(())
+        }
+
+        fn flush(&mut self) {
+            self.capture.record_flush(self.index);
+        }
+    }
 }
 
-#[cfg(not(target_os = "linux"))]
-pub fn try_open(spec: SignalSpec, duration: Duration) -> Result<Box<dyn AudioOutput>> {
-    cpal::CpalAudioOutput::try_open(spec, duration)
+/// Open the configured audio output. `capture` is only used by the capture output
+#[cfg_attr(not(feature = "null"), allow(unused_variables))]
+pub fn try_open(
+    backend: &AudioBackend,
+    capture: &Capture,
+    spec: SignalSpec,
+    duration: Duration,
+) -> Result<Box<dyn AudioOutput>> {
+    match backend {
+        #[cfg(all(feature = "pulse", target_os = "linux"))]
+        AudioBackend::Pulse => pulseaudio::PulseAudioOutput::try_open(spec, duration),
+        #[cfg(any(feature = "cpal", not(target_os = "linux")))]
+        AudioBackend::Cpal => cpal::CpalAudioOutput::try_open(spec, duration),
+        #[cfg(feature = "wav")]
+        AudioBackend::Wav(path) => wav::WavOutput::try_open(path, spec, duration),
+        #[cfg(feature = "pipe")]
+        AudioBackend::Pipe(path) => pipe::PipeOutput::try_open(path.as_deref(), spec, duration),
+        #[cfg(feature = "null")]
+        AudioBackend::Null => null::NullOutput::try_open(spec),
+        #[cfg(feature = "null")]
+        AudioBackend::Capture { pace, samples } => {
+            null::CaptureOutput::try_open(capture, *pace, *samples, spec, duration)
+        }
+        #[allow(unreachable_patterns)]
+        backend => {
+            error!("audio output {backend:?} is not available, it was not enabled at build time");
+            Err(AudioOutputError::BackendUnavailableError)
+        }
+    }
 }
@@ -1,47 +1,76 @@
-use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::audio::{AudioBufferRef, SignalSpec};
+use symphonia::core::codecs::{Decoder, DecoderOptions, CODEC_TYPE_NULL};
 use symphonia::core::errors::Error;
-use symphonia::core::formats::FormatOptions;
-use symphonia::core::io::MediaSourceStream;
+use symphonia::core::formats::{FormatOptions, FormatReader, Packet, SeekMode, SeekTo};
+use symphonia::core::io::{MediaSource, MediaSourceStream};
 use symphonia::core::meta::MetadataOptions;
 use symphonia::core::probe::Hint;
-// use symphonia::core::units::TimeBase;
+use symphonia::core::units::{Time, TimeBase};
 
+use crate::config::{self, AudioBackend, Config};
 use crate::music::song::*;
 use std::fs::File;
+use std::path::Path;
+use std::sync::mpsc;
 
-use log::{debug, info, warn};
+use log::{debug, error, info, warn};
 
-use super::output;
+use super::capture::Capture;
+use super::dsp::Dsp;
+use super::equalizer::Equalizer;
+use super::format::OutputFormat;
+use super::meter::{Levels, Meter};
+use super::output::{self, AudioOutput};
+use super::stream::open_stream;
 
 use std::time;
 
-use tokio::sync::broadcast::{Receiver, Sender};
+use tokio::sync::broadcast::Sender;
 
+use std::panic::AssertUnwindSafe;
 use std::thread;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub enum AudioEvent {
     Finished,
-    Failed,
-    SeekIs(f32),
+    Failed(String),   // the current song can't be played, and why
+    SeekIs(f32, u64), // seek as a fraction of the song, and in milliseconds of the song's
+    // own timeline, whatever the playback speed
+    NextStarted, // the preloaded next song started playing without interruption
+    Interrupted(Song, u64, Option<u64>), // a command stopped playing the song at a position,
+    // in a song of a duration if known, in milliseconds
+    Restarted,      // the audio thread crashed and started over, with its initial settings
+    Levels(Levels), // of the audio being played, at the configured rate
+    StreamTitle(String), // what the stream being played plays now
+    Resolved(u64, Song, Result<SongSource, String>), // an online song was resolved, off
+                    // the audio thread, for a play request
 }
 
 // The audio thread responds immediately (or at least ASAP)
 // to these commands
 #[derive(Debug, Clone)]
 pub enum AudioCommand {
-    PlayNew(Song),
+    PlayNew(Song, SongSource), // played from a file or a stream, resolved already if online
+    PlayNewFrom(Song, SongSource, u64), // starting at a position in milliseconds
     Play,
     Pause,
     Stop, // pause and forget current song
 
+    SetNext(Option<Song>), // song to preload and chain to once the current one finishes
+    SetEqualizer(Equalizer),
+    SetSpeed(f32),               // playback rate, the pitch is kept
+    SetVolume(f32, u64),         // between 0 and 1, reached after a ramp of some milliseconds
+    SetLoop(Option<(u64, u64)>), // go back to A when reaching B, in milliseconds
+
     GetSeek,
-    Seek(f32),
+    Seek(f32),   // fraction of the song, between 0 and 1
+    SeekTo(u64), // absolute position in milliseconds
+    SeekBy(i64), // relative to the current position, in milliseconds
 
     Quit, // quit loops and get ready to exit this thread
 
-    DoneOk,  // internal return status
-    DoneErr, // internal return status
+    DoneOk,          // internal return status
+    DoneErr(String), // internal return status
 }
 use AudioCommand::*;
 
@@ -60,8 +89,50 @@ impl std::fmt::Debug for AudioThread {
     }
 }
 
-pub fn start_audio_thread(rx: Receiver<AudioCommand>, tx: Sender<AudioEvent>) -> AudioThread {
-    let handle = std::thread::spawn(|| audio_thread_fn(rx, tx));
+pub fn start_audio_thread(
+    rx: mpsc::Receiver<AudioCommand>,
+    tx: Sender<AudioEvent>,
+    config: &Config,
+    capture: Capture,
+) -> AudioThread {
+    let seek_mode = match config.seek_mode {
+        config::SeekMode::Accurate => SeekMode::Accurate,
+        config::SeekMode::Coarse => SeekMode::Coarse,
+    };
+    let equalizer = config.startup_equalizer();
+    let format = config.output_format;
+    let backend = config.audio_output.clone();
+    let meter_rate = config.meter_rate;
+    let handle = std::thread::spawn(move || {
+        // a panic would leave the server without audio: start over instead
+        loop {
+            let player = Player::new(
+                seek_mode,
+                equalizer.clone(),
+                format,
+                backend.clone(),
+                capture.clone(),
+                meter_rate,
+            );
+            let run =
+                std::panic::catch_unwind(AssertUnwindSafe(|| audio_thread_fn(player, &rx, &tx)));
+            match run {
+                Ok(()) => break,
+                Err(panic) => {
+                    let reason = panic
+                        .downcast_ref::<&str>()
+                        .map(|s| s.to_string())
+                        .or_else(|| panic.downcast_ref::<String>().cloned())
+                        .unwrap_or_default();
+                    error!("audio thread panicked ({reason}), restarting it");
+                    let _ = tx.send(AudioEvent::Failed(format!(
+                        "audio thread crashed: {reason}"
+                    )));
+                    let _ = tx.send(AudioEvent::Restarted);
+                }
+            }
+        }
+    });
     debug!("audio thread started");
 
     AudioThread {
@@ -69,7 +140,7 @@ pub fn start_audio_thread(rx: Receiver<AudioCommand>, tx: Sender<AudioEvent>) ->
         current: None,
     }
 }
-pub fn audio_thread_send_cmd(cmd: AudioCommand, tx: &Sender<AudioCommand>) {
+pub fn audio_thread_send_cmd(cmd: AudioCommand, tx: &mpsc::Sender<AudioCommand>) {
     tx.send(cmd).unwrap();
     debug!("audio thread cmd passed");
 }
@@ -80,28 +151,241 @@ pub fn stop_audio_thread(audio_thread: AudioThread) {
 }
 
 // convert seek between 0 and 1 to u64 milliseconds value
-fn convert_seek_real_to_ms(seek_real: f32, opt_current_song: Option<Song>) -> u64 {
-    if let Some(current_song) = opt_current_song {
-        return (current_song.duration.as_millis() * (seek_real * 1024f32) as u128 / 1024) as u64;
-    } else {
-        0
-    }
+fn convert_seek_real_to_ms(seek_real: f32, duration_ms: u64) -> u64 {
+    (duration_ms as u128 * (seek_real.clamp(0.0, 1.0) * 1024f32) as u128 / 1024) as u64
 }
 
-// convert seek between 0 and 1 to u64 milliseconds value
-fn convert_seek_ms_to_real(seek_ms: u64, opt_current_song: Option<Song>) -> f32 {
-    if let Some(current_song) = opt_current_song {
-        return seek_ms as f32 / current_song.duration.as_millis() as f32;
-    } else {
-        0.0
+// convert u64 milliseconds value to seek between 0 and 1
+fn convert_seek_ms_to_real(seek_ms: u64, duration_ms: u64) -> f32 {
+    if duration_ms == 0 {
+        return 0.0;
     }
+    seek_ms as f32 / duration_ms as f32
+}
+
+fn time_to_ms(time: Time) -> u64 {
+    time.seconds * 1000 + (time.frac * 1000.0) as u64
+}
+
+fn ms_to_time(ms: u64) -> Time {
+    Time::new(ms / 1000, (ms % 1000) as f64 / 1000.0)
 }
 
 const AUDIO_THREAD_EQ_POLL_PERIOD_MS: u64 = 50;
 
-fn audio_thread_fn(mut rx: Receiver<AudioCommand>, tx: Sender<AudioEvent>) {
-    let mut current_seek_ms = 0; //current seek in milliseconds
-    let mut current_song = None;
+/// A song whose file has been opened and probed, ready to be decoded
+pub(super) struct OpenedSong {
+    song: Song,
+    pub format: Box<dyn FormatReader>,
+    pub decoder: Box<dyn Decoder>,
+    pub track_id: u32,
+    time_base: TimeBase,
+    duration_ms: Option<u64>, // as found in the container, more reliable than the tags
+    titles: Option<mpsc::Receiver<String>>, // of what a stream plays, as it changes
+
+    // for songs that are only part of their file, where they start and end in the file
+    start_ms: u64,
+    end_ts: Option<u64>,
+    first_ts: u64, // packets before this timestamp are decoded but not played
+}
+
+impl OpenedSong {
+    /// Position of the packet within the song, in milliseconds
+    fn position_ms(&self, ts: u64) -> u64 {
+        let tb = self.time_base;
+        (ts * 1000 * (tb.numer as u64) / (tb.denom as u64)).saturating_sub(self.start_ms)
+    }
+
+    /// Whether the packet comes after the end of the song, for songs that stop before their file
+    pub fn is_past_end(&self, packet: &Packet) -> bool {
+        packet.track_id() == self.track_id && self.end_ts.is_some_and(|end| packet.ts() >= end)
+    }
+}
+
+/// The audio output currently open, with the spec it was opened for
+struct OpenedOutput {
+    output: Box<dyn AudioOutput>,
+    spec: SignalSpec,
+}
+
+/// The configured audio output, opened on demand
+struct Output {
+    backend: AudioBackend,
+    capture: Capture, // what the capture output records, if used
+    opened: Option<OpenedOutput>,
+}
+
+impl Output {
+    /// Make sure the output is open and can accept buffers of this spec.
+    /// An output with a matching spec is re-used as-is
+    fn prepare(&mut self, spec: SignalSpec, duration: u64) -> Result<(), String> {
+        if let Some(opened) = &self.opened {
+            if opened.spec == spec {
+                return Ok(());
+            }
+            debug!("audio spec changed, re-opening the audio output");
+        }
+        self.close();
+        let output = output::try_open(&self.backend, &self.capture, spec, duration)
+            .map_err(|e| format!("failed to open the audio output: {e:?}"))?;
+        self.opened.replace(OpenedOutput { output, spec });
+        Ok(())
+    }
+
+    fn write(&mut self, buffer: AudioBufferRef<'_>) -> Result<(), String> {
+        // Get the audio buffer specification. This is a description of the
+        // audio buffer's sample format and sample rate.
+        let spec = *buffer.spec();
+
+        // Get the capacity of the buffer. Note that this is capacity, not length!
+        // The outputs are opened for this many frames, and grow if later buffers are bigger.
+        let duration = buffer.capacity() as u64;
+
+        self.prepare(spec, duration)?;
+        let opened = self.opened.as_mut().unwrap();
+        if let Err(e) = opened.output.write(buffer) {
+            // broken for good, open a new one for the next song
+            self.opened = None;
+            return Err(format!("failed to write to the audio output: {e:?}"));
+        }
+        Ok(())
+    }
+
+    /// Play what remains in the output buffers
+    fn flush(&mut self) {
+        if let Some(ref mut opened) = self.opened {
+            opened.output.flush();
+        }
+    }
+
+    fn close(&mut self) {
+        self.flush();
+        self.opened = None;
+    }
+}
+
+/// Audio thread state that outlives a single song: the output is kept open
+/// across tracks and the next queued song is opened ahead of time,
+/// so that consecutive tracks play without gap
+struct Player {
+    current_song: Option<Song>,
+    source: Option<SongSource>,  // what the current song is played from
+    seek_ms: u64,                // current seek in milliseconds
+    duration_ms: Option<u64>,    // duration of the current song, once opened
+    ab_loop: Option<(u64, u64)>, // within the current song, A before B
+    seek_mode: SeekMode,
+    next_song: Option<Song>,
+    preloaded: Option<OpenedSong>,
+    dsp: Dsp,
+    meter: Option<Meter>, // if levels are reported
+    output: Output,
+}
+
+impl Player {
+    fn new(
+        seek_mode: SeekMode,
+        equalizer: Equalizer,
+        format: OutputFormat,
+        backend: AudioBackend,
+        capture: Capture,
+        meter_rate: u32,
+    ) -> Self {
+        Player {
+            current_song: None,
+            source: None,
+            seek_ms: 0,
+            duration_ms: None,
+            ab_loop: None,
+            seek_mode,
+            next_song: None,
+            preloaded: None,
+            dsp: Dsp::new(equalizer, format),
+            meter: (meter_rate > 0).then(|| Meter::new(meter_rate)),
+            output: Output {
+                backend,
+                capture,
+                opened: None,
+            },
+        }
+    }
+
+    /// Duration of the current song in milliseconds, as precisely as known
+    fn current_duration_ms(&self) -> u64 {
+        match (self.duration_ms, &self.current_song) {
+            (Some(duration_ms), _) => duration_ms,
+            (None, Some(song)) => song.duration.as_millis() as u64,
+            (None, None) => 0,
+        }
+    }
+
+    /// Position in milliseconds that a seek command points to
+    fn seek_target_ms(&self, cmd: &AudioCommand) -> u64 {
+        let duration_ms = self.current_duration_ms();
+        match *cmd {
+            Seek(seek) => convert_seek_real_to_ms(seek, duration_ms),
+            SeekTo(seek_ms) => std::cmp::min(seek_ms, duration_ms),
+            SeekBy(delta_ms) => {
+                (self.seek_ms as i64 + delta_ms).clamp(0, duration_ms as i64) as u64
+            }
+            _ => self.seek_ms,
+        }
+    }
+
+    fn seek_event(&self) -> AudioEvent {
+        AudioEvent::SeekIs(
+            convert_seek_ms_to_real(self.seek_ms, self.current_duration_ms()),
+            self.seek_ms,
+        )
+    }
+
+    fn set_next(&mut self, next_song: Option<Song>) {
+        // whatever was preloaded may not be the next song anymore
+        self.preloaded = None;
+        self.next_song = next_song;
+    }
+
+    /// Open the next song in advance, if there is one and it's not open yet
+    fn preload_next(&mut self) {
+        if self.preloaded.is_none() {
+            if let Some(next_song) = self.next_song.take() {
+                // a live stream would fall behind while waiting: it's opened when it plays
+                if !matches!(
+                    next_song.source,
+                    Some(SongSource::FilePath(_) | SongSource::FileRange(..))
+                ) {
+                    return;
+                }
+                debug!("preloading next song {:?}", next_song.title);
+                match open_song(&next_song, next_song.source.as_ref()) {
+                    Ok(opened) => self.preloaded = Some(opened),
+                    // it fails again, and is reported, when it's its turn to play
+                    Err(e) => warn!("failed to preload {:?}: {e}", next_song.title),
+                }
+            }
+        }
+    }
+
+    /// Process the decoded audio and play it, opening the output
+    /// (again, if the spec changed) when needed
+    fn write(&mut self, decoded: AudioBufferRef<'_>) -> Result<(), String> {
+        let processed = self.dsp.process(decoded);
+        if let Some(meter) = &mut self.meter {
+            meter.process(&processed);
+        }
+        self.output.write(processed)
+    }
+
+    /// Play what remains in the output buffers
+    fn flush_output(&mut self) {
+        self.output.flush();
+    }
+
+    fn close_output(&mut self) {
+        self.output.close();
+    }
+}
+
+fn audio_thread_fn(mut player: Player, rx: &mpsc::Receiver<AudioCommand>, tx: &Sender<AudioEvent>) {
     let mut command_from_decode_loop: Option<AudioCommand> = None;
     loop {
         let do_cmd = if let Some(cmd) = command_from_decode_loop.clone() {
@@ -122,40 +406,35 @@ fn audio_thread_fn(mut rx: Receiver<AudioCommand>, tx: Sender<AudioEvent>) {
             }
             Some(Quit) => {
                 debug!("Quit received in audio thread");
+                player.close_output();
                 break;
             }
-            Some(PlayNew(song)) => {
-                current_song = Some(song.clone());
-                current_seek_ms = 0;
-                match decode(&song, &mut rx, &tx, &mut current_seek_ms) {
-                    Some(DoneOk) => {
-                        tx.send(AudioEvent::Finished).unwrap();
-                        None
-                    }
-                    Some(DoneErr) => {
-                        tx.send(AudioEvent::Failed).unwrap();
-                        None
-                    }
-                    cmd => cmd,
-                }
+            Some(PlayNew(song, source)) => {
+                player.current_song = Some(song);
+                player.source = Some(source);
+                player.seek_ms = 0;
+                player.duration_ms = None;
+                player.ab_loop = None;
+                let result = decode(&mut player, rx, tx);
+                report_done(result, tx)
+            }
+            Some(PlayNewFrom(song, source, seek_ms)) => {
+                player.current_song = Some(song);
+                player.source = Some(source);
+                player.seek_ms = seek_ms;
+                player.duration_ms = None;
+                player.ab_loop = None;
+                let result = decode(&mut player, rx, tx);
+                report_done(result, tx)
             }
             Some(Play) => {
-                if let Some(song) = &current_song {
+                if let Some(song) = &player.current_song {
                     debug!(
-                        "resuming play of current song: {:?} at seek {current_seek_ms}",
-                        song
+                        "resuming play of current song: {:?} at seek {}",
+                        song, player.seek_ms
                     );
-                    match decode(&song, &mut rx, &tx, &mut current_seek_ms) {
-                        Some(DoneOk) => {
-                            tx.send(AudioEvent::Finished).unwrap();
-                            None
-                        }
-                        Some(DoneErr) => {
-                            tx.send(AudioEvent::Failed).unwrap();
-                            None
-                        }
-                        cmd => cmd,
-                    }
+                    let result = decode(&mut player, rx, tx);
+                    report_done(result, tx)
                 } else {
                     warn!("Play requested but no current song");
                     None
@@ -163,201 +442,434 @@ fn audio_thread_fn(mut rx: Receiver<AudioCommand>, tx: Sender<AudioEvent>) {
             }
             Some(Pause) => None,
             Some(Stop) => {
-                current_song = None;
+                player.current_song = None;
+                player.source = None;
+                player.close_output();
+                None
+            }
+            Some(SetNext(next_song)) => {
+                player.set_next(next_song);
+                None
+            }
+            Some(SetEqualizer(equalizer)) => {
+                player.dsp.set_equalizer(equalizer);
+                None
+            }
+            Some(SetSpeed(speed)) => {
+                player.dsp.set_speed(speed);
+                None
+            }
+            Some(SetVolume(volume, ramp_ms)) => {
+                player.dsp.set_volume(volume, ramp_ms);
+                None
+            }
+            Some(SetLoop(ab_loop)) => {
+                player.ab_loop = ab_loop;
                 None
             }
             Some(GetSeek) => {
-                tx.send(AudioEvent::SeekIs(convert_seek_ms_to_real(
-                    current_seek_ms,
-                    current_song.clone(),
-                )))
-                .unwrap();
+                let _ = tx.send(player.seek_event());
+                None
+            }
+            Some(cmd @ (Seek(_) | SeekTo(_) | SeekBy(_))) => {
+                // not playing: the seek happens when the song is opened again
+                player.seek_ms = player.seek_target_ms(&cmd);
+                debug!("seeked audio thread to : {} ms", player.seek_ms);
                 None
             }
-            Some(Seek(seek)) => {
-                current_seek_ms = convert_seek_real_to_ms(seek, current_song.clone());
-                debug!("seeked audio thread to : {current_seek_ms} ms");
+            Some(status @ (DoneOk | DoneErr(_))) => {
+                warn!("unexpected internal status {status:?} in the audio thread, ignoring it");
                 None
             }
-            Some(DoneOk) => panic!(),
-            Some(DoneErr) => panic!(),
         }
     }
     debug!("audio outer loop finished");
 }
 
-pub fn decode(
-    song: &Song,
-    rx: &mut Receiver<AudioCommand>,
-    tx: &Sender<AudioEvent>,
-    seek: &mut u64,
-) -> Option<AudioCommand> {
-    if let Some(SongSource::FilePath(song_source_filepath)) = song.clone().source {
-        let song_src = File::open(&song_source_filepath).unwrap();
-        info!("trying to play file {:?}", song_src);
-
-        // Create the media source stream.
-        let mss = MediaSourceStream::new(Box::new(song_src), Default::default());
-
-        let mut hint = Hint::new();
-        hint.with_extension(&song.format.to_string());
-
-        // Use the default options for metadata and format readers.
-        let meta_opts: MetadataOptions = Default::default();
-        let mut fmt_opts: FormatOptions = Default::default();
-        fmt_opts.enable_gapless = true;
-
-        // Probe the media source.
-        let probed = symphonia::default::get_probe()
-            .format(&hint, mss, &fmt_opts, &meta_opts)
-            .expect("unsupported format");
-
-        // Get the instantiated format reader.
-        let mut format = probed.format; // TODO ? check formats match
-
-        // Find the first audio track with a known (decodeable) codec.
-        let track = format
-            .tracks()
-            .iter()
-            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
-            .expect("no supported audio tracks");
-        // Use the default options for the decoder.
-        let dec_opts: DecoderOptions = Default::default();
-
-        // Create a decoder for the track.
-        let mut decoder = symphonia::default::get_codecs()
-            .make(&track.codec_params, &dec_opts)
-            .expect("unsupported codec");
-
-        // Store the track identifier, it will be used to filter packets.
-
-        let track_id = track.id;
-
-        let mut audio_output = None;
-
-        let no_progress = false;
-
-        // Get the selected track's timebase and duration.
-        let tb = track.codec_params.time_base.unwrap();
-        // let dur = track
-        //     .codec_params
-        //     .n_frames
-        //     .map(|frames| track.codec_params.start_ts + frames);
-
-        // The decode loop.
-        let result: Result<(), Error> = loop {
-            // Get the next packet from the media format.
-            let packet = match format.next_packet() {
-                Ok(packet) => packet,
-                Err(Error::ResetRequired) => {
-                    // The track list has been changed. Re-examine it and create a new set of decoders,
-                    // then restart the decode loop. This is an advanced feature and it is not
-                    // unreasonable to consider this "the end." As of v0.5.0, the only usage of this is
-                    // for chained OGG physical streams.
-                    unimplemented!();
-                }
+/// Let the audio task know how playing the song ended, or pass on the
+/// command that interrupted it
+fn report_done(result: Option<AudioCommand>, tx: &Sender<AudioEvent>) -> Option<AudioCommand> {
+    match result {
+        Some(DoneOk) => {
+            let _ = tx.send(AudioEvent::Finished);
+            None
+        }
+        Some(DoneErr(reason)) => {
+            warn!("failed to play song: {reason}");
+            let _ = tx.send(AudioEvent::Failed(reason));
+            None
+        }
+        cmd => cmd,
+    }
+}
 
-                Err(err) => {
-                    // A unrecoverable error occured, halt decoding.
-                    break Err(err);
-                }
-            };
+/// Open and probe the song's file or stream, and create a decoder for its first audio track.
+/// Online songs must have been resolved to one of them before
+pub(super) fn open_song(song: &Song, source: Option<&SongSource>) -> Result<OpenedSong, String> {
+    let mut hint = Hint::new();
+    hint.with_extension(&song.format.to_string());
+    let mut titles = None;
+    let (song_src, start_ms, end_ms): (Box<dyn MediaSource>, _, _) = match source {
+        Some(SongSource::StreamUrl(url)) => {
+            let opened = open_stream(url)?;
+            hint.mime_type(&opened.content_type);
+            titles = Some(opened.titles);
+            (Box::new(opened.stream), 0, None)
+        }
+        Some(SongSource::FilePath(path)) => (open_file(path, &mut hint)?, 0, None),
+        Some(SongSource::FileRange(path, start_ms, end_ms)) => {
+            (open_file(path, &mut hint)?, *start_ms, *end_ms)
+        }
+        _ => {
+            info!("song has no file or stream to play ({:?})", song);
+            return Err("the song has no file or stream to play".to_string());
+        }
+    };
+
+    // Create the media source stream.
+    let mss = MediaSourceStream::new(song_src, Default::default());
+
+    // Use the default options for metadata and format readers.
+    let meta_opts: MetadataOptions = Default::default();
+    let fmt_opts = FormatOptions {
+        enable_gapless: true,
+        ..Default::default()
+    };
+
+    // Probe the media source.
+    let probed = symphonia::default::get_probe()
+        .format(&hint, mss, &fmt_opts, &meta_opts)
+        .map_err(|e| format!("unsupported format: {e}"))?;
+
+    // Get the instantiated format reader.
+    let format = probed.format; // TODO ? check formats match
+
+    let OpenedTrack {
+        decoder,
+        track_id,
+        time_base,
+        duration_ms,
+    } = open_track(format.as_ref())?;
+
+    let mut opened = OpenedSong {
+        song: song.clone(),
+        format,
+        decoder,
+        track_id,
+        time_base,
+        duration_ms: end_ms
+            .or(duration_ms)
+            .map(|end_ms| end_ms.saturating_sub(start_ms)),
+        titles,
+        start_ms,
+        end_ts: end_ms.map(|end_ms| time_base.calc_timestamp(ms_to_time(end_ms))),
+        first_ts: 0,
+    };
+    if start_ms > 0 {
+        // accurately, so that the songs of a file follow each other without gaps or overlaps
+        opened.first_ts = seek_song(&mut opened, 0, SeekMode::Accurate);
+    }
+    Ok(opened)
+}
+
+/// Open the file, hinting at its format by its extension (e.g. for downloaded songs,
+/// whose format isn't known before)
+fn open_file(path: &Path, hint: &mut Hint) -> Result<Box<dyn MediaSource>, String> {
+    if let Some(extension) = path.extension().and_then(|extension| extension.to_str()) {
+        hint.with_extension(extension);
+    }
+    let file = File::open(path).map_err(|e| format!("failed to open {path:?}: {e}"))?;
+    info!("trying to play file {:?}", file);
+    Ok(Box::new(file))
+}
+
+/// Duration of the song as found in its file's container, when the tags can't be trusted
+pub fn probe_duration(song: &Song) -> Option<time::Duration> {
+    open_song(song, song.source.as_ref())
+        .ok()
+        .and_then(|opened| opened.duration_ms)
+        .map(time::Duration::from_millis)
+}
 
-            // Consume any new metadata that has been read since the last packet.
-            while !format.metadata().is_latest() {
-                // Pop the old head of the metadata queue.
-                format.metadata().pop();
+/// A decoder for a track of a container, with what's needed to follow the track's packets
+struct OpenedTrack {
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    time_base: TimeBase,
+    duration_ms: Option<u64>,
+}
+
+/// Create a decoder for the first audio track of the container
+fn open_track(format: &dyn FormatReader) -> Result<OpenedTrack, String> {
+    // Find the first audio track with a known (decodeable) codec.
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or("no supported audio tracks")?;
+    // Use the default options for the decoder.
+    let dec_opts: DecoderOptions = Default::default();
+
+    // Create a decoder for the track.
+    let decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &dec_opts)
+        .map_err(|e| format!("unsupported codec: {e}"))?;
+
+    // Get the selected track's timebase and duration.
+    let time_base = track
+        .codec_params
+        .time_base
+        .ok_or("the audio track has no time base")?;
+    let duration_ms = track
+        .codec_params
+        .n_frames
+        .map(|frames| time_to_ms(time_base.calc_time(track.codec_params.start_ts + frames)));
+
+    Ok(OpenedTrack {
+        decoder,
+        track_id: track.id,
+        time_base,
+        duration_ms,
+    })
+}
+
+/// The track list changed (e.g. a new logical stream in a chained Ogg file):
+/// decode the new first audio track from now on
+fn reset_track(opened: &mut OpenedSong) -> Result<(), String> {
+    let OpenedTrack {
+        decoder,
+        track_id,
+        time_base,
+        duration_ms,
+    } = open_track(opened.format.as_ref())?;
+    info!("track list changed, now decoding track {track_id}");
+    opened.decoder = decoder;
+    opened.track_id = track_id;
+    opened.time_base = time_base;
+    opened.duration_ms = duration_ms;
+    Ok(())
+}
 
-                // Consume the new metadata at the head of the metadata queue.
+/// Seek the song using the container's index, so that nothing before the seek point
+/// has to be decoded. Returns the timestamp from which decoded packets should be played
+fn seek_song(opened: &mut OpenedSong, seek_ms: u64, mode: SeekMode) -> u64 {
+    let time = ms_to_time(opened.start_ms + seek_ms);
+    let seek_to = SeekTo::Time {
+        time,
+        track_id: Some(opened.track_id),
+    };
+    match opened.format.seek(mode, seek_to) {
+        Ok(seeked_to) => {
+            // the decoder state is invalid after a seek
+            opened.decoder.reset();
+            debug!("seeked to {seeked_to:?}");
+            match mode {
+                SeekMode::Accurate => seeked_to.required_ts,
+                SeekMode::Coarse => seeked_to.actual_ts,
             }
+        }
+        Err(e) => {
+            // not seekable: decode from where we are and discard until the seek point
+            warn!("failed to seek to {seek_ms} ms ({e}), decoding up to it instead");
+            opened.time_base.calc_timestamp(time)
+        }
+    }
+}
 
-            // If the packet does not belong to the selected track, skip over it.
-            if packet.track_id() != track_id {
+fn decode(
+    player: &mut Player,
+    rx: &mpsc::Receiver<AudioCommand>,
+    tx: &Sender<AudioEvent>,
+) -> Option<AudioCommand> {
+    let opened = (player.current_song.as_ref()).map(|song| open_song(song, player.source.as_ref()));
+    let mut opened = match opened {
+        Some(Ok(opened)) => opened,
+        Some(Err(e)) => return Some(DoneErr(e)),
+        None => return Some(DoneErr("no song to play".to_string())),
+    };
+    player.duration_ms = opened.duration_ms;
+    // what the DSP still holds is from another song or position
+    player.dsp.reset();
+    // packets before this timestamp are decoded but not played
+    let mut play_from_ts = opened.first_ts;
+    if player.seek_ms > 0 {
+        play_from_ts = seek_song(&mut opened, player.seek_ms, player.seek_mode);
+    }
+    // position of the current logical stream within the song, for chained streams
+    let mut stream_offset_ms = 0;
+
+    let no_progress = false;
+
+    // The decode loop.
+    let result: Result<(), Error> = loop {
+        // Get the next packet from the media format.
+        let packet = match opened.format.next_packet() {
+            // the song stops before its file: as if the file ended there
+            Ok(packet) if opened.is_past_end(&packet) => {
+                Err(Error::IoError(std::io::ErrorKind::UnexpectedEof.into()))
+            }
+            packet => packet,
+        };
+        let packet = match packet {
+            Ok(packet) => packet,
+            Err(Error::ResetRequired) => {
+                // The track list has been changed. Re-examine it and create a new decoder,
+                // then restart the decode loop. As of v0.5.0, the only usage of this is
+                // for chained OGG physical streams (e.g. web radios, concatenated files).
+                if let Err(e) = reset_track(&mut opened) {
+                    return Some(DoneErr(e));
+                }
+                // timestamps start over with the new stream
+                stream_offset_ms = player.seek_ms;
+                play_from_ts = 0;
+                player.duration_ms = opened.duration_ms.map(|d| stream_offset_ms + d);
                 continue;
             }
-
-            // Decode the packet into audio samples.
-            match decoder.decode(&packet) {
-                Ok(decoded) => {
-                    // If the audio output is not open, try to open it.
-                    if audio_output.is_none() {
-                        // Get the audio buffer specification. This is a description of the decoded
-                        // audio buffer's sample format and sample rate.
-                        let spec = *decoded.spec();
-
-                        // Get the capacity of the decoded buffer. Note that this is capacity, not
-                        // length! The capacity of the decoded buffer is constant for the life of the
-                        // decoder, but the length is not.
-                        let duration = decoded.capacity() as u64;
-
-                        // Try to open the audio output.
-                        audio_output.replace(output::try_open(spec, duration).unwrap());
-                    } else {
-                        // TODO: Check the audio spec. and duration hasn't changed.
+            Err(Error::IoError(io_error))
+                if io_error.kind() == std::io::ErrorKind::UnexpectedEof =>
+            {
+                // Finished reading the song: chain the next one if it's ready,
+                // keeping the audio output open
+                match player.preloaded.take() {
+                    Some(next) => {
+                        info!("gapless transition to {:?}", next.song.title);
+                        player.current_song = Some(next.song.clone());
+                        player.source = next.song.source.clone();
+                        player.seek_ms = 0;
+                        player.duration_ms = next.duration_ms;
+                        player.ab_loop = None;
+                        play_from_ts = next.first_ts;
+                        opened = next;
+                        stream_offset_ms = 0;
+                        let _ = tx.send(AudioEvent::NextStarted);
+                        continue;
                     }
-                    // Write the decoded audio samples to the audio output if the presentation timestamp
-                    // for the packet is >= the seeked position (0 if not seeking).
-                    if packet.ts() * 1000 * (tb.numer as u64) / (tb.denom as u64) >= *seek {
-                        if !no_progress {
-                            // print_progress(packet.ts(), dur, tb);
-                        }
+                    None => break Err(Error::IoError(io_error)),
+                }
+            }
 
-                        if let Some(ref mut audio_output) = audio_output {
-                            audio_output.write(decoded).unwrap()
-                        }
+            Err(err) => {
+                // A unrecoverable error occured, halt decoding.
+                break Err(err);
+            }
+        };
+
+        // Consume any new metadata that has been read since the last packet.
+        while !opened.format.metadata().is_latest() {
+            // Pop the old head of the metadata queue.
+            opened.format.metadata().pop();
+
+            // Consume the new metadata at the head of the metadata queue.
+        }
+
+        // If the packet does not belong to the selected track, skip over it.
+        if packet.track_id() != opened.track_id {
+            continue;
+        }
 
-                        // update seek time
-                        *seek = packet.ts() * 1000 * (tb.numer as u64) / (tb.denom as u64);
+        // Decode the packet into audio samples.
+        match opened.decoder.decode(&packet) {
+            Ok(decoded) => {
+                // Write the decoded audio samples to the audio output if the presentation timestamp
+                // for the packet is >= the seeked position (0 if not seeking).
+                if packet.ts() >= play_from_ts {
+                    if !no_progress {
+                        // print_progress(packet.ts(), dur, tb);
                     }
 
-                    // check for any command
-                    match rx.try_recv() {
-                        Ok(Play) => (),
-                        Ok(GetSeek) => {
-                            tx.send(AudioEvent::SeekIs(convert_seek_ms_to_real(
-                                *seek,
-                                Some(song.clone()),
-                            )))
-                            .unwrap();
-                            ()
+                    // The output is re-used from the previous song if the spec matches.
+                    // The spec can also change within a song, when the track list changes.
+                    if let Err(e) = player.write(decoded) {
+                        return Some(DoneErr(e));
+                    }
+                    if let Some(levels) = player.meter.as_mut().and_then(Meter::take_levels) {
+                        let _ = tx.send(AudioEvent::Levels(levels));
+                    }
+                    if let Some(title) = opened.titles.as_ref().and_then(|t| t.try_recv().ok()) {
+                        let _ = tx.send(AudioEvent::StreamTitle(title));
+                    }
+
+                    // update seek time
+                    player.seek_ms = stream_offset_ms + opened.position_ms(packet.ts());
+
+                    // back to the start of the loop once its end is played,
+                    // only within the current stream of chained streams
+                    if let Some((a, b)) = player.ab_loop {
+                        let packet_end_ms =
+                            stream_offset_ms + opened.position_ms(packet.ts() + packet.dur());
+                        if packet_end_ms >= b && a >= stream_offset_ms {
+                            play_from_ts =
+                                seek_song(&mut opened, a - stream_offset_ms, player.seek_mode);
+                            player.seek_ms = a;
                         }
-                        Ok(cmd) => {
-                            debug!("inner loop received {cmd:?}");
-                            return Some(cmd.clone());
-                        } // TODO exhaust the enum manually to avoid alloc in audio thread
-                        Err(_) => (), // most likely no new cmd
                     }
                 }
-                Err(Error::IoError(_)) => {
-                    // The packet failed to decode due to an IO error, skip the packet.
-                    continue;
-                }
-                Err(Error::DecodeError(_)) => {
-                    // The packet failed to decode due to invalid data, skip the packet.
-                    continue;
-                }
-                Err(err) => {
-                    // An unrecoverable error occured, halt decoding.
-                    break Err(err);
+
+                // open the next song while this one is playing
+                player.preload_next();
+
+                // check for any command
+                match rx.try_recv() {
+                    Ok(Play) => (),
+                    Ok(SetNext(next_song)) => player.set_next(next_song),
+                    Ok(SetEqualizer(equalizer)) => player.dsp.set_equalizer(equalizer),
+                    Ok(SetSpeed(speed)) => player.dsp.set_speed(speed),
+                    Ok(SetVolume(volume, ramp_ms)) => player.dsp.set_volume(volume, ramp_ms),
+                    Ok(SetLoop(ab_loop)) => player.ab_loop = ab_loop,
+                    Ok(cmd @ (Seek(_) | SeekTo(_) | SeekBy(_))) => {
+                        // only within the current stream of chained streams
+                        let seek_ms = std::cmp::max(player.seek_target_ms(&cmd), stream_offset_ms);
+                        debug!("seeking audio thread to : {seek_ms} ms");
+                        play_from_ts =
+                            seek_song(&mut opened, seek_ms - stream_offset_ms, player.seek_mode);
+                        player.seek_ms = seek_ms;
+                        player.dsp.reset();
+                    }
+                    Ok(GetSeek) => {
+                        let _ = tx.send(player.seek_event());
+                    }
+                    Ok(cmd) => {
+                        debug!("inner loop received {cmd:?}");
+                        if let Some(song) = &player.current_song {
+                            let _ = tx.send(AudioEvent::Interrupted(
+                                song.clone(),
+                                player.seek_ms,
+                                player.duration_ms,
+                            ));
+                        }
+                        return Some(cmd.clone());
+                    } // TODO exhaust the enum manually to avoid alloc in audio thread
+                    Err(_) => (), // no new cmd
                 }
             }
-        }; // EOL
-        info!("result playing track: {:?}", result);
-        *seek = 0; // reset seek time
-
-        match result {
-            Err(Error::IoError(io_error)) => match io_error.kind() {
-                // finished reading & playing the song
-                std::io::ErrorKind::UnexpectedEof => return Some(DoneOk),
-                _ => return Some(DoneErr),
-            },
-            Err(_) => return Some(DoneErr),
-            Ok(_) => panic!(), // should be unreachable
+            Err(Error::IoError(_)) => {
+                // The packet failed to decode due to an IO error, skip the packet.
+                continue;
+            }
+            Err(Error::DecodeError(_)) => {
+                // The packet failed to decode due to invalid data, skip the packet.
+                continue;
+            }
+            Err(err) => {
+                // An unrecoverable error occured, halt decoding.
+                break Err(err);
+            }
         }
-    } else {
-        info!("song does not have local filepath ({:?})", song);
-
-        return Some(DoneErr);
+    }; // EOL
+    info!("result playing track: {:?}", result);
+    player.seek_ms = 0; // reset seek time
+
+    match result {
+        Err(Error::IoError(io_error)) => match io_error.kind() {
+            // finished reading & playing the song, let the last samples play out
+            std::io::ErrorKind::UnexpectedEof => {
+                player.flush_output();
+                Some(DoneOk)
+            }
+            _ => Some(DoneErr(format!("failed to read the song: {io_error}"))),
+        },
+        Err(e) => Some(DoneErr(format!("failed to decode the song: {e}"))),
+        Ok(()) => Some(DoneOk),
     }
 }
 
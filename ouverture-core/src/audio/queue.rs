@@ -0,0 +1,487 @@
+//! Play queue: the songs played so far, the current one and the ones coming next,
+//! in the order given by the shuffle and repeat modes
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use strum_macros::{Display, EnumString};
+
+use crate::music::song::Song;
+
+#[derive(
+    Clone, Copy, Debug, Default, Display, EnumString, Serialize, Deserialize, PartialEq, Eq,
+)]
+#[strum(serialize_all = "lowercase")]
+pub enum ShuffleMode {
+    /// Songs play in the order they were enqueued
+    #[default]
+    Off,
+    /// Any order
+    Random,
+    /// Random, but songs of the same artist are spread out over the queue
+    Smart,
+}
+
+#[derive(
+    Clone, Copy, Debug, Default, Display, EnumString, Serialize, Deserialize, PartialEq, Eq,
+)]
+#[strum(serialize_all = "lowercase")]
+pub enum RepeatMode {
+    #[default]
+    Off,
+    /// Play the current song again and again
+    One,
+    /// Start the queue over once it is done
+    All,
+}
+
+/// The whole queue, as shown to the clients
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct QueueListing {
+    pub past: Vec<Song>, // the last one played most recently
+    pub current: Option<Song>,
+    pub future: Vec<Song>, // in the order they will play, positions in the queue index this
+}
+
+/// A song in the queue, with the rank it was enqueued at, to undo a shuffle
+#[derive(Clone, Debug)]
+struct Entry {
+    rank: u64,
+    song: Song,
+}
+
+#[derive(Clone, Debug, Default)]
+pub(super) struct Queue {
+    past: VecDeque<Entry>,   // played songs, the last one most recently
+    current: Option<Entry>,  // playing or paused
+    future: VecDeque<Entry>, // in the order they will play
+    next_rank: u64,
+    cycle_start: usize, // index in past of the first song of the cycle repeated by RepeatMode::All
+    pub shuffle: ShuffleMode,
+    pub repeat: RepeatMode,
+}
+
+impl Queue {
+    pub fn current(&self) -> Option<&Song> {
+        self.current.as_ref().map(|entry| &entry.song)
+    }
+
+    /// Play this song now, out of the queue
+    pub fn set_current(&mut self, song: Song) {
+        let entry = self.entry(song);
+        self.current = Some(entry);
+    }
+
+    pub fn enqueue(&mut self, song: Song) {
+        let entry = self.entry(song);
+        match self.shuffle {
+            ShuffleMode::Off => self.future.push_back(entry),
+            // a smart spot would mean reshuffling what's coming: a random one will do
+            _ => {
+                let index = rand::thread_rng().gen_range(0..=self.future.len());
+                self.future.insert(index, entry);
+            }
+        }
+    }
+
+    /// Insert a song at a position among the upcoming ones, 0 being the next song
+    pub fn insert(&mut self, position: usize, song: Song) {
+        let entry = self.entry(song);
+        self.future.insert(position.min(self.future.len()), entry);
+        self.rerank();
+    }
+
+    pub fn enqueue_many(&mut self, songs: Vec<Song>) {
+        for song in songs {
+            self.enqueue(song);
+        }
+    }
+
+    /// Move an upcoming song to another position, returns false if there's none to move
+    pub fn move_song(&mut self, from: usize, to: usize) -> bool {
+        match self.future.remove(from) {
+            Some(entry) => {
+                self.future.insert(to.min(self.future.len()), entry);
+                self.rerank();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Take an upcoming song out of the queue
+    pub fn remove(&mut self, position: usize) -> Option<Song> {
+        self.future.remove(position).map(|entry| entry.song)
+    }
+
+    /// Empty the queue, but for the current song
+    pub fn clear(&mut self) {
+        self.past.clear();
+        self.future.clear();
+        self.cycle_start = 0;
+    }
+
+    /// Skip to an upcoming song, the ones before it going to the past as if played, returns it
+    pub fn jump_to(&mut self, position: usize) -> Option<&Song> {
+        if position >= self.future.len() {
+            return None;
+        }
+        for _ in 0..position {
+            if let Some(entry) = self.current.replace(self.future.pop_front()?) {
+                self.past.push_back(entry);
+            }
+        }
+        self.next()
+    }
+
+    pub fn listing(&self) -> QueueListing {
+        QueueListing {
+            past: self.past.iter().map(|entry| entry.song.clone()).collect(),
+            current: self.current().cloned(),
+            future: self.future.iter().map(|entry| entry.song.clone()).collect(),
+        }
+    }
+
+    /// Without shuffle, the positions chosen by the user are the enqueue order to go back to
+    /// once a shuffle is undone. While shuffling, new songs go at the end of that order
+    fn rerank(&mut self) {
+        if self.shuffle != ShuffleMode::Off {
+            return;
+        }
+        let entries = self
+            .past
+            .iter_mut()
+            .chain(self.current.iter_mut())
+            .chain(self.future.iter_mut());
+        for (rank, entry) in entries.enumerate() {
+            entry.rank = rank as u64;
+        }
+        self.next_rank = (self.past.len() + self.future.len() + 1) as u64;
+    }
+
+    fn entry(&mut self, song: Song) -> Entry {
+        self.next_rank += 1;
+        Entry {
+            rank: self.next_rank,
+            song,
+        }
+    }
+
+    /// The song to play once the current one is done
+    pub fn upcoming(&self) -> Option<&Song> {
+        if self.repeat == RepeatMode::One && self.current.is_some() {
+            return self.current();
+        }
+        match self.future.front() {
+            Some(entry) => Some(&entry.song),
+            // the cycle starts over in the enqueue order, unless shuffled again
+            None if self.repeat == RepeatMode::All && self.shuffle == ShuffleMode::Off => {
+                let cycle = self.cycle();
+                let current_rank = self.current.as_ref().map(|entry| entry.rank);
+                // as start_cycle_over orders it
+                cycle
+                    .iter()
+                    .find(|entry| cycle.len() == 1 || Some(entry.rank) != current_rank)
+                    .map(|entry| &entry.song)
+            }
+            None => None,
+        }
+    }
+
+    /// The current song is done playing on its own: go on with the upcoming one
+    pub fn finished(&mut self) -> Option<&Song> {
+        if self.repeat == RepeatMode::One && self.current.is_some() {
+            return self.current();
+        }
+        self.next()
+    }
+
+    /// Skip to the next song in the queue, returns it
+    pub fn next(&mut self) -> Option<&Song> {
+        if self.future.is_empty() && self.repeat == RepeatMode::All {
+            self.start_cycle_over();
+        }
+        if let Some(entry) = self.current.take() {
+            self.past.push_back(entry);
+        }
+        self.current = self.future.pop_front();
+        self.current()
+    }
+
+    /// Forget the current song, e.g. because it can't be played
+    pub fn drop_current(&mut self) {
+        self.current = None;
+    }
+
+    /// Go back to the previously played song, returns it
+    pub fn previous(&mut self) -> Option<&Song> {
+        let entry = self.past.pop_back()?;
+        if let Some(current) = self.current.replace(entry) {
+            self.future.push_front(current);
+        }
+        self.cycle_start = self.cycle_start.min(self.past.len());
+        self.current()
+    }
+
+    /// The songs played since the cycle started, the current one included, by rank
+    fn cycle(&self) -> Vec<&Entry> {
+        let mut cycle: Vec<&Entry> = self
+            .past
+            .range(self.cycle_start..)
+            .chain(self.current.iter())
+            .collect();
+        cycle.sort_by_key(|entry| entry.rank);
+        cycle
+    }
+
+    /// Enqueue again all the songs played since the cycle started, the current one included
+    fn start_cycle_over(&mut self) {
+        self.future = self.cycle().into_iter().cloned().collect();
+        self.cycle_start = self.past.len() + self.current.is_some() as usize;
+        self.reorder();
+
+        // a reshuffled cycle shouldn't start with the song that just played
+        let current_rank = self.current.as_ref().map(|entry| entry.rank);
+        if self.future.len() > 1 && self.future.front().map(|entry| entry.rank) == current_rank {
+            self.future.rotate_left(1);
+        }
+    }
+
+    pub fn set_shuffle(&mut self, shuffle: ShuffleMode) {
+        if shuffle != self.shuffle {
+            self.shuffle = shuffle;
+            self.reorder();
+        }
+    }
+
+    /// Order the songs to come according to the shuffle mode. What was played
+    /// stays as-is, so that previous goes back through the actual history
+    fn reorder(&mut self) {
+        let mut future: Vec<Entry> = self.future.drain(..).collect();
+        let mut rng = rand::thread_rng();
+        match self.shuffle {
+            ShuffleMode::Off => future.sort_by_key(|entry| entry.rank),
+            ShuffleMode::Random => future.shuffle(&mut rng),
+            ShuffleMode::Smart => future = spread_artists(future, &mut rng),
+        }
+        self.future = future.into();
+    }
+}
+
+/// Shuffle, but place the songs of each artist at about even intervals over the queue,
+/// each artist starting at a random offset
+fn spread_artists(entries: Vec<Entry>, rng: &mut impl Rng) -> Vec<Entry> {
+    let mut by_artist: BTreeMap<Option<String>, Vec<Entry>> = BTreeMap::new();
+    for entry in entries {
+        by_artist
+            .entry(entry.song.artist.clone())
+            .or_default()
+            .push(entry);
+    }
+
+    let mut placed: Vec<(f64, Entry)> = vec![];
+    for (_, mut songs) in by_artist {
+        songs.shuffle(rng);
+        let interval = 1.0 / songs.len() as f64;
+        let offset = rng.gen_range(0.0..interval);
+        for (i, entry) in songs.into_iter().enumerate() {
+            // a bit of jitter, so that artists with as many songs don't always alternate
+            let jitter = rng.gen_range(-0.1..0.1) * interval;
+            placed.push((offset + i as f64 * interval + jitter, entry));
+        }
+    }
+    placed.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+    placed.into_iter().map(|(_, entry)| entry).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song(title: &str) -> Song {
+        Song {
+            title: Some(title.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn queue(titles: &[&str]) -> Queue {
+        let mut queue = Queue::default();
+        queue.enqueue_many(titles.iter().map(|title| song(title)).collect());
+        queue
+    }
+
+    fn title(song: Option<&Song>) -> Option<&str> {
+        song.and_then(|song| song.title.as_deref())
+    }
+
+    fn future(queue: &Queue) -> Vec<String> {
+        let listing = queue.listing();
+        listing
+            .future
+            .into_iter()
+            .filter_map(|song| song.title)
+            .collect()
+    }
+
+    #[test]
+    fn plays_in_enqueue_order() {
+        let mut queue = queue(&["a", "b", "c"]);
+        assert_eq!(title(queue.upcoming()), Some("a"));
+        assert_eq!(title(queue.next()), Some("a"));
+        assert_eq!(title(queue.upcoming()), Some("b"));
+        assert_eq!(title(queue.finished()), Some("b"));
+        assert_eq!(title(queue.next()), Some("c"));
+        assert!(queue.upcoming().is_none());
+        assert_eq!(title(queue.next()), None);
+    }
+
+    #[test]
+    fn insert_and_move() {
+        let mut queue = queue(&["a", "b", "c"]);
+        queue.insert(0, song("first"));
+        queue.insert(10, song("last"));
+        assert_eq!(future(&queue), ["first", "a", "b", "c", "last"]);
+
+        assert!(queue.move_song(3, 0));
+        assert!(queue.move_song(0, 10));
+        assert!(!queue.move_song(5, 0));
+        assert_eq!(future(&queue), ["first", "a", "b", "last", "c"]);
+
+        assert_eq!(title(queue.remove(1).as_ref()), Some("a"));
+        assert!(queue.remove(4).is_none());
+        assert_eq!(future(&queue), ["first", "b", "last", "c"]);
+    }
+
+    #[test]
+    fn previous_goes_back_through_history() {
+        let mut queue = queue(&["a", "b", "c"]);
+        queue.next();
+        queue.next();
+        assert_eq!(title(queue.previous()), Some("a"));
+        assert_eq!(future(&queue), ["b", "c"]);
+        assert!(queue.previous().is_none());
+        assert_eq!(title(queue.next()), Some("b"));
+    }
+
+    #[test]
+    fn jump_to_skips_songs() {
+        let mut queue = queue(&["a", "b", "c", "d"]);
+        queue.next();
+        assert_eq!(title(queue.jump_to(1)), Some("c"));
+        assert_eq!(queue.listing().past.len(), 2);
+        assert_eq!(future(&queue), ["d"]);
+        assert!(queue.jump_to(1).is_none());
+    }
+
+    #[test]
+    fn repeat_one_plays_the_current_song_again() {
+        let mut queue = queue(&["a", "b"]);
+        queue.next();
+        queue.repeat = RepeatMode::One;
+        assert_eq!(title(queue.upcoming()), Some("a"));
+        assert_eq!(title(queue.finished()), Some("a"));
+        // skipping goes on with the queue
+        assert_eq!(title(queue.next()), Some("b"));
+    }
+
+    #[test]
+    fn repeat_all_starts_over() {
+        let mut queue = queue(&["a", "b", "c"]);
+        queue.repeat = RepeatMode::All;
+        for _ in 0..3 {
+            queue.next();
+        }
+        assert_eq!(title(queue.upcoming()), Some("a"));
+        assert_eq!(title(queue.next()), Some("a"));
+        assert_eq!(future(&queue), ["b", "c"]);
+    }
+
+    #[test]
+    fn repeat_all_upcoming_is_the_song_played_next() {
+        // played in a shuffled order, the cycle starts over in the enqueue order
+        for _ in 0..20 {
+            let mut queue = queue(&["a", "b", "c"]);
+            queue.set_shuffle(ShuffleMode::Random);
+            for _ in 0..3 {
+                queue.next();
+            }
+            queue.set_shuffle(ShuffleMode::Off);
+            queue.repeat = RepeatMode::All;
+            let current = title(queue.current()).map(str::to_string);
+
+            let upcoming = title(queue.upcoming()).map(str::to_string);
+            let expected = if current.as_deref() == Some("a") {
+                "b"
+            } else {
+                "a"
+            };
+            assert_eq!(upcoming.as_deref(), Some(expected));
+            assert_eq!(title(queue.next()), upcoming.as_deref());
+        }
+    }
+
+    #[test]
+    fn repeat_all_of_a_single_song() {
+        let mut queue = queue(&["a"]);
+        queue.repeat = RepeatMode::All;
+        queue.next();
+        assert_eq!(title(queue.upcoming()), Some("a"));
+        assert_eq!(title(queue.next()), Some("a"));
+    }
+
+    #[test]
+    fn shuffle_keeps_the_songs_and_off_restores_the_order() {
+        let titles = ["a", "b", "c", "d", "e", "f", "g", "h"];
+        let mut queue = queue(&titles);
+        queue.next();
+        queue.move_song(0, 10); // "b" last
+        for shuffle in [ShuffleMode::Random, ShuffleMode::Smart] {
+            queue.set_shuffle(shuffle);
+            let mut shuffled = future(&queue);
+            shuffled.sort();
+            assert_eq!(shuffled, &titles[1..]);
+        }
+        queue.set_shuffle(ShuffleMode::Off);
+        assert_eq!(future(&queue), ["c", "d", "e", "f", "g", "h", "b"]);
+    }
+
+    #[test]
+    fn smart_shuffle_spreads_artists() {
+        let mut queue = Queue::default();
+        for artist in ["x", "y"] {
+            for i in 0..10 {
+                queue.enqueue(Song {
+                    artist: Some(artist.to_string()),
+                    title: Some(format!("{artist}{i}")),
+                    ..Default::default()
+                });
+            }
+        }
+        queue.set_shuffle(ShuffleMode::Smart);
+        let artists: Vec<_> = queue
+            .listing()
+            .future
+            .into_iter()
+            .map(|s| s.artist)
+            .collect();
+        // the first half isn't all of one artist, as it would be in enqueue order
+        let x = artists[..10]
+            .iter()
+            .filter(|a| a.as_deref() == Some("x"))
+            .count();
+        assert!((3..=7).contains(&x), "{artists:?}");
+    }
+
+    #[test]
+    fn clear_keeps_the_current_song() {
+        let mut queue = queue(&["a", "b", "c"]);
+        queue.next();
+        queue.next();
+        queue.clear();
+        assert_eq!(title(queue.current()), Some("b"));
+        assert!(queue.listing().past.is_empty());
+        assert!(queue.upcoming().is_none());
+    }
+}
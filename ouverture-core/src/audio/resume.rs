@@ -0,0 +1,145 @@
+//! Resume positions for long-form songs such as podcasts and audiobooks.
+//!
+//! A song is resumable if it is at least `resume_min_duration` long, or of one of
+//! the `resume_genres`. Its position is saved when playback of it is interrupted
+//! (pause, stop, skip, another song played) and when the server exits, and it is
+//! played from there the next time. The position is forgotten when:
+//! - the song is played to its end,
+//! - it is left in its last `RESUME_END_MARGIN_MS`, as if it was done,
+//! - it is left in its first `RESUME_MIN_POSITION_MS`, seeking to the start being
+//!   the way to start over.
+//!
+//! Podcast episodes done in either of the first two ways are marked played,
+//! whether they are resumable or not.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use log::warn;
+use tokio::task::spawn;
+
+use super::AudioState;
+use crate::config::Config;
+use crate::database::{forget_position, list_positions, save_position};
+use crate::music::song::Song;
+use crate::podcast::episode_done;
+
+// left this close to the end, the song counts as done
+const RESUME_END_MARGIN_MS: u64 = 30_000;
+// left this close to the start, the song starts over next time
+const RESUME_MIN_POSITION_MS: u64 = 10_000;
+
+#[derive(Clone)]
+pub(super) struct ResumePositions {
+    config: Config,
+    positions: HashMap<String, u64>, // by song source, in milliseconds
+}
+
+impl ResumePositions {
+    pub fn new(config: &Config) -> Self {
+        ResumePositions {
+            config: config.clone(),
+            positions: HashMap::new(),
+        }
+    }
+
+    fn is_resumable(&self, song: &Song) -> bool {
+        let long = matches!(self.config.resume_min_duration,
+            Some(min) if !song.duration.is_zero() && song.duration >= min);
+        let genre = song.genre.as_ref().is_some_and(|genre| {
+            self.config
+                .resume_genres
+                .iter()
+                .any(|g| g.eq_ignore_ascii_case(genre))
+        });
+        long || genre
+    }
+
+    /// Where to start playing the song from, if not its start
+    pub fn position(&self, song: &Song) -> Option<u64> {
+        if !self.is_resumable(song) {
+            return None;
+        }
+        self.positions.get(&source_key(song)?).copied()
+    }
+
+    /// Playback of the song was interrupted at a position, in a song of that
+    /// duration if known
+    pub fn left_at(&mut self, song: &Song, position_ms: u64, duration_ms: Option<u64>) {
+        if let Some(write) = self.leave(song, position_ms, duration_ms) {
+            spawn(write);
+        }
+    }
+
+    /// As `left_at`, returning the database write to await instead of running it aside
+    pub fn leave(
+        &mut self,
+        song: &Song,
+        position_ms: u64,
+        duration_ms: Option<u64>,
+    ) -> Option<impl Future<Output = ()> + Send + 'static> {
+        let duration_ms = duration_ms.unwrap_or(song.duration.as_millis() as u64);
+        let near_end = duration_ms > 0 && position_ms + RESUME_END_MARGIN_MS >= duration_ms;
+        if near_end {
+            episode_done(&self.config, song);
+            let key = self.forget(song)?;
+            return Some(write_position(self.config.clone(), key, None));
+        }
+        if !self.is_resumable(song) {
+            return None;
+        }
+        if position_ms < RESUME_MIN_POSITION_MS {
+            let key = self.forget(song)?;
+            return Some(write_position(self.config.clone(), key, None));
+        }
+        let key = source_key(song)?;
+        self.positions.insert(key.clone(), position_ms);
+        Some(write_position(self.config.clone(), key, Some(position_ms)))
+    }
+
+    /// The song was played to its end, it starts over next time,
+    /// and it's played if it's an episode
+    pub fn done(&mut self, song: &Song) {
+        if let Some(key) = self.forget(song) {
+            spawn(write_position(self.config.clone(), key, None));
+        }
+        episode_done(&self.config, song);
+    }
+
+    /// Forget the position of the song, returning its key if there was one to forget
+    fn forget(&mut self, song: &Song) -> Option<String> {
+        let key = source_key(song)?;
+        self.positions.remove(&key)?;
+        Some(key)
+    }
+}
+
+/// Save the position of a song in the database, or forget it
+async fn write_position(config: Config, key: String, position_ms: Option<u64>) {
+    let res = match position_ms {
+        Some(position_ms) => save_position(&config, key, position_ms).await,
+        None => forget_position(&config, key).await,
+    };
+    if let Err(e) = res {
+        warn!("failed to update the resume position: {e}");
+    }
+}
+
+fn source_key(song: &Song) -> Option<String> {
+    song.source.clone().map(|source| source.into())
+}
+
+/// Get the saved positions from the database
+pub(super) async fn load_positions(config: Config, audio_state: Arc<Mutex<AudioState>>) {
+    match list_positions(&config).await {
+        Ok(positions) => {
+            let mut state = audio_state.lock().unwrap();
+            // the ones saved in the meantime are more recent
+            for (source, position) in positions {
+                state.resume.positions.entry(source).or_insert(position);
+            }
+        }
+        Err(e) => warn!("failed to get the resume positions from the database: {e}"),
+    }
+}
@@ -0,0 +1,355 @@
+//! HTTP(S) audio streams, such as internet radios
+//!
+//! A thread reads the stream ahead of the decoder into a bounded buffer, which absorbs the
+//! network's jitter, and connects again when the connection is lost. The ICY metadata that
+//! Shoutcast and Icecast servers interleave with the audio is taken out of it, and the
+//! titles it holds are passed on once the decoder reaches them.
+
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::mpsc::{channel, sync_channel, Receiver, RecvTimeoutError, Sender, SyncSender};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use log::{debug, info, warn};
+use symphonia::core::io::MediaSource;
+
+// audio read ahead of the decoder, in chunks of up to CHUNK_BYTES
+const BUFFER_CHUNKS: usize = 64;
+const CHUNK_BYTES: usize = 16 * 1024;
+const CONNECT_TIMEOUT_MS: u64 = 5_000;
+const READ_TIMEOUT_MS: u64 = 5_000;
+// in a row, before giving up on the stream
+const RECONNECT_ATTEMPTS: u32 = 5;
+const RECONNECT_DELAY_MS: u64 = 1_000;
+// with nothing to decode, the song fails after that long
+const STALL_TIMEOUT_MS: u64 = 30_000;
+
+/// What's read ahead, in order
+enum Chunk {
+    Audio(Vec<u8>),
+    Title(String), // what's playing from there on
+}
+
+/// A stream being read, as a source for the decoder
+pub(super) struct HttpStream {
+    chunks: Mutex<Receiver<Chunk>>, // a media source must be Sync
+    chunk: Vec<u8>,
+    read: usize, // in chunk
+    titles: Sender<String>,
+}
+
+/// A stream just connected to, with what the server told about it
+pub(super) struct OpenedStream {
+    pub stream: HttpStream,
+    pub content_type: String,
+    pub titles: Receiver<String>, // changes of what's playing, from the ICY metadata
+}
+
+/// Connect to the stream and start reading it ahead
+pub(super) fn open_stream(url: &str) -> Result<OpenedStream, String> {
+    let agent = ureq::AgentBuilder::new()
+        .timeout_connect(Duration::from_millis(CONNECT_TIMEOUT_MS))
+        .timeout_read(Duration::from_millis(READ_TIMEOUT_MS))
+        .build();
+    let (reader, content_type) = connect(&agent, url)?;
+    info!("connected to the stream {url} ({content_type})");
+
+    let (chunks_tx, chunks) = sync_channel(BUFFER_CHUNKS);
+    let (titles_tx, titles) = channel();
+    let url = url.to_string();
+    std::thread::spawn(move || read_ahead(agent, url, reader, chunks_tx));
+    Ok(OpenedStream {
+        stream: HttpStream {
+            chunks: Mutex::new(chunks),
+            chunk: vec![],
+            read: 0,
+            titles: titles_tx,
+        },
+        content_type,
+        titles,
+    })
+}
+
+fn connect(agent: &ureq::Agent, url: &str) -> Result<(IcyReader, String), String> {
+    let response = agent
+        .get(url)
+        .set("Icy-MetaData", "1")
+        .call()
+        .map_err(|e| format!("failed to connect to {url}: {e}"))?;
+    let metaint = response
+        .header("icy-metaint")
+        .and_then(|metaint| metaint.trim().parse().ok())
+        .filter(|&metaint| metaint > 0);
+    let content_type = response.content_type().to_string();
+    let reader = IcyReader {
+        inner: response.into_reader(),
+        metaint,
+        until_meta: metaint.unwrap_or(0),
+        playing: None,
+        title: None,
+    };
+    Ok((reader, content_type))
+}
+
+/// Read the stream into the buffer until the decoding side is gone,
+/// or the stream can't be read anymore
+fn read_ahead(agent: ureq::Agent, url: String, mut reader: IcyReader, chunks: SyncSender<Chunk>) {
+    let mut attempts = 0;
+    loop {
+        let mut chunk = vec![0; CHUNK_BYTES];
+        match reader.read(&mut chunk) {
+            Ok(read) if read > 0 => {
+                attempts = 0;
+                chunk.truncate(read);
+                let mut read_chunks = vec![Chunk::Audio(chunk)];
+                if let Some(title) = reader.title.take() {
+                    read_chunks.insert(0, Chunk::Title(title));
+                }
+                if read_chunks
+                    .into_iter()
+                    .any(|chunk| chunks.send(chunk).is_err())
+                {
+                    debug!("stopped reading the stream {url}");
+                    return;
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => (),
+            result => {
+                match result {
+                    Err(e) => warn!("lost the stream {url}: {e}"),
+                    _ => warn!("the stream {url} ended"),
+                }
+                // dropping the buffer's sender ends the song once the buffer is played
+                reader = loop {
+                    attempts += 1;
+                    if attempts > RECONNECT_ATTEMPTS {
+                        warn!("giving up on the stream {url}");
+                        return;
+                    }
+                    std::thread::sleep(Duration::from_millis(RECONNECT_DELAY_MS));
+                    match connect(&agent, &url) {
+                        // the title found before goes on, it's not new
+                        Ok((new_reader, _)) => {
+                            break IcyReader {
+                                playing: reader.playing.take(),
+                                ..new_reader
+                            }
+                        }
+                        Err(e) => warn!("{e}"),
+                    }
+                };
+                info!("reconnected to the stream {url}");
+            }
+        }
+    }
+}
+
+impl Read for HttpStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.read == self.chunk.len() {
+            let chunks = self.chunks.get_mut().unwrap();
+            match chunks.recv_timeout(Duration::from_millis(STALL_TIMEOUT_MS)) {
+                Ok(Chunk::Audio(chunk)) => {
+                    self.chunk = chunk;
+                    self.read = 0;
+                }
+                Ok(Chunk::Title(title)) => {
+                    let _ = self.titles.send(title);
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        "the stream stalled",
+                    ))
+                }
+                // the end of the stream
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            }
+        }
+        let read = buf.len().min(self.chunk.len() - self.read);
+        buf[..read].copy_from_slice(&self.chunk[self.read..self.read + read]);
+        self.read += read;
+        Ok(read)
+    }
+}
+
+impl Seek for HttpStream {
+    fn seek(&mut self, _pos: SeekFrom) -> std::io::Result<u64> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+}
+
+impl MediaSource for HttpStream {
+    fn is_seekable(&self) -> bool {
+        false
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// The audio of a stream, without the ICY metadata blocks that come every `metaint` bytes
+struct IcyReader {
+    inner: Box<dyn Read + Send + Sync>,
+    metaint: Option<usize>,
+    until_meta: usize,       // audio bytes left before the next metadata block
+    playing: Option<String>, // the last title found
+    title: Option<String>,   // a new title, not passed on yet
+}
+
+impl Read for IcyReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let Some(metaint) = self.metaint else {
+            return self.inner.read(buf);
+        };
+        if self.until_meta == 0 {
+            // a block is its length in 16 bytes units, then the metadata, padded with zeros
+            let mut length = [0u8];
+            self.inner.read_exact(&mut length)?;
+            let mut metadata = vec![0; length[0] as usize * 16];
+            self.inner.read_exact(&mut metadata)?;
+            // the title is repeated in every block, it's passed on when it changes
+            match stream_title(&metadata) {
+                Some(title) if self.playing.as_ref() != Some(&title) => {
+                    debug!("the stream now plays {title:?}");
+                    self.playing = Some(title.clone());
+                    self.title = Some(title);
+                }
+                _ => (),
+            }
+            self.until_meta = metaint;
+        }
+        let max = buf.len().min(self.until_meta);
+        let read = self.inner.read(&mut buf[..max])?;
+        self.until_meta -= read;
+        Ok(read)
+    }
+}
+
+/// The title in metadata such as `StreamTitle='Artist - Title';StreamUrl='';`
+fn stream_title(metadata: &[u8]) -> Option<String> {
+    let metadata = String::from_utf8_lossy(metadata);
+    let start = metadata.find("StreamTitle='")? + "StreamTitle='".len();
+    let end = metadata[start..]
+        .find("';")
+        .map(|end| start + end)
+        .unwrap_or(metadata.len());
+    let title = metadata[start..end].trim_end_matches('\0').trim();
+    (!title.is_empty()).then(|| title.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+
+    const METAINT: usize = 16;
+
+    /// What a stand-in radio sends on a connection: audio, then a metadata block, and so on
+    struct Connection {
+        audio: Vec<u8>,
+        titles: Vec<&'static str>, // after each METAINT bytes of audio
+    }
+
+    fn metadata_block(title: &str) -> Vec<u8> {
+        let metadata = format!("StreamTitle='{title}';StreamUrl='';");
+        let length = metadata.len().div_ceil(16);
+        let mut block = vec![length as u8];
+        block.extend_from_slice(metadata.as_bytes());
+        block.resize(1 + length * 16, 0);
+        block
+    }
+
+    /// Serve the connections in turn, each closed once sent, and keep the requests
+    fn radio(connections: Vec<Connection>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/stream", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(vec![]));
+        let kept = requests.clone();
+        std::thread::spawn(move || {
+            for connection in connections {
+                let (mut socket, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(socket.try_clone().unwrap());
+                let mut request = String::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    request += &line;
+                }
+                kept.lock().unwrap().push(request.to_lowercase());
+
+                let mut response = format!(
+                    "HTTP/1.0 200 OK\r\nContent-Type: audio/mpeg\r\nicy-metaint: {METAINT}\r\n\r\n"
+                )
+                .into_bytes();
+                let mut titles = connection.titles.iter();
+                for (i, audio) in connection.audio.chunks(METAINT).enumerate() {
+                    if i > 0 {
+                        response.extend(metadata_block(titles.next().unwrap()));
+                    }
+                    response.extend_from_slice(audio);
+                }
+                socket.write_all(&response).unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    #[test]
+    fn titles_are_taken_out_and_lost_stream_goes_on() {
+        let audio: Vec<u8> = (0..96).collect();
+        let (url, requests) = radio(vec![
+            Connection {
+                audio: audio[..48].to_vec(),
+                titles: vec!["Artist - First", "Artist - First"],
+            },
+            // connected again once lost, going on with the same title first
+            Connection {
+                audio: audio[48..].to_vec(),
+                titles: vec!["Artist - First", "Artist - Second"],
+            },
+        ]);
+
+        let mut opened = open_stream(&url).unwrap();
+        assert_eq!(opened.content_type, "audio/mpeg");
+        let mut read = vec![0; audio.len()];
+        opened.stream.read_exact(&mut read).unwrap();
+        assert_eq!(read, audio);
+
+        // each title once, as it changes
+        let titles: Vec<String> = opened.titles.try_iter().collect();
+        assert_eq!(titles, ["Artist - First", "Artist - Second"]);
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests.iter().all(|r| r.contains("icy-metadata: 1")));
+    }
+
+    #[test]
+    fn unreachable_stream_fails_to_open() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/stream", listener.local_addr().unwrap());
+        drop(listener);
+        assert!(open_stream(&url).is_err());
+    }
+
+    #[test]
+    fn stream_title_from_metadata() {
+        let metadata = b"StreamTitle='Artist - It''s a title';StreamUrl='';\0\0\0";
+        assert_eq!(
+            stream_title(metadata).as_deref(),
+            Some("Artist - It''s a title")
+        );
+        assert_eq!(
+            stream_title(b"StreamTitle='Unterminated\0\0").as_deref(),
+            Some("Unterminated")
+        );
+        assert_eq!(stream_title(b"StreamTitle='';"), None);
+        assert_eq!(stream_title(b"StreamUrl='http://radio.test';"), None);
+    }
+}
@@ -0,0 +1,277 @@
+//! Time-stretching: change the playback speed while keeping the pitch
+//!
+//! This is a WSOLA (waveform similarity overlap-add): the output is built from overlapping
+//! windowed frames of the input, taken every `hop * speed` input frames and laid out every
+//! `hop` output frames. Around each nominal position, the frame that best continues
+//! the previous one is picked, which avoids the phasing artifacts of a plain overlap-add.
+
+use symphonia::core::audio::{AudioBuffer, Signal};
+
+pub const MIN_SPEED: f32 = 0.5;
+pub const MAX_SPEED: f32 = 3.0;
+
+// half a window, and the output hop
+const HOP_MS: u32 = 20;
+// how far around the nominal position a better matching frame is looked for
+const SEARCH_MS: u32 = 8;
+// only one in this many samples is used to compare frames, to save CPU
+const CORRELATION_STEP: usize = 4;
+
+pub(super) struct TimeStretcher {
+    speed: f64,
+    rate: u32,
+    hop: usize,
+    search: usize,
+    window: Vec<f32>, // Hann window, two hops long
+
+    input: Vec<Vec<f32>>, // per channel, input not entirely consumed yet
+    position: f64,        // nominal start of the next frame in `input`
+    natural: usize,       // where the previous frame would naturally continue, in `input`
+    tail: Vec<Vec<f32>>,  // windowed end of the previous frame, to overlap with the next one
+    started: bool,
+
+    stretched: Vec<Vec<f32>>,         // per channel, output of the last call
+    output: Option<AudioBuffer<f32>>, // re-used between calls to avoid allocations
+}
+
+impl TimeStretcher {
+    pub fn new() -> Self {
+        TimeStretcher {
+            speed: 1.0,
+            rate: 0,
+            hop: 0,
+            search: 0,
+            window: vec![],
+            input: vec![],
+            position: 0.0,
+            natural: 0,
+            tail: vec![],
+            started: false,
+            stretched: vec![],
+            output: None,
+        }
+    }
+
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed.clamp(MIN_SPEED, MAX_SPEED) as f64;
+    }
+
+    pub fn is_bypassed(&self) -> bool {
+        self.speed == 1.0
+    }
+
+    /// Forget the buffered input, as after a seek
+    pub fn reset(&mut self) {
+        let channels = self.input.len();
+        self.configure(self.rate, channels);
+    }
+
+    fn configure(&mut self, rate: u32, channels: usize) {
+        self.rate = rate;
+        self.hop = (rate * HOP_MS / 1000) as usize;
+        self.search = (rate * SEARCH_MS / 1000) as usize;
+        let len = 2 * self.hop;
+        self.window = (0..len)
+            .map(|i| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / len as f32).cos())
+            .collect();
+        self.input = vec![vec![]; channels];
+        self.position = 0.0;
+        self.natural = 0;
+        self.tail = vec![vec![0.0; self.hop]; channels];
+        self.started = false;
+        self.stretched = vec![vec![]; channels];
+    }
+
+    /// Stretch the buffer. The output lags behind the input by about a window,
+    /// and may be empty
+    pub fn process(&mut self, buffer: &AudioBuffer<f32>) -> &AudioBuffer<f32> {
+        let spec = *buffer.spec();
+        let channels = spec.channels.count();
+        if spec.rate != self.rate || channels != self.input.len() {
+            self.configure(spec.rate, channels);
+        }
+
+        for (c, input) in self.input.iter_mut().enumerate() {
+            input.extend_from_slice(buffer.chan(c));
+        }
+        for stretched in self.stretched.iter_mut() {
+            stretched.clear();
+        }
+
+        while let Some(start) = self.next_frame() {
+            self.overlap_add(start);
+            self.natural = start + self.hop;
+            self.position += self.hop as f64 * self.speed;
+            self.started = true;
+        }
+
+        // drop the input that no frame can start from anymore
+        let consumed = std::cmp::min(
+            (self.position as usize).saturating_sub(self.search),
+            self.natural,
+        );
+        for input in self.input.iter_mut() {
+            input.drain(..consumed);
+        }
+        self.position -= consumed as f64;
+        self.natural -= consumed;
+
+        // copy the stretched samples in an audio buffer
+        let frames = self.stretched.first().map(|s| s.len()).unwrap_or(0);
+        let reusable = matches!(&self.output, Some(output)
+            if *output.spec() == spec && output.capacity() >= frames);
+        if !reusable {
+            let capacity = std::cmp::max(frames, buffer.capacity());
+            self.output = Some(AudioBuffer::new(capacity as u64, spec));
+        }
+        let output = self.output.as_mut().unwrap();
+        output.clear();
+        output.render_reserved(Some(frames));
+        for (c, stretched) in self.stretched.iter().enumerate() {
+            output.chan_mut(c).copy_from_slice(stretched);
+        }
+        output
+    }
+
+    /// Start of the next frame to overlap-add, if there is enough input for it
+    fn next_frame(&self) -> Option<usize> {
+        let nominal = self.position.round() as usize;
+        let first = nominal.saturating_sub(self.search);
+        let last = nominal + self.search;
+
+        let available = self.input.first().map(|i| i.len()).unwrap_or(0);
+        if last + 2 * self.hop > available || self.natural + self.hop > available {
+            return None;
+        }
+        if !self.started {
+            return Some(nominal);
+        }
+
+        // pick the candidate most similar to the natural continuation of the previous frame
+        let mono = |i: usize| self.input.iter().map(|c| c[i]).sum::<f32>();
+        let reference: Vec<f32> = (0..self.hop)
+            .step_by(CORRELATION_STEP)
+            .map(|k| mono(self.natural + k))
+            .collect();
+
+        let mut best = (nominal, f32::MIN);
+        for candidate in first..=last {
+            let (mut correlation, mut energy) = (0.0, 0.0);
+            for (j, k) in (0..self.hop).step_by(CORRELATION_STEP).enumerate() {
+                let x = mono(candidate + k);
+                correlation += x * reference[j];
+                energy += x * x;
+            }
+            let score = correlation / (energy.sqrt() + f32::EPSILON);
+            if score > best.1 {
+                best = (candidate, score);
+            }
+        }
+        Some(best.0)
+    }
+
+    fn overlap_add(&mut self, start: usize) {
+        let hop = self.hop;
+        for c in 0..self.input.len() {
+            let input = &self.input[c][start..start + 2 * hop];
+            let tail = &mut self.tail[c];
+            let stretched = &mut self.stretched[c];
+            for k in 0..hop {
+                stretched.push(tail[k] + self.window[k] * input[k]);
+                tail[k] = self.window[hop + k] * input[hop + k];
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::dsp::signal::{buffer, sine};
+
+    const RATE: u32 = 44100;
+    const CHUNK: usize = 1024;
+
+    /// Stretch a stereo sine, fed in chunks as the player does
+    fn stretch(speed: f32, frequency: f32, frames: usize) -> Vec<f32> {
+        let mut stretcher = TimeStretcher::new();
+        stretcher.set_speed(speed);
+        let input = sine(RATE, frequency, 0.5, frames);
+        let mut output = vec![];
+        for chunk in input.chunks(CHUNK) {
+            let stretched = stretcher.process(&buffer(RATE, &[chunk.to_vec(), chunk.to_vec()]));
+            assert_eq!(stretched.chan(0), stretched.chan(1));
+            output.extend_from_slice(stretched.chan(0));
+        }
+        output
+    }
+
+    /// The most output frames missing at a speed, for the window and search still buffered
+    fn lag(speed: f32) -> f32 {
+        (RATE * (2 * HOP_MS + SEARCH_MS) / 1000) as f32 / speed
+    }
+
+    /// The frequency of a sine, from its zero crossings
+    fn frequency(samples: &[f32]) -> f32 {
+        let crossings = (samples.windows(2))
+            .filter(|pair| (pair[0] < 0.0) != (pair[1] < 0.0))
+            .count();
+        crossings as f32 / 2.0 / (samples.len() as f32 / RATE as f32)
+    }
+
+    #[test]
+    fn speeds() {
+        let mut stretcher = TimeStretcher::new();
+        assert!(stretcher.is_bypassed());
+        stretcher.set_speed(10.0);
+        assert_eq!(stretcher.speed, MAX_SPEED as f64);
+        stretcher.set_speed(0.0);
+        assert_eq!(stretcher.speed, MIN_SPEED as f64);
+        stretcher.set_speed(1.0);
+        assert!(stretcher.is_bypassed());
+    }
+
+    #[test]
+    fn faster() {
+        let frames = 2 * RATE as usize;
+        let output = stretch(1.5, 440.0, frames);
+        let expected = frames as f32 / 1.5;
+        let lag = lag(1.5);
+        assert!(
+            (output.len() as f32 - expected).abs() <= lag,
+            "{} frames instead of about {expected}",
+            output.len()
+        );
+        // at the same pitch
+        let steady = &output[RATE as usize / 10..];
+        assert!((frequency(steady) - 440.0).abs() < 440.0 * 0.02);
+    }
+
+    #[test]
+    fn slower() {
+        let frames = RATE as usize;
+        let output = stretch(0.5, 440.0, frames);
+        let expected = frames as f32 * 2.0;
+        let lag = lag(0.5);
+        assert!(
+            (output.len() as f32 - expected).abs() <= lag,
+            "{} frames instead of about {expected}",
+            output.len()
+        );
+        let steady = &output[RATE as usize / 10..];
+        assert!((frequency(steady) - 440.0).abs() < 440.0 * 0.02);
+    }
+
+    #[test]
+    fn reset_forgets_the_input() {
+        let mut stretcher = TimeStretcher::new();
+        stretcher.set_speed(2.0);
+        let input = buffer(RATE, &[sine(RATE, 440.0, 0.5, CHUNK)]);
+        stretcher.process(&input);
+        stretcher.reset();
+        assert!(stretcher.input.iter().all(Vec::is_empty));
+        // as from the start, not enough for a frame yet
+        let stretched = stretcher.process(&input);
+        assert_eq!(stretched.frames(), 0);
+    }
+}
@@ -0,0 +1,238 @@
+//! Waveform overview of a whole song, as drawn behind a seek bar
+//!
+//! The song is decoded once, as fast as possible, and summarized as the lowest and
+//! highest sample of each of `WAVEFORM_BUCKETS` slices of equal duration.
+//! The summaries are kept in the database, by song source.
+
+use serde::{Deserialize, Serialize};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::errors::Error;
+
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use log::{debug, warn};
+
+use super::player::open_song;
+use crate::config::Config;
+use crate::database::{get_waveform, save_waveform};
+use crate::music::song::{Song, SongSource};
+
+pub const WAVEFORM_BUCKETS: usize = 1000;
+
+// frames summarized together while decoding, before the song's length is known for sure
+const CHUNK_FRAMES: usize = 256;
+
+/// Lowest and highest sample per slice of the song, all channels together,
+/// scaled so that 127 is full scale. Short songs may have fewer slices
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Waveform {
+    pub min: Vec<i8>,
+    pub max: Vec<i8>,
+}
+
+impl Waveform {
+    /// Merge the chunks into at most `buckets` slices
+    fn from_chunks(chunks: &[(f32, f32)], buckets: usize) -> Self {
+        let buckets = buckets.min(chunks.len());
+        let mut waveform = Waveform::default();
+        for bucket in 0..buckets {
+            let slice =
+                &chunks[bucket * chunks.len() / buckets..(bucket + 1) * chunks.len() / buckets];
+            let min = slice.iter().map(|chunk| chunk.0).fold(0.0, f32::min);
+            let max = slice.iter().map(|chunk| chunk.1).fold(0.0, f32::max);
+            waveform.min.push(quantize(min));
+            waveform.max.push(quantize(max));
+        }
+        waveform
+    }
+
+    /// As stored in the database: the min and max of each slice, one after the other
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.min
+            .iter()
+            .zip(&self.max)
+            .flat_map(|(&min, &max)| [min as u8, max as u8])
+            .collect()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Waveform {
+            min: bytes.iter().step_by(2).map(|&b| b as i8).collect(),
+            max: bytes.iter().skip(1).step_by(2).map(|&b| b as i8).collect(),
+        }
+    }
+}
+
+fn quantize(sample: f32) -> i8 {
+    (sample * 127.0).round().clamp(-127.0, 127.0) as i8
+}
+
+/// Decode the whole song to summarize it
+pub fn compute_waveform(song: &Song) -> Result<Waveform, String> {
+    if let Some(SongSource::StreamUrl(_)) = song.source {
+        return Err("a stream has no end to summarize".to_string());
+    }
+    // only for the songs that can be played without resolving them
+    let mut opened = open_song(song, song.source.as_ref())?;
+    let mut chunks: Vec<(f32, f32)> = vec![];
+    let mut chunk = (0.0f32, 0.0f32);
+    let mut chunk_frames = 0;
+    let mut samples: Option<SampleBuffer<f32>> = None;
+    loop {
+        let packet = match opened.format.next_packet() {
+            Ok(packet) if opened.is_past_end(&packet) => break,
+            Ok(packet) => packet,
+            Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            // a chained stream: only the first one is summarized
+            Err(Error::ResetRequired) => break,
+            Err(e) => return Err(format!("failed to read the song: {e}")),
+        };
+        if packet.track_id() != opened.track_id {
+            continue;
+        }
+        let decoded = match opened.decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            Err(Error::IoError(_)) | Err(Error::DecodeError(_)) => continue,
+            Err(e) => return Err(format!("failed to decode the song: {e}")),
+        };
+
+        let spec = *decoded.spec();
+        let channels = spec.channels.count();
+        let needed = decoded.capacity() * channels;
+        if samples.as_ref().is_none_or(|s| s.capacity() < needed) {
+            samples = Some(SampleBuffer::new(decoded.capacity() as u64, spec));
+        }
+        let samples = samples.as_mut().unwrap();
+        samples.copy_interleaved_ref(decoded);
+        for frame in samples.samples().chunks(channels) {
+            for &sample in frame {
+                chunk.0 = chunk.0.min(sample);
+                chunk.1 = chunk.1.max(sample);
+            }
+            chunk_frames += 1;
+            if chunk_frames == CHUNK_FRAMES {
+                chunks.push(chunk);
+                chunk = (0.0, 0.0);
+                chunk_frames = 0;
+            }
+        }
+    }
+    if chunk_frames > 0 {
+        chunks.push(chunk);
+    }
+    Ok(Waveform::from_chunks(&chunks, WAVEFORM_BUCKETS))
+}
+
+/// The song's waveform from the database, computed and saved there first if missing
+pub async fn waveform(config: &Config, song: Song) -> Result<Waveform> {
+    let source: String = song
+        .source
+        .clone()
+        .ok_or_else(|| eyre!("the song has no source"))?
+        .into();
+    match get_waveform(config, source.clone()).await {
+        Ok(Some(waveform)) => return Ok(waveform),
+        Ok(None) => (),
+        Err(e) => warn!("failed to read the waveform from the database: {e}"),
+    }
+
+    debug!("computing the waveform of {:?}", song.title);
+    let waveform = tokio::task::spawn_blocking(move || compute_waveform(&song))
+        .await?
+        .map_err(|e| eyre!(e))?;
+    if let Err(e) = save_waveform(config, source, &waveform).await {
+        warn!("failed to save the waveform: {e}");
+    }
+    Ok(waveform)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::music::song::AudioFormat;
+
+    #[test]
+    fn buckets() {
+        // each chunk a step further from silence
+        let chunks: Vec<(f32, f32)> = (1..=10)
+            .map(|i| (-0.1 * i as f32, 0.05 * i as f32))
+            .collect();
+        let waveform = Waveform::from_chunks(&chunks, 4);
+        // chunks 0-1, 2-4, 5-6 and 7-9, by the extremes of their last chunk
+        assert_eq!(waveform.min, [-25, -64, -89, -127]);
+        assert_eq!(waveform.max, [13, 32, 44, 64]);
+
+        // short songs have a slice per chunk
+        let waveform = Waveform::from_chunks(&chunks[..3], WAVEFORM_BUCKETS);
+        assert_eq!(waveform.min, [-13, -25, -38]);
+        assert_eq!(waveform.max, [6, 13, 19]);
+        assert_eq!(
+            Waveform::from_chunks(&[], WAVEFORM_BUCKETS),
+            Waveform::default()
+        );
+    }
+
+    #[test]
+    fn quantized() {
+        assert_eq!(quantize(1.0), 127);
+        assert_eq!(quantize(-1.0), -127);
+        // clipped samples
+        assert_eq!(quantize(1.5), 127);
+        assert_eq!(quantize(-1.5), -127);
+        assert_eq!(quantize(0.5), 64);
+    }
+
+    #[test]
+    fn stored_as_bytes() {
+        let waveform = Waveform {
+            min: vec![-127, -3, 0],
+            max: vec![127, 5, 0],
+        };
+        let bytes = waveform.to_bytes();
+        assert_eq!(bytes, [0x81, 0x7f, 0xfd, 0x05, 0x00, 0x00]);
+        assert_eq!(Waveform::from_bytes(&bytes), waveform);
+        assert_eq!(Waveform::from_bytes(&[]), Waveform::default());
+    }
+
+    #[test]
+    fn computed_from_the_file() {
+        // a mono 16-bit WAV: ten chunks at half scale, then ten at a quarter, negative
+        let frames = 20 * CHUNK_FRAMES;
+        let mut data = vec![];
+        for i in 0..frames {
+            let sample: i16 = if i < frames / 2 { 16384 } else { -8192 };
+            data.extend_from_slice(&sample.to_le_bytes());
+        }
+        let mut wav = vec![];
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data.len() as u32).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        for field in [16u32, 1 | 1 << 16, 8000, 16000, 2 | 16 << 16] {
+            wav.extend_from_slice(&field.to_le_bytes());
+        }
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        wav.extend_from_slice(&data);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("steps.wav");
+        std::fs::write(&path, wav).unwrap();
+
+        let song = Song {
+            source: Some(SongSource::FilePath(path)),
+            format: AudioFormat::wav,
+            ..Default::default()
+        };
+        let waveform = compute_waveform(&song).unwrap();
+        assert_eq!(waveform.max, [[64; 10], [0; 10]].concat());
+        assert_eq!(waveform.min, [[0; 10], [-32; 10]].concat());
+    }
+
+    #[test]
+    fn streams_have_no_waveform() {
+        let song = Song {
+            source: Some(SongSource::StreamUrl("http://radio.test/live".to_string())),
+            ..Default::default()
+        };
+        assert!(compute_waveform(&song).is_err());
+    }
+}
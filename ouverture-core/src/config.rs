@@ -1,15 +1,60 @@
 use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 use toml;
 
-use log::trace;
+use log::{trace, warn};
 
 use color_eyre::Result;
 
 use platform_dirs::AppDirs;
 
+use crate::audio::{Equalizer, EqualizerBand, FilterKind, OutputFormat};
+
+/// How the audio thread seeks within a song
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekMode {
+    /// Land exactly on the requested position
+    Accurate,
+    /// Land on the nearest position the container can seek to, faster
+    Coarse,
+}
+
+/// Where the audio thread sends the decoded audio
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub enum AudioBackend {
+    /// PulseAudio server (Linux)
+    Pulse,
+    /// Default device of the platform's native API (ALSA, CoreAudio, WASAPI)
+    Cpal,
+    /// 32-bit float WAV file, created anew when the server starts. The songs follow each
+    /// other in it, a change of format going on in a numbered file next to it
+    Wav(PathBuf),
+    /// Raw interleaved signed 16-bit little-endian PCM, to a file or FIFO, or stdout if none
+    Pipe(Option<PathBuf>),
+    /// Discard the audio, at the pace it would have been played
+    Null,
+    /// Discard the audio but record what was written, for testing.
+    /// `pace` is the speed relative to real-time, 0 for as fast as possible
+    Capture { pace: f32, samples: bool },
+}
+
+impl Default for AudioBackend {
+    /// The sound server or device of the platform, if built in
+    fn default() -> AudioBackend {
+        if cfg!(all(feature = "pulse", target_os = "linux")) {
+            AudioBackend::Pulse
+        } else if cfg!(any(feature = "cpal", not(target_os = "linux"))) {
+            AudioBackend::Cpal
+        } else {
+            AudioBackend::Null
+        }
+    }
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct Config {
     pub library: Vec<PathBuf>,
@@ -20,6 +65,49 @@ pub struct Config {
 
     pub database_dir: PathBuf,
     pub database_port: usize,
+
+    pub seek_mode: SeekMode,
+    pub audio_output: AudioBackend,
+    /// Sample rate and channels to convert all songs to, if any
+    pub output_format: OutputFormat,
+    /// Reports of the audio levels and spectrum per second, 0 for none
+    pub meter_rate: u32,
+    /// Compute the waveforms of the songs when scanning the library, rather than when asked
+    pub scan_waveforms: bool,
+    /// Keep the library up to date as files are added, changed, moved and deleted,
+    /// without scanning
+    pub watch_library: bool,
+
+    /// Equalizer preset applied at startup
+    pub equalizer: Option<String>,
+    pub equalizer_presets: BTreeMap<String, Equalizer>,
+
+    /// Songs at least this long resume where they were left, none if unset
+    pub resume_min_duration: Option<Duration>,
+    /// Songs of these genres resume where they were left whatever their length,
+    /// compared without case
+    pub resume_genres: Vec<String>,
+
+    /// yt-dlp, or a program taking the same options, to play online songs
+    pub resolver_command: PathBuf,
+    /// How long the resolver command may run, downloading included
+    pub resolver_timeout: Duration,
+    /// Where online songs are downloaded to, and found again
+    pub resolver_cache_dir: PathBuf,
+    /// Stream online songs rather than downloading them
+    pub resolver_stream: bool,
+
+    /// Where online songs downloaded into the library are kept
+    pub download_dir: PathBuf,
+    /// Downloads running at the same time
+    pub download_concurrency: usize,
+    /// Times a failed download is tried again
+    pub download_retries: u32,
+
+    /// Where podcast episodes are downloaded to
+    pub podcast_dir: PathBuf,
+    /// How often the podcast feeds are checked for new episodes, never if none
+    pub podcast_refresh: Option<Duration>,
 }
 
 impl Config {
@@ -62,6 +150,164 @@ impl Config {
             config.database_dir = PathBuf::from(database_dir);
         }
 
+        if let Some(toml::Value::String(seek_mode)) = t.get("seek_mode") {
+            config.seek_mode = match seek_mode.as_str() {
+                "coarse" => SeekMode::Coarse,
+                _ => SeekMode::Accurate,
+            };
+        }
+
+        if let Some(toml::Value::String(audio_output)) = t.get("audio_output") {
+            let path = t
+                .get("audio_output_path")
+                .and_then(|p| p.as_str())
+                .map(PathBuf::from);
+            config.audio_output = match (audio_output.as_str(), path) {
+                ("pulse", _) => AudioBackend::Pulse,
+                ("cpal", _) | ("alsa", _) => AudioBackend::Cpal,
+                ("wav", Some(path)) => AudioBackend::Wav(path),
+                ("wav", None) => {
+                    warn!(
+                        "the wav audio output needs an audio_output_path, using the default output"
+                    );
+                    AudioBackend::default()
+                }
+                ("pipe", path) => AudioBackend::Pipe(path),
+                ("null", _) => AudioBackend::Null,
+                ("capture", _) => AudioBackend::Capture {
+                    pace: match t.get("capture_pace") {
+                        Some(toml::Value::Float(pace)) => *pace as f32,
+                        Some(toml::Value::Integer(pace)) => *pace as f32,
+                        _ => 1.0,
+                    },
+                    samples: matches!(t.get("capture_samples"), Some(toml::Value::Boolean(true))),
+                },
+                (other, _) => {
+                    warn!("unknown audio output '{other}' in config, using the default output");
+                    AudioBackend::default()
+                }
+            };
+        }
+
+        if let Some(toml::Value::Integer(output_rate)) = t.get("output_rate") {
+            config.output_format.rate = match output_rate {
+                8000..=384000 => Some(*output_rate as u32),
+                _ => {
+                    warn!("output_rate must be between 8000 and 384000 Hz, ignoring it");
+                    None
+                }
+            };
+        }
+
+        if let Some(toml::Value::Integer(output_channels)) = t.get("output_channels") {
+            config.output_format.channels = match output_channels {
+                1 | 2 => Some(*output_channels as usize),
+                _ => {
+                    warn!("output_channels can only be 1 (mono) or 2 (stereo), ignoring it");
+                    None
+                }
+            };
+        }
+
+        if let Some(toml::Value::Boolean(scan_waveforms)) = t.get("scan_waveforms") {
+            config.scan_waveforms = *scan_waveforms;
+        }
+
+        if let Some(toml::Value::Boolean(watch_library)) = t.get("watch_library") {
+            config.watch_library = *watch_library;
+        }
+
+        if let Some(toml::Value::Integer(meter_rate)) = t.get("meter_rate") {
+            config.meter_rate = (*meter_rate).clamp(0, 100) as u32;
+        }
+
+        if let Some(toml::Value::String(equalizer)) = t.get("equalizer") {
+            config.equalizer = Some(equalizer.clone());
+        }
+
+        if let Some(toml::Value::Table(presets)) = t.get("equalizer_presets") {
+            for (name, preset) in presets {
+                match Config::toml_to_equalizer(preset) {
+                    Some(equalizer) => {
+                        config.equalizer_presets.insert(name.clone(), equalizer);
+                    }
+                    None => warn!("invalid equalizer preset '{name}' in config, ignoring it"),
+                }
+            }
+        }
+
+        // in minutes, 0 to only go by genre
+        match t.get("resume_min_duration") {
+            Some(toml::Value::Integer(0)) => config.resume_min_duration = None,
+            Some(toml::Value::Integer(minutes)) if *minutes > 0 => {
+                config.resume_min_duration = Some(Duration::from_secs(*minutes as u64 * 60));
+            }
+            Some(_) => warn!("resume_min_duration must be a number of minutes, ignoring it"),
+            None => (),
+        }
+
+        if let Some(toml::Value::Array(genres)) = t.get("resume_genres") {
+            config.resume_genres = genres
+                .iter()
+                .filter_map(|v| v.as_str().map(String::from))
+                .collect();
+        }
+
+        if let Some(toml::Value::String(command)) = t.get("resolver_command") {
+            config.resolver_command = PathBuf::from(command);
+        }
+
+        // in seconds
+        match t.get("resolver_timeout") {
+            Some(toml::Value::Integer(seconds)) if *seconds > 0 => {
+                config.resolver_timeout = Duration::from_secs(*seconds as u64);
+            }
+            Some(_) => warn!("resolver_timeout must be a number of seconds, ignoring it"),
+            None => (),
+        }
+
+        if let Some(toml::Value::String(cache_dir)) = t.get("resolver_cache_dir") {
+            config.resolver_cache_dir = PathBuf::from(cache_dir);
+        }
+
+        if let Some(toml::Value::Boolean(stream)) = t.get("resolver_stream") {
+            config.resolver_stream = *stream;
+        }
+
+        if let Some(toml::Value::String(download_dir)) = t.get("download_dir") {
+            config.download_dir = PathBuf::from(download_dir);
+        }
+
+        match t.get("download_concurrency") {
+            Some(toml::Value::Integer(concurrency)) if *concurrency > 0 => {
+                config.download_concurrency = *concurrency as usize;
+            }
+            Some(_) => warn!("download_concurrency must be at least 1, ignoring it"),
+            None => (),
+        }
+
+        match t.get("download_retries") {
+            Some(toml::Value::Integer(retries)) if *retries >= 0 => {
+                config.download_retries = *retries as u32;
+            }
+            Some(_) => warn!("download_retries must be a number, ignoring it"),
+            None => (),
+        }
+
+        if let Some(toml::Value::String(podcast_dir)) = t.get("podcast_dir") {
+            config.podcast_dir = PathBuf::from(podcast_dir);
+        }
+
+        // in minutes, 0 to only refresh when asked
+        match t.get("podcast_refresh") {
+            Some(toml::Value::Integer(0)) => config.podcast_refresh = None,
+            Some(toml::Value::Integer(minutes)) if *minutes > 0 => {
+                config.podcast_refresh = Some(Duration::from_secs(*minutes as u64 * 60));
+            }
+            Some(_) => warn!("podcast_refresh must be a number of minutes, ignoring it"),
+            None => (),
+        }
+
         if let Some(toml::Value::Array(library)) = t.get("library") {
             config.library = library
                 .clone()
@@ -72,6 +318,51 @@ impl Config {
 
         Ok(config)
     }
+
+    /// Read an equalizer preset such as
+    /// { preamp = -3.0, bands = [{ kind = "low_shelf", frequency = 120, gain = 4.0, q = 0.7 }] }
+    fn toml_to_equalizer(v: &toml::Value) -> Option<Equalizer> {
+        let as_f32 = |v: &toml::Value| match v {
+            toml::Value::Float(f) => Some(*f as f32),
+            toml::Value::Integer(i) => Some(*i as f32),
+            _ => None,
+        };
+
+        let t = v.as_table()?;
+        let mut equalizer = Equalizer {
+            preamp_db: t.get("preamp").map(as_f32).unwrap_or(Some(0.0))?,
+            bands: vec![],
+        };
+        if let Some(bands) = t.get("bands") {
+            for band in bands.as_array()? {
+                let band = band.as_table()?;
+                let kind = match band.get("kind").and_then(|k| k.as_str()).unwrap_or("peak") {
+                    "peak" => FilterKind::Peak,
+                    "low_shelf" => FilterKind::LowShelf,
+                    "high_shelf" => FilterKind::HighShelf,
+                    "low_pass" => FilterKind::LowPass,
+                    "high_pass" => FilterKind::HighPass,
+                    _ => return None,
+                };
+                equalizer.bands.push(EqualizerBand {
+                    kind,
+                    frequency: as_f32(band.get("frequency")?)?,
+                    gain_db: band.get("gain").map(as_f32).unwrap_or(Some(0.0))?,
+                    q: band.get("q").map(as_f32).unwrap_or(Some(0.707))?,
+                });
+            }
+        }
+        Some(equalizer)
+    }
+
+    /// The equalizer configured to be used at startup, flat if none
+    pub fn startup_equalizer(&self) -> Equalizer {
+        self.equalizer
+            .as_ref()
+            .and_then(|name| self.equalizer_presets.get(name))
+            .cloned()
+            .unwrap_or_default()
+    }
 }
 
 impl Default for Config {
@@ -86,6 +377,37 @@ impl Default for Config {
                 .unwrap()
                 .data_dir,
             database_port: 6604,
+
+            seek_mode: SeekMode::Accurate,
+            audio_output: AudioBackend::default(),
+            output_format: OutputFormat::default(),
+            meter_rate: 20,
+            scan_waveforms: false,
+            watch_library: false,
+
+            equalizer: None,
+            equalizer_presets: BTreeMap::new(),
+
+            resume_min_duration: Some(Duration::from_secs(30 * 60)),
+            resume_genres: vec!["Podcast".to_string(), "Audiobook".to_string()],
+
+            resolver_command: PathBuf::from("yt-dlp"),
+            resolver_timeout: Duration::from_secs(120),
+            resolver_cache_dir: AppDirs::new(Some("ouverture/online"), true)
+                .unwrap()
+                .cache_dir,
+            resolver_stream: false,
+
+            download_dir: AppDirs::new(Some("ouverture/downloads"), true)
+                .unwrap()
+                .data_dir,
+            download_concurrency: 2,
+            download_retries: 2,
+
+            podcast_dir: AppDirs::new(Some("ouverture/podcasts"), true)
+                .unwrap()
+                .data_dir,
+            podcast_refresh: Some(Duration::from_secs(60 * 60)),
         }
     }
 }
@@ -0,0 +1,36 @@
+use sea_orm::sea_query::ColumnDef;
+use sea_orm::{error::*, sea_query, DbConn, ExecResult};
+
+use super::setup::create_table;
+
+use sea_orm::prelude::*;
+
+/// Online song downloaded into the library, linked to the file it was imported from
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[sea_orm(table_name = "downloads")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub source: String, // the online source, as stored in the songs table
+    pub path: String,
+}
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+/// Also run on databases created before downloads existed
+pub async fn create_download_table(db: &DbConn) -> Result<ExecResult, DbErr> {
+    let stmt = sea_query::Table::create()
+        .table(Entity)
+        .if_not_exists()
+        .col(
+            ColumnDef::new(Column::Source)
+                .string()
+                .not_null()
+                .primary_key(),
+        )
+        .col(ColumnDef::new(Column::Path).string().not_null())
+        .to_owned();
+
+    create_table(db, &stmt).await
+}
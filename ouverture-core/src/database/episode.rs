@@ -0,0 +1,94 @@
+use sea_orm::sea_query::ColumnDef;
+use sea_orm::{error::*, sea_query, DbConn, ExecResult};
+
+use super::setup::create_table;
+
+use chrono::prelude::{Local, TimeZone};
+use std::time::Duration;
+
+use crate::music::song::Song;
+use crate::podcast::feed::audio_format;
+use crate::podcast::{Episode, PODCAST_GENRE};
+use sea_orm::entity::*;
+use sea_orm::prelude::*;
+
+/// An episode of a podcast, as found in its feed
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[sea_orm(table_name = "episodes")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub source: String, // 'enclosure:<url>', as stored in the songs table
+    pub podcast: String, // url of the feed
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,  // title of the podcast
+    pub published: Option<i64>, // unix timestamp, in seconds
+    pub duration: i64,          // in milliseconds, 0 if unknown
+    pub played: bool,
+}
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+/// Also run on databases created before podcasts existed
+pub async fn create_episode_table(db: &DbConn) -> Result<ExecResult, DbErr> {
+    let stmt = sea_query::Table::create()
+        .table(Entity)
+        .if_not_exists()
+        .col(
+            ColumnDef::new(Column::Source)
+                .string()
+                .not_null()
+                .primary_key(),
+        )
+        .col(ColumnDef::new(Column::Podcast).string().not_null())
+        .col(ColumnDef::new(Column::Title).string())
+        .col(ColumnDef::new(Column::Artist).string())
+        .col(ColumnDef::new(Column::Album).string())
+        .col(ColumnDef::new(Column::Published).big_integer())
+        .col(ColumnDef::new(Column::Duration).big_integer().not_null())
+        .col(ColumnDef::new(Column::Played).boolean().not_null())
+        .to_owned();
+
+    create_table(db, &stmt).await
+}
+
+impl From<Episode> for ActiveModel {
+    fn from(e: Episode) -> ActiveModel {
+        ActiveModel {
+            source: Set(e.song.source.map(Into::into).unwrap_or_default()),
+            podcast: Set(e.podcast),
+            title: Set(e.song.title),
+            artist: Set(e.song.artist),
+            album: Set(e.song.album),
+            published: Set(e.published.map(|date| date.timestamp())),
+            duration: Set(e.song.duration.as_millis() as i64),
+            played: Set(e.played),
+        }
+    }
+}
+
+/// Not downloaded, that's not known to the database
+impl From<Model> for Episode {
+    fn from(m: Model) -> Episode {
+        let url = m.source.strip_prefix("enclosure:").unwrap_or(&m.source);
+        let format = audio_format("", url);
+        Episode {
+            song: Song {
+                title: m.title,
+                artist: m.artist,
+                album: m.album,
+                genre: Some(PODCAST_GENRE.to_string()),
+                source: Some(m.source.into()),
+                duration: Duration::from_millis(m.duration as u64),
+                format,
+                ..Default::default()
+            },
+            podcast: m.podcast,
+            published: (m.published).and_then(|seconds| Local.timestamp_opt(seconds, 0).single()),
+            played: m.played,
+            downloaded: false,
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use sea_orm::sea_query::ColumnDef;
+use sea_orm::{error::*, sea_query, DbConn, ExecResult};
+
+use super::setup::create_table;
+
+use std::path::PathBuf;
+
+use crate::library::LibraryFile;
+use sea_orm::entity::*;
+use sea_orm::prelude::*;
+
+/// A file of the library as last scanned, to tell whether it changed since
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[sea_orm(table_name = "files")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub path: String,
+    pub size: i64,     // in bytes
+    pub modified: i64, // in nanoseconds since the epoch
+    pub hash: String,  // of the content
+    pub missing: bool, // not found by the last scan, its songs are kept but not listed
+}
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+/// Also run on databases created before the scans were incremental
+pub async fn create_file_table(db: &DbConn) -> Result<ExecResult, DbErr> {
+    let stmt = sea_query::Table::create()
+        .table(Entity)
+        .if_not_exists()
+        .col(
+            ColumnDef::new(Column::Path)
+                .string()
+                .not_null()
+                .primary_key(),
+        )
+        .col(ColumnDef::new(Column::Size).big_integer().not_null())
+        .col(ColumnDef::new(Column::Modified).big_integer().not_null())
+        .col(ColumnDef::new(Column::Hash).string().not_null())
+        .col(ColumnDef::new(Column::Missing).boolean().not_null())
+        .to_owned();
+
+    create_table(db, &stmt).await
+}
+
+impl From<LibraryFile> for ActiveModel {
+    fn from(f: LibraryFile) -> ActiveModel {
+        ActiveModel {
+            path: Set(f.path.to_string_lossy().into_owned()),
+            size: Set(f.size as i64),
+            modified: Set(f.modified),
+            hash: Set(f.hash),
+            missing: Set(f.missing),
+        }
+    }
+}
+
+impl From<Model> for LibraryFile {
+    fn from(m: Model) -> LibraryFile {
+        LibraryFile {
+            path: PathBuf::from(m.path),
+            size: m.size as u64,
+            modified: m.modified,
+            hash: m.hash,
+            missing: m.missing,
+        }
+    }
+}
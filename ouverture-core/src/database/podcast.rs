@@ -0,0 +1,64 @@
+use sea_orm::sea_query::ColumnDef;
+use sea_orm::{error::*, sea_query, DbConn, ExecResult};
+
+use super::setup::create_table;
+
+use crate::podcast::Podcast;
+use sea_orm::entity::*;
+use sea_orm::prelude::*;
+
+/// A podcast subscription
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[sea_orm(table_name = "podcasts")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub url: String, // of the feed, or its path
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub auto_download: bool,
+}
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+/// Also run on databases created before podcasts existed
+pub async fn create_podcast_table(db: &DbConn) -> Result<ExecResult, DbErr> {
+    let stmt = sea_query::Table::create()
+        .table(Entity)
+        .if_not_exists()
+        .col(
+            ColumnDef::new(Column::Url)
+                .string()
+                .not_null()
+                .primary_key(),
+        )
+        .col(ColumnDef::new(Column::Title).string())
+        .col(ColumnDef::new(Column::Author).string())
+        .col(ColumnDef::new(Column::AutoDownload).boolean().not_null())
+        .to_owned();
+
+    create_table(db, &stmt).await
+}
+
+impl From<Podcast> for ActiveModel {
+    fn from(p: Podcast) -> ActiveModel {
+        ActiveModel {
+            url: Set(p.url),
+            title: Set(p.title),
+            author: Set(p.author),
+            auto_download: Set(p.auto_download),
+        }
+    }
+}
+
+impl From<Model> for Podcast {
+    fn from(m: Model) -> Podcast {
+        Podcast {
+            url: m.url,
+            title: m.title,
+            author: m.author,
+            auto_download: m.auto_download,
+        }
+    }
+}
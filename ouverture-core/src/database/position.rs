@@ -0,0 +1,36 @@
+use sea_orm::sea_query::ColumnDef;
+use sea_orm::{error::*, sea_query, DbConn, ExecResult};
+
+use super::setup::create_table;
+
+use sea_orm::prelude::*;
+
+/// Where playback of a song was left, to resume from there
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[sea_orm(table_name = "positions")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub source: String, // as stored in the songs table
+    pub position: i64, // in milliseconds
+}
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+/// Also run on databases created before resume positions existed
+pub async fn create_position_table(db: &DbConn) -> Result<ExecResult, DbErr> {
+    let stmt = sea_query::Table::create()
+        .table(Entity)
+        .if_not_exists()
+        .col(
+            ColumnDef::new(Column::Source)
+                .string()
+                .not_null()
+                .primary_key(),
+        )
+        .col(ColumnDef::new(Column::Position).big_integer().not_null())
+        .to_owned();
+
+    create_table(db, &stmt).await
+}
@@ -0,0 +1,69 @@
+use sea_orm::sea_query::ColumnDef;
+use sea_orm::{error::*, sea_query, DbConn, ExecResult};
+
+use super::setup::create_table;
+
+use crate::schedule::Schedule;
+use sea_orm::entity::*;
+use sea_orm::prelude::*;
+
+use std::time::Duration;
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[sea_orm(table_name = "schedules")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    pub recurrence: String, // 'once:<time>' or 'cron:<expression>'
+    pub target: String,     // 'query:<query>' or 'playlist:<path>'
+    pub ramp_up: i64,       // in milliseconds
+}
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+/// Also run on databases created before schedules existed
+pub async fn create_schedule_table(db: &DbConn) -> Result<ExecResult, DbErr> {
+    let stmt = sea_query::Table::create()
+        .table(Entity)
+        .if_not_exists()
+        .col(
+            ColumnDef::new(Column::Id)
+                .integer()
+                .not_null()
+                .auto_increment()
+                .primary_key(),
+        )
+        .col(ColumnDef::new(Column::Recurrence).string().not_null())
+        .col(ColumnDef::new(Column::Target).string().not_null())
+        .col(ColumnDef::new(Column::RampUp).big_integer().not_null())
+        .to_owned();
+
+    create_table(db, &stmt).await
+}
+
+/// Convert a schedule into an insertable Model, the database picks the id
+impl From<Schedule> for ActiveModel {
+    fn from(s: Schedule) -> ActiveModel {
+        ActiveModel {
+            recurrence: Set(s.when.into()),
+            target: Set(s.what.into()),
+            ramp_up: Set(s.ramp_up.as_millis() as i64),
+            ..Default::default()
+        }
+    }
+}
+
+impl TryFrom<Model> for Schedule {
+    type Error = String;
+
+    fn try_from(m: Model) -> Result<Schedule, String> {
+        Ok(Schedule {
+            id: m.id,
+            when: m.recurrence.try_into()?,
+            what: m.target.try_into()?,
+            ramp_up: Duration::from_millis(m.ramp_up as u64),
+        })
+    }
+}
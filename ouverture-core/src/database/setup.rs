@@ -19,13 +19,18 @@ pub struct Model {
     pub album: Option<String>,
     pub source: Option<String>,
     pub duration: i64, // duration of the song in milliseconds
+    pub genre: Option<String>,
+    pub file: Option<String>, // of the library the song was scanned from, see the files table
 }
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {}
 
 impl ActiveModelBehavior for ActiveModel {}
 
-async fn create_table(db: &DbConn, stmt: &TableCreateStatement) -> Result<ExecResult, DbErr> {
+pub(super) async fn create_table(
+    db: &DbConn,
+    stmt: &TableCreateStatement,
+) -> Result<ExecResult, DbErr> {
     let builder = db.get_database_backend();
     db.execute(builder.build(stmt)).await
 }
@@ -46,11 +51,48 @@ pub async fn create_post_table(db: &DbConn) -> Result<ExecResult, DbErr> {
         .col(ColumnDef::new(Column::Album).string())
         .col(ColumnDef::new(Column::Source).string())
         .col(ColumnDef::new(Column::Duration).big_integer())
+        .col(ColumnDef::new(Column::Genre).string())
         .to_owned();
 
     create_table(db, &stmt).await
 }
 
+/// For databases created before songs had a genre
+pub async fn add_genre_column(db: &DbConn) -> Result<ExecResult, DbErr> {
+    let builder = db.get_database_backend();
+    db.execute(Statement::from_string(
+        builder,
+        "ALTER TABLE songs ADD COLUMN IF NOT EXISTS genre varchar".to_string(),
+    ))
+    .await
+}
+
+/// For databases created before the scans were incremental
+pub async fn add_file_column(db: &DbConn) -> Result<ExecResult, DbErr> {
+    let builder = db.get_database_backend();
+    db.execute(Statement::from_string(
+        builder,
+        "ALTER TABLE songs ADD COLUMN IF NOT EXISTS file varchar".to_string(),
+    ))
+    .await
+}
+
+/// A source is in the library once: drop the duplicates the scans used to insert,
+/// keeping the first of each, and have the database refuse new ones
+pub async fn make_sources_unique(db: &DbConn) -> Result<ExecResult, DbErr> {
+    let builder = db.get_database_backend();
+    db.execute(Statement::from_string(
+        builder,
+        "DELETE FROM songs a USING songs b WHERE a.source = b.source AND a.id > b.id".to_string(),
+    ))
+    .await?;
+    db.execute(Statement::from_string(
+        builder,
+        "CREATE UNIQUE INDEX IF NOT EXISTS songs_source ON songs (source)".to_string(),
+    ))
+    .await
+}
+
 /// Convert a song into an insertable Model
 impl From<Song> for ActiveModel {
     fn from(s: Song) -> ActiveModel {
@@ -63,6 +105,7 @@ impl From<Song> for ActiveModel {
                 Some(source) => Some(source.into()),
             }),
             duration: Set(s.duration.as_millis() as i64),
+            genre: Set(s.genre),
             ..Default::default()
         }
     }
@@ -74,6 +117,7 @@ impl From<Model> for Song {
             title: a.title.into(),
             artist: a.artist.into(),
             album: a.album.into(),
+            genre: a.genre,
             source: match a.source {
                 None => None,
                 Some(source) => Some(source.into()),
@@ -0,0 +1,36 @@
+use sea_orm::sea_query::ColumnDef;
+use sea_orm::{error::*, sea_query, DbConn, ExecResult};
+
+use super::setup::create_table;
+
+use sea_orm::prelude::*;
+
+/// Waveform overview of a song, see audio::Waveform
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[sea_orm(table_name = "waveforms")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub source: String, // as stored in the songs table
+    pub summary: Vec<u8>,
+}
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+/// Also run on databases created before waveforms existed
+pub async fn create_waveform_table(db: &DbConn) -> Result<ExecResult, DbErr> {
+    let stmt = sea_query::Table::create()
+        .table(Entity)
+        .if_not_exists()
+        .col(
+            ColumnDef::new(Column::Source)
+                .string()
+                .not_null()
+                .primary_key(),
+        )
+        .col(ColumnDef::new(Column::Summary).binary().not_null())
+        .to_owned();
+
+    create_table(db, &stmt).await
+}
@@ -0,0 +1,468 @@
+//! Background downloads of online songs into the library, and of podcast episodes
+//!
+//! Jobs are queued by the clients (and the podcast refreshes) and run a few at a time (`download_concurrency`): the
+//! audio is fetched by the resolver command, tagged with what's known of it online, imported
+//! as any file of the library, and linked to the online source so that it's not fetched again.
+//! Failed downloads are tried again (`download_retries`), and every change of a job, progress
+//! included, is pushed to the subscribed clients. Episodes are only downloaded to
+//! `podcast_dir`, where they are played from.
+
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use audiotags::Tag;
+use tokio::sync::{broadcast, Notify, Semaphore};
+
+use crate::config::Config;
+use crate::database::{add_db, get_download, save_download};
+use crate::music::song::{Song, SongSource};
+use crate::podcast::{downloaded_episode, fetch_enclosure};
+use crate::resolver::{CommandResolver, Fetched};
+use crate::server::ServerEvent;
+
+use log::{debug, info, warn};
+
+// jobs that are over, kept to be listed
+const FINISHED_KEPT: usize = 20;
+// between attempts, times the attempts made
+const RETRY_DELAY_SECS: u64 = 5;
+// progress changes smaller than that aren't pushed to the clients
+const PROGRESS_STEP: f32 = 0.01;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Download {
+    pub id: u32,
+    pub song: Song, // the online song
+    pub state: DownloadState,
+    pub progress: f32, // fraction downloaded, of the current attempt
+    pub attempts: u32,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub enum DownloadState {
+    #[default]
+    Queued,
+    Running,
+    Done(Song), // as imported into the library
+    Failed(String),
+    Cancelled,
+}
+
+impl Download {
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self.state,
+            DownloadState::Done(_) | DownloadState::Failed(_) | DownloadState::Cancelled
+        )
+    }
+}
+
+struct Job {
+    download: Download,
+    cancelled: Arc<AtomicBool>,
+}
+
+/// The jobs, in the order they were queued
+#[derive(Default)]
+pub struct DownloadQueue {
+    jobs: Vec<Job>,
+    next_id: u32,
+}
+
+impl DownloadQueue {
+    pub fn add(&mut self, song: Song) -> Download {
+        self.next_id += 1;
+        let download = Download {
+            id: self.next_id,
+            song,
+            ..Default::default()
+        };
+        self.jobs.push(Job {
+            download: download.clone(),
+            cancelled: Arc::new(AtomicBool::new(false)),
+        });
+        download
+    }
+
+    pub fn list(&self) -> Vec<Download> {
+        self.jobs.iter().map(|job| job.download.clone()).collect()
+    }
+
+    /// Cancel a job, at once if it's queued, or when its command is stopped if it's running.
+    /// Returns the job if it changed
+    pub fn cancel(&mut self, id: u32) -> Option<Download> {
+        let job = self.jobs.iter_mut().find(|job| job.download.id == id)?;
+        match job.download.state {
+            DownloadState::Queued => {
+                job.download.state = DownloadState::Cancelled;
+                Some(job.download.clone())
+            }
+            DownloadState::Running => {
+                job.cancelled.store(true, Ordering::Relaxed);
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Stop all the running commands, e.g. when the server stops
+    pub fn cancel_all(&mut self) {
+        for job in &self.jobs {
+            job.cancelled.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Mark the first queued job as running, and return it
+    fn start_next(&mut self) -> Option<(Download, Arc<AtomicBool>)> {
+        let job = (self.jobs.iter_mut())
+            .find(|job| matches!(job.download.state, DownloadState::Queued))?;
+        job.download.state = DownloadState::Running;
+        Some((job.download.clone(), job.cancelled.clone()))
+    }
+
+    /// Change a job, and return it as changed
+    fn update(&mut self, id: u32, change: impl FnOnce(&mut Download)) -> Option<Download> {
+        let job = self.jobs.iter_mut().find(|job| job.download.id == id)?;
+        change(&mut job.download);
+        let download = job.download.clone();
+        // forget the oldest jobs that are over
+        let finished = self
+            .jobs
+            .iter()
+            .filter(|j| j.download.is_finished())
+            .count();
+        let mut to_forget = finished.saturating_sub(FINISHED_KEPT);
+        self.jobs.retain(|job| {
+            let forget = to_forget > 0 && job.download.is_finished();
+            to_forget -= forget as usize;
+            !forget
+        });
+        Some(download)
+    }
+}
+
+/// Queue the download of a song, to be started by the runner
+pub fn queue_download(
+    queue: &Mutex<DownloadQueue>,
+    changed: &Notify,
+    events: &broadcast::Sender<ServerEvent>,
+    song: Song,
+) -> Download {
+    let download = queue.lock().unwrap().add(song);
+    info!("download {} queued", download.id);
+    let _ = events.send(ServerEvent::Download(Box::new(download.clone())));
+    changed.notify_one();
+    download
+}
+
+/// Start the queued jobs whenever a job is added or over, until aborted
+pub async fn run_downloads(
+    config: Config,
+    queue: Arc<Mutex<DownloadQueue>>,
+    changed: Arc<Notify>,
+    events: broadcast::Sender<ServerEvent>,
+) {
+    let slots = Arc::new(Semaphore::new(config.download_concurrency.max(1)));
+    loop {
+        while let Ok(slot) = slots.clone().try_acquire_owned() {
+            let Some((download, cancelled)) = queue.lock().unwrap().start_next() else {
+                break;
+            };
+            let _ = events.send(ServerEvent::Download(Box::new(download.clone())));
+            let (config, queue, changed, events) = (
+                config.clone(),
+                queue.clone(),
+                changed.clone(),
+                events.clone(),
+            );
+            tokio::spawn(async move {
+                run_job(&config, &queue, download, cancelled, &events).await;
+                drop(slot);
+                changed.notify_one();
+            });
+        }
+        changed.notified().await;
+    }
+}
+
+async fn run_job(
+    config: &Config,
+    queue: &Arc<Mutex<DownloadQueue>>,
+    download: Download,
+    cancelled: Arc<AtomicBool>,
+    events: &broadcast::Sender<ServerEvent>,
+) {
+    let id = download.id;
+    let update = |change: &dyn Fn(&mut Download)| {
+        if let Some(download) = queue.lock().unwrap().update(id, change) {
+            let _ = events.send(ServerEvent::Download(Box::new(download)));
+        }
+    };
+
+    let (url, is_episode) = match download.song.source.clone() {
+        Some(SongSource::YoutubeUrl(url)) => (url, false),
+        Some(SongSource::EnclosureUrl(url)) => (url, true),
+        source => {
+            let reason = format!("can't download {source:?}");
+            return update(&|d| d.state = DownloadState::Failed(reason.clone()));
+        }
+    };
+    let source = download.song.source.clone().unwrap_or(SongSource::Unknown);
+    let source_key: String = source.clone().into();
+
+    // downloaded before
+    let before = if is_episode {
+        downloaded_episode(config, &url).map(|_| download.song.clone())
+    } else {
+        match get_download(config, source_key.clone()).await {
+            Ok(Some(path)) if path.is_file() => {
+                let file = path.clone();
+                match tokio::task::spawn_blocking(move || Song::from_path(&file)).await {
+                    Ok(song) => Some(song),
+                    Err(e) => {
+                        let reason = format!("failed to read {path:?}: {e}");
+                        warn!("{reason}");
+                        return update(&|d| d.state = DownloadState::Failed(reason.clone()));
+                    }
+                }
+            }
+            Ok(_) => None,
+            Err(e) => {
+                warn!("failed to find whether {url} was downloaded before: {e:?}");
+                None
+            }
+        }
+    };
+    if let Some(song) = before {
+        info!("{url} was downloaded before");
+        return update(&|d| {
+            d.progress = 1.0;
+            d.state = DownloadState::Done(song.clone());
+        });
+    }
+
+    let max_attempts = config.download_retries + 1;
+    let mut attempts = 0;
+    let result = loop {
+        attempts += 1;
+        update(&|d| {
+            d.attempts = attempts;
+            d.progress = 0.0;
+        });
+        let result = fetch(config, &source, queue, id, &cancelled, events).await;
+        match result {
+            // the resolver finds the episodes where they were downloaded
+            Ok(_) if is_episode => break Ok(download.song.clone()),
+            Ok(fetched) => break import(config, &source_key, fetched).await,
+            Err(e) if cancelled.load(Ordering::Relaxed) => break Err(e),
+            Err(e) if attempts < max_attempts => {
+                warn!("failed to download {url} (attempt {attempts}/{max_attempts}): {e}");
+                tokio::time::sleep(Duration::from_secs(RETRY_DELAY_SECS * attempts as u64)).await;
+                if cancelled.load(Ordering::Relaxed) {
+                    break Err(e);
+                }
+            }
+            Err(e) => break Err(e),
+        }
+    };
+
+    let state = match result {
+        Ok(song) => {
+            info!("{url} downloaded");
+            DownloadState::Done(song)
+        }
+        Err(_) if cancelled.load(Ordering::Relaxed) => {
+            info!("download of {url} cancelled");
+            DownloadState::Cancelled
+        }
+        Err(e) => {
+            warn!("failed to download {url}: {e}");
+            DownloadState::Failed(e)
+        }
+    };
+    update(&|d| d.state = state.clone());
+}
+
+/// Run the resolver command, or download the episode, in a blocking thread,
+/// reporting its progress
+async fn fetch(
+    config: &Config,
+    source: &SongSource,
+    queue: &Arc<Mutex<DownloadQueue>>,
+    id: u32,
+    cancelled: &Arc<AtomicBool>,
+    events: &broadcast::Sender<ServerEvent>,
+) -> Result<Fetched, String> {
+    let resolver = CommandResolver::new(config);
+    let (download_dir, podcast_dir) = (config.download_dir.clone(), config.podcast_dir.clone());
+    let (source, queue, cancelled, events) = (
+        source.clone(),
+        queue.clone(),
+        cancelled.clone(),
+        events.clone(),
+    );
+    tokio::task::spawn_blocking(move || {
+        let mut reported = 0.0;
+        let mut progress = |fraction: f32| {
+            if (fraction - reported).abs() < PROGRESS_STEP && fraction < 1.0 {
+                return;
+            }
+            reported = fraction;
+            if let Some(download) = queue.lock().unwrap().update(id, |d| d.progress = fraction) {
+                let _ = events.send(ServerEvent::Download(Box::new(download)));
+            }
+        };
+        match source {
+            SongSource::YoutubeUrl(url) => {
+                resolver.fetch(&url, &download_dir, &mut progress, &cancelled)
+            }
+            SongSource::EnclosureUrl(url) => {
+                fetch_enclosure(&url, &podcast_dir, &mut progress, &cancelled).map(|path| Fetched {
+                    path,
+                    title: None,
+                    artist: None,
+                })
+            }
+            source => Err(format!("can't download {source:?}")),
+        }
+    })
+    .await
+    .map_err(|e| format!("the download of {id} stopped: {e}"))?
+}
+
+/// Tag the file with what's known of it online, add it to the library,
+/// and link it to its online source
+async fn import(config: &Config, source: &str, fetched: Fetched) -> Result<Song, String> {
+    let path = fetched.path.clone();
+    let song = tokio::task::spawn_blocking(move || {
+        tag(&fetched)?;
+        Ok::<_, String>(Song::from_path(&fetched.path))
+    })
+    .await
+    .map_err(|e| format!("failed to import {path:?}: {e}"))??;
+    debug!("importing {song:?}");
+    add_db(config, song.clone())
+        .await
+        .map_err(|e| format!("failed to add {path:?} to the library: {e}"))?;
+    if let Err(e) = save_download(config, source.to_string(), &path).await {
+        warn!("failed to link {path:?} to {source}: {e:?}");
+    }
+    Ok(song)
+}
+
+/// Fill the title and artist the file lacks
+fn tag(fetched: &Fetched) -> Result<(), String> {
+    let path = &fetched.path;
+    let mut tag = (Tag::new().read_from_path(path))
+        .map_err(|e| format!("failed to read the tags of {path:?}: {e}"))?;
+    let mut changed = false;
+    if let (None, Some(title)) = (tag.title(), &fetched.title) {
+        tag.set_title(title);
+        changed = true;
+    }
+    if let (None, Some(artist)) = (tag.artist(), &fetched.artist) {
+        tag.set_artist(artist);
+        changed = true;
+    }
+    if changed {
+        (tag.write_to_path(&path.to_string_lossy()))
+            .map_err(|e| format!("failed to tag {path:?}: {e}"))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song(title: &str) -> Song {
+        Song {
+            title: Some(title.to_string()),
+            source: Some(SongSource::YoutubeUrl(format!("https://youtu.be/{title}"))),
+            ..Default::default()
+        }
+    }
+
+    fn states(queue: &DownloadQueue) -> Vec<(u32, String)> {
+        (queue.list().into_iter())
+            .map(|download| (download.id, format!("{:?}", download.state)))
+            .collect()
+    }
+
+    #[test]
+    fn jobs_start_in_order() {
+        let mut queue = DownloadQueue::default();
+        let first = queue.add(song("a"));
+        let second = queue.add(song("b"));
+        assert_eq!((first.id, second.id), (1, 2));
+        assert!(matches!(first.state, DownloadState::Queued));
+
+        let (running, cancelled) = queue.start_next().unwrap();
+        assert_eq!(running.id, 1);
+        assert!(matches!(running.state, DownloadState::Running));
+        assert!(!cancelled.load(Ordering::Relaxed));
+        assert_eq!(queue.start_next().unwrap().0.id, 2);
+        assert!(queue.start_next().is_none());
+    }
+
+    #[test]
+    fn cancelled_jobs() {
+        let mut queue = DownloadQueue::default();
+        for title in ["a", "b", "c"] {
+            queue.add(song(title));
+        }
+        let (_, running_cancelled) = queue.start_next().unwrap();
+
+        // queued: at once, and it's not started
+        let cancelled = queue.cancel(2).unwrap();
+        assert!(matches!(cancelled.state, DownloadState::Cancelled));
+        assert!(queue.cancel(2).is_none());
+        assert_eq!(queue.start_next().unwrap().0.id, 3);
+
+        // running: when its command sees it
+        assert!(queue.cancel(1).is_none());
+        assert!(running_cancelled.load(Ordering::Relaxed));
+        assert!(matches!(queue.list()[0].state, DownloadState::Running));
+
+        assert!(queue.cancel(4).is_none());
+    }
+
+    #[test]
+    fn all_cancelled_at_exit() {
+        let mut queue = DownloadQueue::default();
+        queue.add(song("a"));
+        queue.add(song("b"));
+        let flags = [queue.start_next().unwrap().1, queue.start_next().unwrap().1];
+        queue.cancel_all();
+        assert!(flags.iter().all(|flag| flag.load(Ordering::Relaxed)));
+    }
+
+    #[test]
+    fn update_changes_one_job() {
+        let mut queue = DownloadQueue::default();
+        queue.add(song("a"));
+        queue.add(song("b"));
+        let updated = queue.update(2, |d| d.progress = 0.5).unwrap();
+        assert_eq!((updated.id, updated.progress), (2, 0.5));
+        assert_eq!(queue.list()[0].progress, 0.0);
+        assert!(queue.update(3, |d| d.progress = 0.5).is_none());
+    }
+
+    #[test]
+    fn oldest_finished_jobs_are_forgotten() {
+        let mut queue = DownloadQueue::default();
+        let unfinished = queue.add(song("kept")).id;
+        for i in 0..FINISHED_KEPT + 3 {
+            let id = queue.add(song(&i.to_string())).id;
+            queue.update(id, |d| d.state = DownloadState::Failed("gone".to_string()));
+        }
+        let listed = states(&queue);
+        assert_eq!(listed.len(), FINISHED_KEPT + 1);
+        // the unfinished one, then the last finished ones
+        assert_eq!(listed[0], (unfinished, "Queued".to_string()));
+        assert_eq!(listed[1].0, unfinished + 4);
+        assert_eq!(listed.last().unwrap().0, queue.next_id);
+    }
+}
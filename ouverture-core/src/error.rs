@@ -2,7 +2,7 @@ use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum ServerError {
-    #[error("message too big (maximum size is 64k)")]
+    #[error("message too big (maximum size is 64M)")]
     MessageTooBig,
     #[error("not native protocol")]
     NotNativeProtocol,
@@ -2,10 +2,14 @@ pub mod api_router;
 pub mod audio;
 pub mod config;
 pub mod database;
+pub mod download;
 pub mod error;
 pub mod library;
 pub mod logger;
 pub mod music;
+pub mod podcast;
+pub mod resolver;
+pub mod schedule;
 pub mod server;
 
 use config::Config;
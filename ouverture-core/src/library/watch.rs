@@ -0,0 +1,154 @@
+//! Watching the library directories, to update the library as files come and go
+//!
+//! The events (inotify on Linux) come in bursts, an album being copied makes several per
+//! file: the paths they name are gathered until the directories are quiet for
+//! `QUIET_MS`, or for at most `MAX_WAIT_MS` while they keep changing, and then updated at
+//! once. Files renamed or moved within the library are found where they were, so their
+//! songs keep their ids and what's saved about them.
+
+use notify::{Event, RecursiveMode, Watcher};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+use super::update;
+use crate::config::Config;
+
+use log::{debug, info, warn};
+
+// without events for that long, the changes gathered are updated
+const QUIET_MS: u64 = 2_000;
+// the longest the changes gathered wait for the directories to be quiet
+const MAX_WAIT_MS: u64 = 30_000;
+
+/// Watch the library directories and update the library as they change, until aborted
+pub async fn run_watcher(config: Config) {
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let handler = move |event: notify::Result<Event>| {
+        let _ = sender.send(event);
+    };
+    let mut watcher = match notify::recommended_watcher(handler) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            warn!("failed to watch the library: {e}");
+            return;
+        }
+    };
+    for dir in &config.library {
+        match watcher.watch(dir, RecursiveMode::Recursive) {
+            Ok(()) => info!("watching {dir:?}"),
+            Err(e) => warn!("failed to watch {dir:?}: {e}"),
+        }
+    }
+
+    while let Some(changed) = next_changes(&mut receiver).await {
+        debug!("library changed: {changed:?}");
+        update(&config, changed.into_iter().collect()).await;
+    }
+}
+
+/// The paths named by the next burst of events, once it's over or has lasted `MAX_WAIT_MS`.
+/// None when the watcher is gone
+async fn next_changes(
+    receiver: &mut mpsc::UnboundedReceiver<notify::Result<Event>>,
+) -> Option<HashSet<PathBuf>> {
+    let mut changed = HashSet::new();
+    gather(&mut changed, receiver.recv().await?);
+    let deadline = Instant::now() + Duration::from_millis(MAX_WAIT_MS);
+    loop {
+        tokio::select! {
+            event = receiver.recv() => match event {
+                Some(event) => gather(&mut changed, event),
+                None => break,
+            },
+            _ = tokio::time::sleep(Duration::from_millis(QUIET_MS)) => break,
+            _ = tokio::time::sleep_until(deadline) => break,
+        }
+    }
+    Some(changed)
+}
+
+fn gather(changed: &mut HashSet<PathBuf>, event: notify::Result<Event>) {
+    match event {
+        Ok(event) => changed.extend(event.paths),
+        Err(e) => warn!("failed to watch the library: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::EventKind;
+
+    fn event(path: &str) -> notify::Result<Event> {
+        Ok(Event::new(EventKind::Any).add_path(PathBuf::from(path)))
+    }
+
+    fn paths(names: &[&str]) -> HashSet<PathBuf> {
+        names.iter().map(PathBuf::from).collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn burst_updated_once_quiet() {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let start = Instant::now();
+        tokio::spawn(async move {
+            for path in ["/music/a.mp3", "/music/b.mp3", "/music/a.mp3"] {
+                sender.send(event(path)).unwrap();
+                tokio::time::sleep(Duration::from_millis(QUIET_MS / 2)).await;
+            }
+            // after a quiet while
+            tokio::time::sleep(Duration::from_millis(QUIET_MS * 2)).await;
+            sender.send(event("/music/c.mp3")).unwrap();
+        });
+
+        let changed = next_changes(&mut receiver).await.unwrap();
+        assert_eq!(changed, paths(&["/music/a.mp3", "/music/b.mp3"]));
+        // the last event, then quiet
+        assert_eq!(start.elapsed(), Duration::from_millis(QUIET_MS * 2));
+
+        let changed = next_changes(&mut receiver).await.unwrap();
+        assert_eq!(changed, paths(&["/music/c.mp3"]));
+        // the watcher is gone
+        assert_eq!(next_changes(&mut receiver).await, None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn endless_changes_updated_at_last() {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let start = Instant::now();
+        // often enough for the directories never to be quiet, and not on the deadline
+        let every = QUIET_MS / 3;
+        let sending = tokio::spawn(async move {
+            for i in 0.. {
+                if sender.send(event(&format!("/music/{i}.mp3"))).is_err() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(every)).await;
+            }
+        });
+
+        let changed = next_changes(&mut receiver).await.unwrap();
+        assert_eq!(start.elapsed(), Duration::from_millis(MAX_WAIT_MS));
+        assert_eq!(changed.len() as u64, MAX_WAIT_MS.div_ceil(every));
+        // the next ones are gathered from there
+        let changed = next_changes(&mut receiver).await.unwrap();
+        assert!(!changed.contains(&PathBuf::from("/music/0.mp3")));
+        assert_eq!(changed.len() as u64, MAX_WAIT_MS.div_ceil(every));
+        sending.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn watch_errors_are_skipped() {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        sender
+            .send(Err(notify::Error::generic("overflow")))
+            .unwrap();
+        sender.send(event("/music/a.mp3")).unwrap();
+        drop(sender);
+        let changed = next_changes(&mut receiver).await.unwrap();
+        assert_eq!(changed, paths(&["/music/a.mp3"]));
+    }
+}
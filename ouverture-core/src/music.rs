@@ -1,2 +1,3 @@
+pub mod cue;
 pub mod playlist;
 pub mod song;
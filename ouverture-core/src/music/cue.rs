@@ -0,0 +1,287 @@
+//! CUE sheets, describing the songs held in single-file images of whole albums
+//!
+//! Only what's needed to split the images is read: the FILE, TRACK, TITLE,
+//! PERFORMER, `REM GENRE` and `INDEX 01` commands. A song starts at its `INDEX 01`
+//! and ends where the next song of the same file starts, so that the pregaps
+//! (`INDEX 00`) are played at the end of the previous song, as on the original disc.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::song::{AudioFormat, Song, SongSource};
+
+// CUE sheet times are in minutes, seconds and frames of a CD
+const FRAMES_PER_SECOND: u64 = 75;
+
+#[derive(Clone, Debug, Default)]
+pub struct CueSheet {
+    pub title: Option<String>, // of the album
+    pub performer: Option<String>,
+    pub genre: Option<String>,
+    pub files: Vec<CueFile>,
+}
+
+/// An image and the songs it holds
+#[derive(Clone, Debug, Default)]
+pub struct CueFile {
+    pub path: PathBuf,
+    pub tracks: Vec<CueTrack>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CueTrack {
+    pub number: u32,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub start_ms: Option<u64>, // INDEX 01
+}
+
+impl CueSheet {
+    /// Read a CUE sheet, with the paths of the images relative to its directory
+    pub fn from_path(path: &Path) -> std::io::Result<CueSheet> {
+        // CUE sheets often aren't UTF-8, keep what can be read
+        let text = String::from_utf8_lossy(&std::fs::read(path)?).into_owned();
+        let dir = path.parent().unwrap_or(Path::new(""));
+        Ok(CueSheet::parse(&text, dir))
+    }
+
+    pub fn parse(text: &str, dir: &Path) -> CueSheet {
+        let mut sheet = CueSheet::default();
+        for line in text.trim_start_matches('\u{feff}').lines() {
+            let line = line.trim();
+            let (command, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let args = args.trim();
+            let file = sheet.files.last_mut();
+            let track = file.and_then(|file| file.tracks.last_mut());
+            match (command.to_uppercase().as_str(), track) {
+                ("FILE", _) => {
+                    // the file type comes last, the name may be unquoted and hold spaces
+                    let name = match args.rsplit_once(char::is_whitespace) {
+                        Some((name, _)) => name.trim(),
+                        None => args,
+                    };
+                    sheet.files.push(CueFile {
+                        path: dir.join(unquote(name)),
+                        tracks: vec![],
+                    });
+                }
+                ("TRACK", _) => {
+                    let number = args.split_whitespace().next().and_then(|n| n.parse().ok());
+                    if let Some(file) = sheet.files.last_mut() {
+                        file.tracks.push(CueTrack {
+                            number: number.unwrap_or(file.tracks.len() as u32 + 1),
+                            ..Default::default()
+                        });
+                    }
+                }
+                ("TITLE", Some(track)) => track.title = Some(unquote(args)),
+                ("TITLE", None) => sheet.title = Some(unquote(args)),
+                ("PERFORMER", Some(track)) => track.performer = Some(unquote(args)),
+                ("PERFORMER", None) => sheet.performer = Some(unquote(args)),
+                ("INDEX", Some(track)) => {
+                    if let Some(("01", time)) = args.split_once(char::is_whitespace) {
+                        track.start_ms = parse_time(time.trim());
+                    }
+                }
+                ("REM", _) => {
+                    if let Some(("GENRE", genre)) = args.split_once(char::is_whitespace) {
+                        sheet.genre = Some(unquote(genre.trim()));
+                    }
+                }
+                _ => (),
+            }
+        }
+        sheet
+    }
+
+    /// The songs of one of the sheet's images, which has the given format and duration.
+    /// Tracks without a start are left out
+    pub fn songs(
+        &self,
+        file: &CueFile,
+        format: AudioFormat,
+        file_duration: Option<Duration>,
+    ) -> Vec<Song> {
+        let tracks: Vec<(&CueTrack, u64)> = (file.tracks.iter())
+            .filter_map(|track| track.start_ms.map(|start| (track, start)))
+            .collect();
+        let ends = (tracks.iter().skip(1))
+            .map(|(_, start)| Some(*start))
+            .chain([None]);
+        tracks
+            .iter()
+            .zip(ends)
+            .map(|((track, start), end)| {
+                let end_ms = end.or(file_duration.map(|d| d.as_millis() as u64));
+                Song {
+                    artist: track.performer.clone().or(self.performer.clone()),
+                    album: self.title.clone(),
+                    title: Some(
+                        (track.title.clone()).unwrap_or(format!("Track {:02}", track.number)),
+                    ),
+                    genre: self.genre.clone(),
+                    source: Some(SongSource::FileRange(file.path.clone(), *start, end)),
+                    duration: Duration::from_millis(
+                        end_ms.unwrap_or(*start).saturating_sub(*start),
+                    ),
+                    format: format.clone(),
+                    ..Default::default()
+                }
+            })
+            .collect()
+    }
+}
+
+fn unquote(s: &str) -> String {
+    s.strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .unwrap_or(s)
+        .to_string()
+}
+
+/// mm:ss:ff in milliseconds
+fn parse_time(time: &str) -> Option<u64> {
+    let mut fields = time.split(':').map(|field| field.parse::<u64>().ok());
+    match (fields.next(), fields.next(), fields.next()) {
+        (Some(Some(minutes)), Some(Some(seconds)), Some(Some(frames))) => {
+            Some((minutes * 60 + seconds) * 1000 + frames * 1000 / FRAMES_PER_SECOND)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALBUM: &str = r#"REM GENRE "Jazz"
+REM DATE 1959
+PERFORMER "The Band"
+TITLE "Live at Home"
+FILE Live at Home.flac WAVE
+  TRACK 01 AUDIO
+    TITLE "Opening"
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    TITLE "Guest Spot"
+    PERFORMER "A Guest"
+    INDEX 00 03:58:00
+    INDEX 01 04:00:00
+  TRACK 03 AUDIO
+    INDEX 01 07:30:37
+"#;
+
+    fn range(song: &Song) -> (&Path, u64, Option<u64>) {
+        match &song.source {
+            Some(SongSource::FileRange(path, start, end)) => (path, *start, *end),
+            source => panic!("not a file range: {source:?}"),
+        }
+    }
+
+    #[test]
+    fn times() {
+        assert_eq!(parse_time("00:00:00"), Some(0));
+        assert_eq!(parse_time("04:00:00"), Some(240_000));
+        // 37 frames of a 75th of a second
+        assert_eq!(parse_time("07:30:37"), Some(450_493));
+        assert_eq!(parse_time("01:02:74"), Some(62_986));
+        for invalid in ["", "1:02", "a:00:00", "01:-2:00"] {
+            assert_eq!(parse_time(invalid), None, "{invalid}");
+        }
+    }
+
+    #[test]
+    fn sheet() {
+        let sheet = CueSheet::parse(ALBUM, Path::new("/music"));
+        assert_eq!(sheet.title.as_deref(), Some("Live at Home"));
+        assert_eq!(sheet.performer.as_deref(), Some("The Band"));
+        assert_eq!(sheet.genre.as_deref(), Some("Jazz"));
+        assert_eq!(sheet.files.len(), 1);
+        // unquoted, with spaces
+        assert_eq!(sheet.files[0].path, Path::new("/music/Live at Home.flac"));
+
+        let tracks = &sheet.files[0].tracks;
+        let numbers: Vec<_> = tracks.iter().map(|track| track.number).collect();
+        assert_eq!(numbers, [1, 2, 3]);
+        assert_eq!(tracks[1].title.as_deref(), Some("Guest Spot"));
+        assert_eq!(tracks[1].performer.as_deref(), Some("A Guest"));
+        // from INDEX 01, not from the pregap
+        assert_eq!(tracks[1].start_ms, Some(240_000));
+    }
+
+    #[test]
+    fn songs() {
+        let sheet = CueSheet::parse(ALBUM, Path::new("/music"));
+        let file = &sheet.files[0];
+        let songs = sheet.songs(file, AudioFormat::flac, Some(Duration::from_secs(600)));
+        assert_eq!(songs.len(), 3);
+
+        let path = Path::new("/music/Live at Home.flac");
+        // the pregap of the second track ends the first one
+        assert_eq!(range(&songs[0]), (path, 0, Some(240_000)));
+        assert_eq!(songs[0].duration, Duration::from_secs(240));
+        assert_eq!(range(&songs[1]), (path, 240_000, Some(450_493)));
+        // the last one ends with the file
+        assert_eq!(range(&songs[2]), (path, 450_493, None));
+        assert_eq!(songs[2].duration, Duration::from_millis(600_000 - 450_493));
+
+        // the track's own performer, else the album's
+        assert_eq!(songs[0].artist.as_deref(), Some("The Band"));
+        assert_eq!(songs[1].artist.as_deref(), Some("A Guest"));
+        assert_eq!(songs[0].title.as_deref(), Some("Opening"));
+        assert_eq!(songs[2].title.as_deref(), Some("Track 03"));
+        assert!(songs.iter().all(|song| {
+            song.album.as_deref() == Some("Live at Home")
+                && song.genre.as_deref() == Some("Jazz")
+                && song.format == AudioFormat::flac
+        }));
+
+        // unknown duration of the file
+        let songs = sheet.songs(file, AudioFormat::flac, None);
+        assert_eq!(songs[2].duration, Duration::ZERO);
+    }
+
+    #[test]
+    fn several_files() {
+        let text = "TITLE \"Double\"\n\
+            FILE \"CD1.wav\" WAVE\n\
+            TRACK 01 AUDIO\nINDEX 01 00:00:00\n\
+            TRACK 02 AUDIO\nTITLE \"No start\"\n\
+            TRACK 03 AUDIO\nINDEX 01 02:00:00\n\
+            FILE \"CD2.wav\" WAVE\n\
+            TRACK 04 AUDIO\nINDEX 01 00:00:00\n";
+        let sheet = CueSheet::parse(text, Path::new("albums"));
+        assert_eq!(sheet.files.len(), 2);
+        assert_eq!(sheet.files[1].path, Path::new("albums/CD2.wav"));
+
+        // each file's songs end with it, and tracks without a start are left out
+        let first = sheet.songs(&sheet.files[0], AudioFormat::wav, None);
+        let starts: Vec<_> = first.iter().map(|song| range(song).1).collect();
+        assert_eq!(starts, [0, 120_000]);
+        assert_eq!(range(&first[1]).2, None);
+        let second = sheet.songs(&sheet.files[1], AudioFormat::wav, None);
+        assert_eq!(second.len(), 1);
+        assert_eq!(second[0].title.as_deref(), Some("Track 04"));
+    }
+
+    #[test]
+    fn byte_order_mark() {
+        let sheet = CueSheet::parse(&format!("\u{feff}{ALBUM}"), Path::new(""));
+        // the first command, right after the mark, is read
+        assert_eq!(sheet.genre.as_deref(), Some("Jazz"));
+        assert_eq!(sheet.files[0].tracks.len(), 3);
+    }
+
+    #[test]
+    fn not_utf8() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("latin1.cue");
+        // "Café" in Latin-1
+        let text = b"PERFORMER \"Caf\xe9\"\r\nFILE \"album.flac\" WAVE\r\n  TRACK 01 AUDIO\r\n    INDEX 01 00:00:00\r\n";
+        std::fs::write(&path, text).unwrap();
+        let sheet = CueSheet::from_path(&path).unwrap();
+        assert_eq!(sheet.performer.as_deref(), Some("Caf\u{fffd}"));
+        assert_eq!(sheet.files[0].path, dir.path().join("album.flac"));
+        assert_eq!(sheet.files[0].tracks[0].start_ms, Some(0));
+    }
+}
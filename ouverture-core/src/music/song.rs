@@ -32,6 +32,8 @@ pub struct Song {
     pub album: Option<String>,
     /// Title of the song
     pub title: Option<String>,
+    /// Genre of the song
+    pub genre: Option<String>,
 
     /// Where to fetch the music data
     pub source: Option<SongSource>,
@@ -62,7 +64,14 @@ pub struct Song {
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum SongSource {
     FilePath(PathBuf),
+    /// Part of a file holding several songs (e.g. an image described by a CUE sheet),
+    /// from a start to an end in milliseconds, or to the end of the file if None
+    FileRange(PathBuf, u64, Option<u64>),
     YoutubeUrl(String),
+    /// HTTP(S) audio stream, e.g. an internet radio
+    StreamUrl(String),
+    /// Audio enclosed in a feed, e.g. a podcast episode, downloaded or streamed
+    EnclosureUrl(String),
     Unknown,
 }
 
@@ -71,7 +80,13 @@ impl Into<String> for SongSource {
     fn into(self) -> String {
         match self {
             FilePath(path) => String::from("path:") + path.to_str().unwrap(),
+            FileRange(path, start, end) => {
+                let end = end.map(|end| end.to_string()).unwrap_or_default();
+                format!("range:{start}:{end}:") + path.to_str().unwrap()
+            }
             YoutubeUrl(url) => String::from("yt_url:") + &url,
+            StreamUrl(url) => String::from("stream:") + &url,
+            EnclosureUrl(url) => String::from("enclosure:") + &url,
             Unknown => String::from("unknown"),
         }
     }
@@ -83,10 +98,29 @@ impl From<String> for SongSource {
         if s.starts_with(yt) {
             return YoutubeUrl(s.strip_prefix(yt).unwrap().into());
         }
+        let stream = "stream:";
+        if s.starts_with(stream) {
+            return StreamUrl(s.strip_prefix(stream).unwrap().into());
+        }
+        let enclosure = "enclosure:";
+        if s.starts_with(enclosure) {
+            return EnclosureUrl(s.strip_prefix(enclosure).unwrap().into());
+        }
         let path = "path:";
         if s.starts_with(path) {
             return FilePath(s.strip_prefix(path).unwrap().into());
         }
+        // range:<start>:<end, empty if none>:<path>
+        if let Some(range) = s.strip_prefix("range:") {
+            let mut fields = range.splitn(3, ':');
+            if let (Some(start), Some(end), Some(path)) =
+                (fields.next(), fields.next(), fields.next())
+            {
+                if let Ok(start) = start.parse() {
+                    return FileRange(path.into(), start, end.parse().ok());
+                }
+            }
+        }
 
         return Unknown;
     }
@@ -113,6 +147,7 @@ impl Default for Song {
             artist: None,
             album: None,
             title: None,
+            genre: None,
             source: None,
             duration: Duration::from_millis(0),
             added_date: Local::now(),
@@ -124,10 +159,9 @@ impl Default for Song {
     }
 }
 
-impl Song {
-    pub fn from_path(path: &Path) -> Song {
-        let tag = Tag::new().read_from_path(path).unwrap();
-
+impl AudioFormat {
+    /// Guess the format from the file's content
+    pub fn from_path(path: &Path) -> AudioFormat {
         let kind = infer::get_from_path(path)
             .expect("file type read successfully")
             .expect("file type known");
@@ -150,6 +184,53 @@ impl Song {
             // TODO reject the new song
             warn!("format {} is not suported", kind.mime_type());
         }
+        format
+    }
+}
+
+impl Song {
+    /// An HTTP(S) audio stream, e.g. an internet radio
+    pub fn from_stream_url(url: &str, title: Option<String>) -> Song {
+        Song {
+            source: Some(StreamUrl(url.to_string())),
+            title: title.or(Some(url.to_string())),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_stream_url(s: &str) -> bool {
+        s.starts_with("http://") || s.starts_with("https://")
+    }
+
+    /// A song on YouTube, by the URL of its page, played through a resolver
+    pub fn from_youtube_url(url: &str) -> Song {
+        Song {
+            source: Some(YoutubeUrl(url.to_string())),
+            title: Some(url.to_string()),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_youtube_url(s: &str) -> bool {
+        Song::is_stream_url(s)
+            && ["youtube.com/", "youtu.be/"]
+                .iter()
+                .any(|host| s.contains(host))
+    }
+
+    /// A podcast episode, by the URL of its audio, e.g. to mark it played
+    pub fn from_enclosure_url(url: &str) -> Song {
+        Song {
+            source: Some(EnclosureUrl(url.to_string())),
+            title: Some(url.to_string()),
+            ..Default::default()
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Song {
+        let tag = Tag::new().read_from_path(path).unwrap();
+        let format = AudioFormat::from_path(path);
+
         let duration;
         if let Some(d) = tag.duration() {
             duration = Duration::from_secs(d as u64);
@@ -165,6 +246,7 @@ impl Song {
             title: tag.title().map(|s| s.into()),
             artist: tag.artists().map(|v| v[0].to_string()),
             album: tag.album().map(|a| a.title.to_string()),
+            genre: tag.genre().map(|g| g.into()),
             duration,
             format,
 
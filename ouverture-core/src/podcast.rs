@@ -0,0 +1,246 @@
+//! Podcast subscriptions: RSS and Atom feeds, checked for new episodes every `podcast_refresh`
+//!
+//! The episodes are songs with an `EnclosureUrl` source, of the "Podcast" genre so that they
+//! resume where they were left (see `audio::resume`). They are streamed, or played from
+//! `podcast_dir` once downloaded there, which the download jobs do for the new episodes of the
+//! podcasts subscribed to with `auto_download` (only the last one on the first refresh, not the
+//! whole back catalog). An episode is marked played once played to its end.
+//!
+//! Feeds are read from HTTP(S) URLs or from local files, and subscriptions are imported and
+//! exported as OPML.
+
+pub mod feed;
+pub mod opml;
+
+use chrono::prelude::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{broadcast, Notify};
+
+use crate::config::Config;
+use crate::database::set_episode_played;
+use crate::database::{add_episodes, list_episodes, list_podcasts, save_podcast};
+use crate::download::{queue_download, DownloadQueue};
+use crate::music::song::{AudioFormat, Song, SongSource};
+use crate::resolver::{cache_key, cached};
+use crate::server::ServerEvent;
+pub use feed::{Feed, PODCAST_GENRE};
+
+use log::{debug, info, warn};
+
+// the most episodes listed at once, the last published ones, for the reply to fit
+pub const EPISODES_LISTED: usize = 100;
+const CONNECT_TIMEOUT_MS: u64 = 10_000;
+const READ_TIMEOUT_MS: u64 = 30_000;
+const DOWNLOAD_BUFFER_BYTES: usize = 64 * 1024;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct Podcast {
+    pub url: String, // of the feed, or its path
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub auto_download: bool, // the new episodes
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Episode {
+    pub song: Song,
+    pub podcast: String, // url of the feed
+    pub published: Option<DateTime<Local>>,
+    pub played: bool,
+    pub downloaded: bool,
+}
+
+/// Read a feed, from an HTTP(S) URL or a local file
+pub fn read_feed(location: &str) -> Result<Feed, String> {
+    let text = if Song::is_stream_url(location) {
+        agent()
+            .get(location)
+            .call()
+            .map_err(|e| format!("failed to get the feed {location}: {e}"))?
+            .into_string()
+            .map_err(|e| format!("failed to read the feed {location}: {e}"))?
+    } else {
+        let path = location.strip_prefix("file://").unwrap_or(location);
+        std::fs::read_to_string(path).map_err(|e| format!("failed to read {path}: {e}"))?
+    };
+    Feed::parse(&text)
+}
+
+fn agent() -> ureq::Agent {
+    ureq::AgentBuilder::new()
+        .timeout_connect(Duration::from_millis(CONNECT_TIMEOUT_MS))
+        .timeout_read(Duration::from_millis(READ_TIMEOUT_MS))
+        .build()
+}
+
+/// Read the podcast's feed, save what it tells of the podcast and its episodes not seen
+/// before, which are returned
+pub async fn refresh(config: &Config, podcast: &Podcast) -> Result<Vec<Episode>, String> {
+    let location = podcast.url.clone();
+    let feed = tokio::task::spawn_blocking(move || read_feed(&location))
+        .await
+        .map_err(|e| format!("the refresh of {} stopped: {e}", podcast.url))??;
+    let updated = Podcast {
+        title: feed.title.or(podcast.title.clone()),
+        author: feed.author.or(podcast.author.clone()),
+        ..podcast.clone()
+    };
+    if &updated != podcast {
+        save_podcast(config, updated)
+            .await
+            .map_err(|e| format!("failed to save the podcast {}: {e}", podcast.url))?;
+    }
+    let episodes = (feed.episodes.into_iter())
+        .map(|episode| Episode {
+            song: episode.song,
+            podcast: podcast.url.clone(),
+            published: episode.published,
+            ..Default::default()
+        })
+        .collect();
+    add_episodes(config, episodes)
+        .await
+        .map_err(|e| format!("failed to save the episodes of {}: {e}", podcast.url))
+}
+
+/// The episodes of a podcast or of all of them, the last published first
+pub async fn episodes(config: &Config, podcast: Option<String>) -> Result<Vec<Episode>, String> {
+    let mut episodes = list_episodes(config, podcast)
+        .await
+        .map_err(|e| format!("failed to read the episodes: {e}"))?;
+    episodes.truncate(EPISODES_LISTED);
+    for episode in &mut episodes {
+        if let Some(SongSource::EnclosureUrl(url)) = &episode.song.source {
+            episode.downloaded = downloaded_episode(config, url).is_some();
+        }
+    }
+    Ok(episodes)
+}
+
+/// Refresh the podcasts whenever asked and every `podcast_refresh`, until aborted
+pub async fn run_podcasts(
+    config: Config,
+    refresh_asked: Arc<Notify>,
+    downloads: Arc<Mutex<DownloadQueue>>,
+    downloads_changed: Arc<Notify>,
+    events: broadcast::Sender<ServerEvent>,
+) {
+    loop {
+        let podcasts = list_podcasts(&config).await.unwrap_or_else(|e| {
+            warn!("failed to read the podcasts: {e}");
+            vec![]
+        });
+        for podcast in podcasts {
+            let first = list_episodes(&config, Some(podcast.url.clone()))
+                .await
+                .is_ok_and(|episodes| episodes.is_empty());
+            let mut new = match refresh(&config, &podcast).await {
+                Ok(new) => new,
+                Err(e) => {
+                    warn!("failed to refresh the podcast {}: {e}", podcast.url);
+                    continue;
+                }
+            };
+            info!("{} new episodes of {}", new.len(), podcast.url);
+            if !podcast.auto_download {
+                continue;
+            }
+            if first {
+                new.sort_by_key(|episode| std::cmp::Reverse(episode.published));
+                new.truncate(1);
+            }
+            for episode in new {
+                queue_download(&downloads, &downloads_changed, &events, episode.song);
+            }
+        }
+
+        match config.podcast_refresh {
+            Some(every) => tokio::select! {
+                _ = tokio::time::sleep(every) => (),
+                _ = refresh_asked.notified() => debug!("podcasts refresh asked"),
+            },
+            None => refresh_asked.notified().await,
+        }
+    }
+}
+
+/// The file an episode was downloaded to, if it was
+pub fn downloaded_episode(config: &Config, url: &str) -> Option<PathBuf> {
+    cached(&config.podcast_dir, &cache_key(url))
+}
+
+/// Download an episode to the directory, under the name the resolver finds it by. Calls
+/// `progress` with the fraction downloaded so far, if the server tells the length,
+/// and gives up as soon as `cancelled` is set
+pub fn fetch_enclosure(
+    url: &str,
+    dir: &Path,
+    progress: &mut dyn FnMut(f32),
+    cancelled: &AtomicBool,
+) -> Result<PathBuf, String> {
+    std::fs::create_dir_all(dir).map_err(|e| format!("failed to create {dir:?}: {e}"))?;
+    let response = (agent().get(url).call()).map_err(|e| format!("failed to get {url}: {e}"))?;
+    let length: Option<u64> = (response.header("Content-Length"))
+        .and_then(|length| length.trim().parse().ok())
+        .filter(|&length| length > 0);
+    let extension = match feed::audio_format(response.content_type(), url) {
+        AudioFormat::unsupported => "audio".to_string(),
+        format => format.to_string(),
+    };
+
+    let key = cache_key(url);
+    let partial = dir.join(key.clone() + ".part");
+    let path = dir.join(key + "." + &extension);
+    let mut file =
+        File::create(&partial).map_err(|e| format!("failed to create {partial:?}: {e}"))?;
+    let mut reader = response.into_reader();
+    let mut buf = vec![0; DOWNLOAD_BUFFER_BYTES];
+    let mut downloaded = 0;
+    let result = loop {
+        if cancelled.load(Ordering::Relaxed) {
+            break Err(format!("the download of {url} was cancelled"));
+        }
+        let read = match reader.read(&mut buf) {
+            Ok(0) => break Ok(()),
+            Ok(read) => read,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => break Err(format!("failed to download {url}: {e}")),
+        };
+        if let Err(e) = file.write_all(&buf[..read]) {
+            break Err(format!("failed to write {partial:?}: {e}"));
+        }
+        downloaded += read as u64;
+        if let Some(length) = length {
+            progress(downloaded as f32 / length as f32);
+        }
+    };
+    let result = result.and_then(|_| match length {
+        Some(length) if downloaded < length => Err(format!("the download of {url} was cut short")),
+        _ => std::fs::rename(&partial, &path).map_err(|e| format!("failed to keep {path:?}: {e}")),
+    });
+    if let Err(e) = result {
+        let _ = std::fs::remove_file(&partial);
+        return Err(e);
+    }
+    progress(1.0);
+    info!("downloaded {url} to {path:?}");
+    Ok(path)
+}
+
+/// The song was played to its end, if it's an episode it's played
+pub fn episode_done(config: &Config, song: &Song) {
+    if let Some(SongSource::EnclosureUrl(_)) = song.source {
+        let (config, source) = (config.clone(), song.source.clone().unwrap().into());
+        tokio::spawn(async move {
+            if let Err(e) = set_episode_played(&config, source, true).await {
+                warn!("failed to mark the episode played: {e}");
+            }
+        });
+    }
+}
@@ -0,0 +1,145 @@
+//! RSS 2.0 and Atom podcast feeds
+//!
+//! Only what's needed to list the episodes is read: the titles and authors, and for each
+//! item or entry its audio enclosure (`<enclosure>` or `<link rel="enclosure">`), publication
+//! date and `itunes:duration`. Items without audio are left out. Elements are matched by their
+//! local name, whatever the namespace prefix the feed gives them.
+
+use chrono::prelude::{DateTime, Local};
+use roxmltree::{Document, Node};
+use std::time::Duration;
+
+use crate::music::song::{AudioFormat, Song, SongSource};
+
+// the genre of all the episodes, which makes them resumable by default
+pub const PODCAST_GENRE: &str = "Podcast";
+
+#[derive(Clone, Debug, Default)]
+pub struct Feed {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub episodes: Vec<FeedEpisode>, // as ordered in the feed, usually the newest first
+}
+
+#[derive(Clone, Debug)]
+pub struct FeedEpisode {
+    pub song: Song, // with an `EnclosureUrl` source
+    pub published: Option<DateTime<Local>>,
+}
+
+impl Feed {
+    pub fn parse(text: &str) -> Result<Feed, String> {
+        let document = Document::parse(text).map_err(|e| format!("invalid feed: {e}"))?;
+        let root = document.root_element();
+        let (channel, item_name) = match root.tag_name().name() {
+            "rss" => (
+                child(root, "channel").ok_or("RSS feed without a channel")?,
+                "item",
+            ),
+            "feed" => (root, "entry"),
+            other => return Err(format!("not an RSS or Atom feed, but <{other}>")),
+        };
+        let title = child_text(channel, "title");
+        let author = child_text(channel, "author")
+            .or_else(|| child(channel, "author").and_then(|a| child_text(a, "name")));
+
+        let episodes = (channel.children())
+            .filter(|node| node.tag_name().name() == item_name)
+            .filter_map(|item| {
+                let (url, mime_type) = enclosure(item)?;
+                let song = Song {
+                    title: child_text(item, "title").or(Some(url.clone())),
+                    artist: (child_text(item, "author"))
+                        .filter(|author| !author.contains('@')) // RSS authors are e-mails
+                        .or(author.clone()),
+                    album: title.clone(),
+                    genre: Some(PODCAST_GENRE.to_string()),
+                    duration: child_text(item, "duration")
+                        .and_then(|d| parse_duration(&d))
+                        .unwrap_or_default(),
+                    format: audio_format(&mime_type, &url),
+                    source: Some(SongSource::EnclosureUrl(url)),
+                    ..Default::default()
+                };
+                let published = child_text(item, "pubDate")
+                    .and_then(|date| DateTime::parse_from_rfc2822(&date).ok())
+                    .or_else(|| {
+                        (child_text(item, "published").or_else(|| child_text(item, "updated")))
+                            .and_then(|date| DateTime::parse_from_rfc3339(&date).ok())
+                    })
+                    .map(|date| date.with_timezone(&Local));
+                Some(FeedEpisode { song, published })
+            })
+            .collect();
+
+        Ok(Feed {
+            title,
+            author,
+            episodes,
+        })
+    }
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children()
+        .find(|child| child.tag_name().name() == name)
+}
+
+/// The trimmed text of the first child of that name, if not empty
+fn child_text(node: Node, name: &str) -> Option<String> {
+    let text: String = (child(node, name)?.descendants())
+        .filter(|node| node.is_text())
+        .filter_map(|node| node.text())
+        .collect();
+    let text = text.trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+/// The URL and MIME type of the audio of an item, if it has some
+fn enclosure(item: Node) -> Option<(String, String)> {
+    item.children().find_map(|node| {
+        let url = match node.tag_name().name() {
+            "enclosure" => node.attribute("url")?,
+            "link" if node.attribute("rel") == Some("enclosure") => node.attribute("href")?,
+            _ => return None,
+        };
+        let mime_type = node.attribute("type").unwrap_or_default();
+        // some feeds leave the type out
+        let audio = mime_type.starts_with("audio/")
+            || (mime_type.is_empty() && audio_format("", url) != AudioFormat::unsupported);
+        audio.then(|| (url.trim().to_string(), mime_type.to_string()))
+    })
+}
+
+/// From the MIME type, or the extension of the URL
+pub(crate) fn audio_format(mime_type: &str, url: &str) -> AudioFormat {
+    use AudioFormat::*;
+    match mime_type {
+        "audio/mpeg" | "audio/mp3" => return mp3,
+        "audio/mp4" | "audio/x-m4a" | "audio/m4a" => return m4a,
+        "audio/aac" => return aac,
+        "audio/ogg" | "audio/opus" => return ogg,
+        "audio/flac" | "audio/x-flac" => return flac,
+        "audio/wav" | "audio/x-wav" => return wav,
+        _ => (),
+    }
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    match path.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("mp3") => mp3,
+        Some("m4a") | Some("mp4") => m4a,
+        Some("aac") => aac,
+        Some("ogg") | Some("oga") | Some("opus") => ogg,
+        Some("flac") => flac,
+        Some("wav") => wav,
+        _ => unsupported,
+    }
+}
+
+/// An `itunes:duration`: seconds, or `mm:ss`, or `hh:mm:ss`
+fn parse_duration(duration: &str) -> Option<Duration> {
+    let mut seconds = 0.0;
+    for field in duration.split(':') {
+        seconds = seconds * 60.0 + field.trim().parse::<f64>().ok()?;
+    }
+    (seconds.is_finite() && seconds >= 0.0).then(|| Duration::from_secs_f64(seconds))
+}
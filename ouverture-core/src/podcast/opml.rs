@@ -0,0 +1,57 @@
+//! OPML subscription lists, to move podcast subscriptions between players
+//!
+//! Every `<outline>` with an `xmlUrl` is a subscription, however the outlines are nested
+//! (players often group them in categories).
+
+use roxmltree::Document;
+
+use super::Podcast;
+
+/// The podcasts listed, with their titles if given
+pub fn parse(text: &str) -> Result<Vec<Podcast>, String> {
+    let document = Document::parse(text).map_err(|e| format!("invalid OPML: {e}"))?;
+    if document.root_element().tag_name().name() != "opml" {
+        return Err("not an OPML document".to_string());
+    }
+    Ok(document
+        .descendants()
+        .filter(|node| node.tag_name().name() == "outline")
+        .filter_map(|outline| {
+            let url = outline.attribute("xmlUrl")?.trim();
+            let title = (outline.attribute("title"))
+                .or(outline.attribute("text"))
+                .map(str::trim)
+                .filter(|title| !title.is_empty());
+            (!url.is_empty()).then(|| Podcast {
+                url: url.to_string(),
+                title: title.map(String::from),
+                ..Default::default()
+            })
+        })
+        .collect())
+}
+
+pub fn to_opml(podcasts: &[Podcast]) -> String {
+    let mut opml = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<opml version=\"2.0\">\n",
+        "  <head><title>ouverture podcasts</title></head>\n",
+        "  <body>\n",
+    ));
+    for podcast in podcasts {
+        let title = escape(podcast.title.as_deref().unwrap_or(&podcast.url));
+        opml += &format!(
+            "    <outline type=\"rss\" text=\"{title}\" title=\"{title}\" xmlUrl=\"{}\"/>\n",
+            escape(&podcast.url)
+        );
+    }
+    opml += "  </body>\n</opml>\n";
+    opml
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
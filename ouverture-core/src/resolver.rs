@@ -0,0 +1,495 @@
+//! Resolvers turn the sources of online songs into sources the decoder can play:
+//! a local file, or a direct HTTP(S) audio stream
+//!
+//! The resolver of `YoutubeUrl` sources runs an external command with the options of
+//! yt-dlp, `resolver_command` in the config. It either downloads the audio to a cache,
+//! where it's found again the next time, or asks for the URL of the audio to stream it
+//! (with `resolver_stream = true`). Any program that takes the same options can stand in:
+//!
+//! - downloading: `<command> --no-playlist -f <format> -o <cache dir>/<key>.%(ext)s
+//!   --print after_move:filepath <url>`, printing the path of the downloaded file last
+//! - streaming: `<command> --no-playlist -f <format> -g <url>`, printing the audio's URL first
+//!
+//! `EnclosureUrl` sources, the podcast episodes, are played from `podcast_dir` once they are
+//! downloaded there (see `podcast`), and streamed before.
+//!
+//! Songs downloaded into the library (see `download`) are fetched with
+//! `<command> --no-playlist -f <format> -o <template> --newline --progress
+//! --print after_move:<fields> <url>`, which prints its progress as `[download]  42.0%` lines
+//! and the uploader, title and path of the file, separated by tabs, last.
+
+use std::hash::Hasher;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use twox_hash::XxHash64;
+
+use log::{debug, info};
+
+use crate::config::Config;
+use crate::music::song::SongSource;
+
+// audio the decoder can play without ffmpeg to convert it
+const AUDIO_FORMAT: &str = "bestaudio[ext=m4a]/bestaudio[acodec=opus]/bestaudio";
+// audio that can be tagged too, to keep in the library
+const LIBRARY_FORMAT: &str = "bestaudio[ext=m4a]/bestaudio[ext=mp3]";
+const LIBRARY_TEMPLATE: &str = "%(uploader)s - %(title)s [%(id)s].%(ext)s";
+const FETCHED_FIELDS: &str = "after_move:%(uploader)s\t%(title)s\t%(filepath)s";
+const POLL_PERIOD_MS: u64 = 50;
+// files being downloaded, not ready to be played
+const PARTIAL_EXTENSIONS: [&str; 3] = ["part", "ytdl", "temp"];
+
+pub trait Resolver: Send + Sync {
+    /// Whether the resolver knows this kind of source
+    fn handles(&self, source: &SongSource) -> bool;
+
+    /// A playable source (`FilePath` or `StreamUrl`) for this one. May block for long,
+    /// e.g. while downloading
+    fn resolve(&self, source: &SongSource) -> Result<SongSource, String>;
+}
+
+/// All the resolvers, the first one to handle a source resolves it
+#[derive(Clone, Default)]
+pub struct Resolvers(Vec<Arc<dyn Resolver>>);
+
+impl Resolvers {
+    pub fn new(config: &Config) -> Self {
+        Resolvers(vec![
+            Arc::new(CommandResolver::new(config)),
+            Arc::new(EnclosureResolver {
+                dir: config.podcast_dir.clone(),
+            }),
+        ])
+    }
+
+    /// Whether the decoder can play the source as it is
+    pub fn is_playable(source: &SongSource) -> bool {
+        matches!(
+            source,
+            SongSource::FilePath(_) | SongSource::FileRange(..) | SongSource::StreamUrl(_)
+        )
+    }
+
+    pub fn resolve(&self, source: &SongSource) -> Result<SongSource, String> {
+        if Resolvers::is_playable(source) {
+            return Ok(source.clone());
+        }
+        match self.0.iter().find(|resolver| resolver.handles(source)) {
+            Some(resolver) => resolver.resolve(source),
+            None => Err(format!("no way to play {source:?}")),
+        }
+    }
+}
+
+/// A song downloaded to keep, and what's known of it online
+#[derive(Clone, Debug)]
+pub struct Fetched {
+    pub path: PathBuf,
+    pub title: Option<String>,
+    pub artist: Option<String>, // the uploader
+}
+
+/// Resolves `YoutubeUrl` sources with yt-dlp, or a program that takes the same options
+pub struct CommandResolver {
+    command: PathBuf,
+    timeout: Duration,
+    cache_dir: Option<PathBuf>, // none to stream instead of downloading
+}
+
+impl CommandResolver {
+    pub fn new(config: &Config) -> Self {
+        CommandResolver {
+            command: config.resolver_command.clone(),
+            timeout: config.resolver_timeout,
+            cache_dir: (!config.resolver_stream).then(|| config.resolver_cache_dir.clone()),
+        }
+    }
+
+    fn download(&self, url: &str, cache_dir: &Path) -> Result<SongSource, String> {
+        let key = cache_key(url);
+        if let Some(path) = cached(cache_dir, &key) {
+            debug!("{url} is cached at {path:?}");
+            return Ok(SongSource::FilePath(path));
+        }
+        std::fs::create_dir_all(cache_dir)
+            .map_err(|e| format!("failed to create the cache {cache_dir:?}: {e}"))?;
+        let template = cache_dir.join(key + ".%(ext)s");
+        let output = self.run(
+            &[
+                "--no-playlist",
+                "-f",
+                AUDIO_FORMAT,
+                "-o",
+                &template.to_string_lossy(),
+                "--print",
+                "after_move:filepath",
+                url,
+            ],
+            Some(self.timeout),
+            &mut |_| (),
+            &AtomicBool::new(false),
+        )?;
+        let path = output
+            .lines()
+            .rev()
+            .find(|line| !line.trim().is_empty())
+            .map(|line| PathBuf::from(line.trim()))
+            .filter(|path| path.is_file())
+            .ok_or_else(|| format!("{:?} didn't download {url}", self.command))?;
+        info!("downloaded {url} to {path:?}");
+        Ok(SongSource::FilePath(path))
+    }
+
+    fn stream(&self, url: &str) -> Result<SongSource, String> {
+        let output = self.run(
+            &["--no-playlist", "-f", AUDIO_FORMAT, "-g", url],
+            Some(self.timeout),
+            &mut |_| (),
+            &AtomicBool::new(false),
+        )?;
+        output
+            .lines()
+            .map(str::trim)
+            .find(|line| line.starts_with("http://") || line.starts_with("https://"))
+            .map(|stream_url| SongSource::StreamUrl(stream_url.to_string()))
+            .ok_or_else(|| format!("{:?} found no audio to stream for {url}", self.command))
+    }
+
+    /// Download the audio of an URL to a directory, to keep it. Calls `progress` with the
+    /// fraction downloaded so far, and gives up as soon as `cancelled` is set.
+    /// There's no timeout, long videos take their time
+    pub fn fetch(
+        &self,
+        url: &str,
+        dir: &Path,
+        progress: &mut dyn FnMut(f32),
+        cancelled: &AtomicBool,
+    ) -> Result<Fetched, String> {
+        std::fs::create_dir_all(dir).map_err(|e| format!("failed to create {dir:?}: {e}"))?;
+        let template = dir.join(LIBRARY_TEMPLATE);
+        let output = self.run(
+            &[
+                "--no-playlist",
+                "-f",
+                LIBRARY_FORMAT,
+                "-o",
+                &template.to_string_lossy(),
+                "--newline",
+                "--progress",
+                "--print",
+                FETCHED_FIELDS,
+                url,
+            ],
+            None,
+            &mut |line| {
+                if let Some(fraction) = download_progress(line) {
+                    progress(fraction)
+                }
+            },
+            cancelled,
+        )?;
+        // yt-dlp prints NA for the fields it doesn't know
+        let known = |field: &str| (field != "NA" && !field.is_empty()).then(|| field.to_string());
+        let fetched = output
+            .lines()
+            .rev()
+            .find_map(
+                |line| match line.trim().splitn(3, '\t').collect::<Vec<_>>()[..] {
+                    [artist, title, path] => Some(Fetched {
+                        path: PathBuf::from(path),
+                        title: known(title),
+                        artist: known(artist),
+                    }),
+                    _ => None,
+                },
+            )
+            .filter(|fetched| fetched.path.is_file())
+            .ok_or_else(|| format!("{:?} didn't download {url}", self.command))?;
+        info!("fetched {url} to {:?}", fetched.path);
+        Ok(fetched)
+    }
+
+    /// Run the command to completion and return what it printed, or why it failed:
+    /// not found, timed out, cancelled, or its last error message.
+    /// Each line it prints, on either output, is passed to `on_line` as soon as it's read
+    fn run(
+        &self,
+        args: &[&str],
+        timeout: Option<Duration>,
+        on_line: &mut dyn FnMut(&str),
+        cancelled: &AtomicBool,
+    ) -> Result<String, String> {
+        debug!("running {:?} {args:?}", self.command);
+        let mut child = Command::new(&self.command)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("failed to run {:?}: {e}", self.command))?;
+        // read while it runs, so that it doesn't block on full pipes
+        let (lines_tx, lines) = channel();
+        let read_lines = |pipe: Box<dyn Read + Send>, is_stderr: bool| {
+            let lines_tx = lines_tx.clone();
+            std::thread::spawn(move || {
+                for line in BufReader::new(pipe).lines().map_while(|line| line.ok()) {
+                    if lines_tx.send((is_stderr, line)).is_err() {
+                        return;
+                    }
+                }
+            })
+        };
+        read_lines(Box::new(child.stdout.take().unwrap()), false);
+        read_lines(Box::new(child.stderr.take().unwrap()), true);
+        drop(lines_tx);
+
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut stdout = String::new();
+        let mut last_error = None;
+        // until both outputs are closed
+        loop {
+            match lines.recv_timeout(Duration::from_millis(POLL_PERIOD_MS)) {
+                Ok((is_stderr, line)) => {
+                    on_line(&line);
+                    if !is_stderr {
+                        stdout += &line;
+                        stdout.push('\n');
+                    } else if !line.trim().is_empty() {
+                        last_error = Some(line);
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            let failure = if cancelled.load(Ordering::Relaxed) {
+                format!("{:?} was cancelled", self.command)
+            } else if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                format!(
+                    "{:?} timed out after {} s",
+                    self.command,
+                    timeout.unwrap_or_default().as_secs()
+                )
+            } else {
+                continue;
+            };
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(failure);
+        }
+        let status = child
+            .wait()
+            .map_err(|e| format!("failed to wait for {:?}: {e}", self.command))?;
+        if !status.success() {
+            let reason = last_error.as_deref().unwrap_or("no error message");
+            return Err(format!("{:?} failed ({status}): {reason}", self.command));
+        }
+        Ok(stdout)
+    }
+}
+
+impl Resolver for CommandResolver {
+    fn handles(&self, source: &SongSource) -> bool {
+        matches!(source, SongSource::YoutubeUrl(_))
+    }
+
+    fn resolve(&self, source: &SongSource) -> Result<SongSource, String> {
+        let SongSource::YoutubeUrl(url) = source else {
+            return Err(format!("can't resolve {source:?}"));
+        };
+        match &self.cache_dir {
+            Some(cache_dir) => self.download(url, cache_dir),
+            None => self.stream(url),
+        }
+    }
+}
+
+/// Plays the downloaded `EnclosureUrl` sources from their file, and streams the others
+pub struct EnclosureResolver {
+    dir: PathBuf,
+}
+
+impl Resolver for EnclosureResolver {
+    fn handles(&self, source: &SongSource) -> bool {
+        matches!(source, SongSource::EnclosureUrl(_))
+    }
+
+    fn resolve(&self, source: &SongSource) -> Result<SongSource, String> {
+        let SongSource::EnclosureUrl(url) = source else {
+            return Err(format!("can't resolve {source:?}"));
+        };
+        Ok(match cached(&self.dir, &cache_key(url)) {
+            Some(path) => SongSource::FilePath(path),
+            None => SongSource::StreamUrl(url.clone()),
+        })
+    }
+}
+
+/// The name of the downloaded file of an URL in the cache, without its extension.
+/// The hash must stay the same across builds, the files outlive them
+pub(crate) fn cache_key(url: &str) -> String {
+    let mut hasher = XxHash64::with_seed(0);
+    hasher.write(url.as_bytes());
+    format!("{:016x}", hasher.finish())
+}
+
+pub(crate) fn cached(cache_dir: &Path, key: &str) -> Option<PathBuf> {
+    std::fs::read_dir(cache_dir)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .find(|path| {
+            path.file_stem().is_some_and(|stem| stem == key)
+                && !path.extension().is_some_and(|extension| {
+                    PARTIAL_EXTENSIONS
+                        .iter()
+                        .any(|partial| extension == *partial)
+                })
+        })
+}
+
+/// The fraction downloaded, from a progress line such as
+/// `[download]  42.3% of 3.20MiB at 1.10MiB/s ETA 00:02`
+fn download_progress(line: &str) -> Option<f32> {
+    let line = line.trim().strip_prefix("[download]")?;
+    let percent = line.split_whitespace().next()?.strip_suffix('%')?;
+    let percent: f32 = percent.parse().ok()?;
+    Some((percent / 100.0).clamp(0.0, 1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use std::sync::Mutex;
+
+    // a script being written can't be run ("text file busy") by a process forked meanwhile
+    static SCRIPTS: Mutex<()> = Mutex::new(());
+
+    const DOWNLOADS: &str = r#"
+while [ $# -gt 0 ]; do
+    case "$1" in
+        -o) out="$2"; shift;;
+    esac
+    shift
+done
+path=$(echo "$out" | sed 's/%(ext)s/m4a/')
+echo "[download] Destination: $path"
+echo audio > "$path"
+echo "$path"
+"#;
+
+    /// A resolver running a shell script standing in for yt-dlp
+    fn resolver(dir: &Path, script: &str, stream: bool) -> CommandResolver {
+        let command = dir.join("yt-dlp");
+        std::fs::write(&command, format!("#!/bin/sh\n{script}")).unwrap();
+        std::fs::set_permissions(&command, std::fs::Permissions::from_mode(0o755)).unwrap();
+        CommandResolver {
+            command,
+            timeout: Duration::from_secs(10),
+            cache_dir: (!stream).then(|| dir.join("cache")),
+        }
+    }
+
+    fn youtube(url: &str) -> SongSource {
+        SongSource::YoutubeUrl(url.to_string())
+    }
+
+    #[test]
+    fn downloads_to_the_cache_once() {
+        let _lock = SCRIPTS.lock().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let mut resolver = resolver(dir.path(), DOWNLOADS, false);
+        let url = "https://youtu.be/abc";
+        let path = dir.path().join("cache").join(cache_key(url) + ".m4a");
+        match resolver.resolve(&youtube(url)) {
+            Ok(SongSource::FilePath(resolved)) => assert_eq!(resolved, path),
+            resolved => panic!("resolved to {resolved:?}"),
+        }
+
+        // found in the cache, the command isn't run again
+        resolver.command = dir.path().join("missing");
+        match resolver.resolve(&youtube(url)) {
+            Ok(SongSource::FilePath(resolved)) => assert_eq!(resolved, path),
+            resolved => panic!("resolved to {resolved:?}"),
+        }
+    }
+
+    #[test]
+    fn streams_the_printed_url() {
+        let _lock = SCRIPTS.lock().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let script = "echo '[info] abc: Downloading webpage'\necho https://cdn.test/audio.m4a";
+        let resolver = resolver(dir.path(), script, true);
+        match resolver.resolve(&youtube("https://youtu.be/abc")) {
+            Ok(SongSource::StreamUrl(url)) => assert_eq!(url, "https://cdn.test/audio.m4a"),
+            resolved => panic!("resolved to {resolved:?}"),
+        }
+    }
+
+    #[test]
+    fn failure_gives_the_last_error() {
+        let _lock = SCRIPTS.lock().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let script = "echo 'WARNING: retrying' >&2\necho 'ERROR: Video unavailable' >&2\nexit 1";
+        let resolver = resolver(dir.path(), script, false);
+        let e = resolver
+            .resolve(&youtube("https://youtu.be/abc"))
+            .unwrap_err();
+        assert!(e.contains("exit status: 1"), "{e}");
+        assert!(e.contains("ERROR: Video unavailable"), "{e}");
+    }
+
+    #[test]
+    fn malformed_output_is_an_error() {
+        let _lock = SCRIPTS.lock().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let script = r#"echo '{"url": "https://cdn.te'"#;
+        let source = youtube("https://youtu.be/abc");
+
+        // not the path of a downloaded file
+        let e = resolver(dir.path(), script, false).resolve(&source);
+        assert!(e.unwrap_err().contains("didn't download"));
+        // not an URL to stream
+        let e = resolver(dir.path(), script, true).resolve(&source);
+        assert!(e.unwrap_err().contains("found no audio to stream"));
+    }
+
+    #[test]
+    fn slow_command_times_out() {
+        let _lock = SCRIPTS.lock().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let mut resolver = resolver(dir.path(), "exec sleep 30", false);
+        resolver.timeout = Duration::from_millis(300);
+        let start = Instant::now();
+        let e = resolver
+            .resolve(&youtube("https://youtu.be/abc"))
+            .unwrap_err();
+        assert!(e.contains("timed out"), "{e}");
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn missing_command_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let resolver = CommandResolver {
+            command: dir.path().join("missing"),
+            timeout: Duration::from_secs(10),
+            cache_dir: None,
+        };
+        let e = resolver
+            .resolve(&youtube("https://youtu.be/abc"))
+            .unwrap_err();
+        assert!(e.contains("failed to run"), "{e}");
+    }
+
+    #[test]
+    fn progress_lines() {
+        let line = "[download]  42.5% of 3.20MiB at 1.10MiB/s ETA 00:02";
+        assert_eq!(download_progress(line), Some(0.425));
+        assert_eq!(download_progress("[download] Destination: a.m4a"), None);
+        assert_eq!(download_progress("[info] 42.5%"), None);
+    }
+}
@@ -0,0 +1,689 @@
+//! Scheduled playback: a playlist or the songs matching a query start playing at a given
+//! time, once or on a cron-like recurrence, with the volume ramping up as for an alarm clock
+
+use chrono::prelude::{DateTime, Datelike, Local, NaiveDate, NaiveDateTime, TimeZone};
+use chrono::Duration as ChronoDuration;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+use crate::audio::AudioState;
+use crate::config::Config;
+use crate::database::{list_schedules, remove_schedule};
+use crate::library::list;
+use crate::music::song::{Song, SongSource};
+
+use log::{debug, info, warn};
+
+use color_eyre::eyre::eyre;
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Schedule {
+    pub id: i32, // given by the database, ignored when adding a schedule
+    pub when: Recurrence,
+    pub what: ScheduleTarget,
+    pub ramp_up: Duration, // from silence to full volume
+}
+
+/// When a schedule starts playing
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum Recurrence {
+    /// Once, then the schedule is removed
+    Once(DateTime<Local>),
+    /// Whenever the cron expression matches
+    Cron(Cron),
+}
+
+/// What a schedule plays
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum ScheduleTarget {
+    /// The library songs matching the query, as for a list
+    Query(String),
+    /// The songs of a .m3u playlist file
+    Playlist(PathBuf),
+}
+
+// needed by the EnumString and EnumIter derives of server::Command
+impl Default for Schedule {
+    fn default() -> Self {
+        Schedule {
+            id: 0,
+            when: Recurrence::Once(Local::now()),
+            what: ScheduleTarget::Query(String::new()),
+            ramp_up: Duration::ZERO,
+        }
+    }
+}
+
+/// A standard 5-field cron expression: minute, hour, day of month, month, day of week
+/// (0 or 7 being Sunday). Fields are '*', numbers, ranges ('1-5') and lists of those,
+/// with an optional step ('*/15', '0-30/10'). As with cron, a day matches on either its day
+/// of the month or its day of the week when both fields are restricted, a field starting
+/// with '*' (such as '*/2') not being restricted
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Cron {
+    expression: String,
+    // one bit per allowed value
+    minutes: u64,
+    hours: u32,
+    days: u32,
+    months: u16,
+    weekdays: u8,
+    // cron matches a day on either the day of the month or the day of the week
+    // when both are restricted, i.e. don't start with '*'
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+// how far to look for the next match of a cron expression, which may never match (e.g. Feb 30)
+const CRON_MAX_DAYS_AHEAD: i64 = 366 * 5;
+
+impl FromStr for Cron {
+    type Err = String;
+
+    fn from_str(expression: &str) -> Result<Cron, String> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!(
+                "invalid cron expression '{expression}', expected 5 fields: minute hour day month weekday"
+            ));
+        }
+        let mut weekdays = parse_cron_field(fields[4], 0, 7)?;
+        // 7 is Sunday too
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+        Ok(Cron {
+            expression: fields.join(" "),
+            minutes: parse_cron_field(fields[0], 0, 59)?,
+            hours: parse_cron_field(fields[1], 0, 23)? as u32,
+            days: parse_cron_field(fields[2], 1, 31)? as u32,
+            months: parse_cron_field(fields[3], 1, 12)? as u16,
+            weekdays: weekdays as u8,
+            days_restricted: !fields[2].starts_with('*'),
+            weekdays_restricted: !fields[4].starts_with('*'),
+        })
+    }
+}
+
+/// The allowed values of a cron field, as bits
+fn parse_cron_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let invalid = || format!("invalid cron field '{field}', expected values in {min}-{max}");
+    let mut bits = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().map_err(|_| invalid())?),
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (
+                    start.parse::<u32>().map_err(|_| invalid())?,
+                    end.parse::<u32>().map_err(|_| invalid())?,
+                ),
+                // '5/15' means from 5 to the end, by steps of 15
+                None if step > 1 => (range.parse::<u32>().map_err(|_| invalid())?, max),
+                None => {
+                    let value = range.parse::<u32>().map_err(|_| invalid())?;
+                    (value, value)
+                }
+            },
+        };
+        if step == 0 || start < min || end > max || start > end {
+            return Err(invalid());
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+impl Cron {
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        if self.months & (1 << date.month()) == 0 {
+            return false;
+        }
+        let day = self.days & (1 << date.day()) != 0;
+        let weekday = self.weekdays & (1 << date.weekday().num_days_from_sunday()) != 0;
+        match (self.days_restricted, self.weekdays_restricted) {
+            (true, true) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+
+    /// The first time strictly after the given one that matches, in its time zone
+    pub fn next_after<Tz: TimeZone>(&self, after: DateTime<Tz>) -> Option<DateTime<Tz>> {
+        let timezone = after.timezone();
+        let after = after.naive_local();
+        for days_ahead in 0..CRON_MAX_DAYS_AHEAD {
+            let date = after.date() + ChronoDuration::days(days_ahead);
+            if !self.matches_day(date) {
+                continue;
+            }
+            for hour in (0..24).filter(|h| self.hours & (1 << h) != 0) {
+                for minute in (0..60).filter(|m| self.minutes & (1 << m) != 0) {
+                    let time = date.and_hms_opt(hour, minute, 0)?;
+                    if time <= after {
+                        continue;
+                    }
+                    // skipped when it doesn't exist, in a daylight saving time gap
+                    if let Some(time) = timezone.from_local_datetime(&time).earliest() {
+                        return Some(time);
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+impl fmt::Display for Cron {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.expression)
+    }
+}
+
+/// What a check of the schedules does with one
+#[derive(Debug, PartialEq, Eq)]
+enum Due {
+    Start, // its time came since the last check
+    Later,
+    Over, // a one-shot done or missed while the server was down, or a cron that can't match
+}
+
+impl Recurrence {
+    /// The next time to start playing, strictly after the given one
+    pub fn next_after(&self, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        match self {
+            Recurrence::Once(time) if *time > after => Some(*time),
+            Recurrence::Once(_) => None,
+            Recurrence::Cron(cron) => cron.next_after(after),
+        }
+    }
+
+    fn due(&self, last_check: DateTime<Local>, now: DateTime<Local>) -> Due {
+        match self.next_after(last_check) {
+            Some(time) if time <= now => Due::Start,
+            Some(_) => Due::Later,
+            None => Due::Over,
+        }
+    }
+}
+
+impl FromStr for Recurrence {
+    type Err = String;
+
+    /// A cron expression, a date and time ('2024-05-01 07:30'), or a time ('07:30'),
+    /// the next one to come
+    fn from_str(s: &str) -> Result<Recurrence, String> {
+        let s = s.trim();
+        if s.split_whitespace().count() == 5 {
+            return Ok(Recurrence::Cron(s.parse()?));
+        }
+        let invalid = || {
+            format!("invalid time '{s}', expected e.g. '2024-05-01 07:30', '07:30' or a cron expression")
+        };
+        let naive = match NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M") {
+            Ok(naive) => naive,
+            Err(_) => {
+                let time = chrono::NaiveTime::parse_from_str(s, "%H:%M").map_err(|_| invalid())?;
+                let now = Local::now().naive_local();
+                let today = now.date().and_time(time);
+                if today > now {
+                    today
+                } else {
+                    today + ChronoDuration::days(1)
+                }
+            }
+        };
+        let time = Local
+            .from_local_datetime(&naive)
+            .earliest()
+            .ok_or_else(invalid)?;
+        Ok(Recurrence::Once(time))
+    }
+}
+
+impl fmt::Display for Recurrence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Recurrence::Once(time) => write!(f, "{}", time.format("%Y-%m-%d %H:%M")),
+            Recurrence::Cron(cron) => write!(f, "{cron}"),
+        }
+    }
+}
+
+// stored in the database as 'once:<rfc3339 time>' or 'cron:<expression>'
+impl From<Recurrence> for String {
+    fn from(recurrence: Recurrence) -> String {
+        match recurrence {
+            Recurrence::Once(time) => String::from("once:") + &time.to_rfc3339(),
+            Recurrence::Cron(cron) => String::from("cron:") + &cron.expression,
+        }
+    }
+}
+
+impl TryFrom<String> for Recurrence {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Recurrence, String> {
+        if let Some(time) = s.strip_prefix("once:") {
+            let time = DateTime::parse_from_rfc3339(time).map_err(|e| e.to_string())?;
+            Ok(Recurrence::Once(time.with_timezone(&Local)))
+        } else if let Some(expression) = s.strip_prefix("cron:") {
+            Ok(Recurrence::Cron(expression.parse()?))
+        } else {
+            Err(format!("unknown recurrence '{s}'"))
+        }
+    }
+}
+
+impl fmt::Display for ScheduleTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScheduleTarget::Query(query) => write!(f, "songs matching '{query}'"),
+            ScheduleTarget::Playlist(path) => write!(f, "playlist {}", path.display()),
+        }
+    }
+}
+
+// stored in the database as 'query:<query>' or 'playlist:<path>'
+impl From<ScheduleTarget> for String {
+    fn from(target: ScheduleTarget) -> String {
+        match target {
+            ScheduleTarget::Query(query) => String::from("query:") + &query,
+            ScheduleTarget::Playlist(path) => String::from("playlist:") + &path.to_string_lossy(),
+        }
+    }
+}
+
+impl TryFrom<String> for ScheduleTarget {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<ScheduleTarget, String> {
+        if let Some(query) = s.strip_prefix("query:") {
+            Ok(ScheduleTarget::Query(query.to_string()))
+        } else if let Some(path) = s.strip_prefix("playlist:") {
+            Ok(ScheduleTarget::Playlist(PathBuf::from(path)))
+        } else {
+            Err(format!("unknown schedule target '{s}'"))
+        }
+    }
+}
+
+impl ScheduleTarget {
+    async fn songs(&self, config: &Config) -> color_eyre::Result<Vec<Song>> {
+        match self {
+            ScheduleTarget::Query(query) => list(config, Some(query.clone())).await,
+            ScheduleTarget::Playlist(path) => (std::fs::read_to_string(path))
+                .map(|contents| read_m3u(path, &contents))
+                .map_err(|e| eyre!("failed to read playlist {path:?}: {e}")),
+        }
+    }
+}
+
+/// The songs of a .m3u playlist, paths being relative to the playlist's directory.
+/// Only their source is known, the audio thread finds out the rest
+fn read_m3u(path: &Path, contents: &str) -> Vec<Song> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| Song {
+            title: Path::new(line)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned()),
+            source: Some(SongSource::FilePath(dir.join(line))),
+            ..Default::default()
+        })
+        .collect()
+}
+
+// how long the scheduler sleeps at most before checking the schedules again,
+// in case the clock jumped (e.g. after a suspend)
+const SCHEDULER_MAX_SLEEP_SECS: u64 = 60;
+
+/// Start the schedules when their time comes, until aborted. `changed` wakes the scheduler
+/// up when a schedule is added or removed
+pub async fn run_scheduler(
+    config: Config,
+    audio_state: Arc<Mutex<AudioState>>,
+    changed: Arc<Notify>,
+) {
+    let mut last_check = Local::now();
+    loop {
+        let schedules = match list_schedules(&config).await {
+            Ok(schedules) => schedules,
+            Err(e) => {
+                warn!("failed to read the schedules: {e}");
+                vec![]
+            }
+        };
+
+        let now = Local::now();
+        for schedule in &schedules {
+            match schedule.when.due(last_check, now) {
+                Due::Start => start(&config, &audio_state, schedule).await,
+                Due::Later => (),
+                Due::Over => {
+                    info!("schedule {} is over, removing it", schedule.id);
+                    if let Err(e) = remove_schedule(&config, schedule.id).await {
+                        warn!("failed to remove schedule {}: {e}", schedule.id);
+                    }
+                }
+            }
+        }
+        last_check = now;
+
+        let next = schedules
+            .iter()
+            .filter_map(|schedule| schedule.when.next_after(now))
+            .min();
+        let max_sleep = Duration::from_secs(SCHEDULER_MAX_SLEEP_SECS);
+        let sleep = next.map_or(max_sleep, |next| {
+            (next - now).to_std().unwrap_or_default().min(max_sleep)
+        });
+        debug!("next schedule at {next:?}, checking again in {sleep:?}");
+        tokio::select! {
+            _ = tokio::time::sleep(sleep) => (),
+            _ = changed.notified() => debug!("schedules changed"),
+        }
+    }
+}
+
+async fn start(config: &Config, audio_state: &Arc<Mutex<AudioState>>, schedule: &Schedule) {
+    info!("starting schedule {}: {}", schedule.id, schedule.what);
+    let songs = match schedule.what.songs(config).await {
+        Ok(songs) => songs,
+        Err(e) => {
+            warn!("schedule {} isn't played: {e:?}", schedule.id);
+            return;
+        }
+    };
+    if songs.is_empty() {
+        warn!("schedule {} has nothing to play", schedule.id);
+        return;
+    }
+    audio_state
+        .lock()
+        .unwrap()
+        .play_all_fading_in(songs, schedule.ramp_up);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::offset::LocalResult;
+    use chrono::{FixedOffset, NaiveDateTime, Timelike, Utc};
+
+    fn cron(expression: &str) -> Cron {
+        expression.parse().unwrap()
+    }
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    fn naive(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    /// Central European time in 2025: the clocks go forward from 02:00 to 03:00 on March 30,
+    /// and back from 03:00 to 02:00 on October 26
+    #[derive(Clone, Copy, Debug)]
+    struct Cet2025;
+
+    impl Cet2025 {
+        fn winter() -> FixedOffset {
+            FixedOffset::east_opt(3600).unwrap()
+        }
+        fn summer() -> FixedOffset {
+            FixedOffset::east_opt(2 * 3600).unwrap()
+        }
+    }
+
+    impl TimeZone for Cet2025 {
+        type Offset = FixedOffset;
+
+        fn from_offset(_: &FixedOffset) -> Self {
+            Cet2025
+        }
+
+        fn offset_from_local_date(&self, local: &NaiveDate) -> LocalResult<FixedOffset> {
+            self.offset_from_local_datetime(&local.and_hms_opt(12, 0, 0).unwrap())
+        }
+
+        fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> LocalResult<FixedOffset> {
+            let local = *local;
+            if naive("2025-03-30 02:00") <= local && local < naive("2025-03-30 03:00") {
+                LocalResult::None
+            } else if naive("2025-10-26 02:00") <= local && local < naive("2025-10-26 03:00") {
+                LocalResult::Ambiguous(Cet2025::summer(), Cet2025::winter())
+            } else if naive("2025-03-30 03:00") <= local && local < naive("2025-10-26 02:00") {
+                LocalResult::Single(Cet2025::summer())
+            } else {
+                LocalResult::Single(Cet2025::winter())
+            }
+        }
+
+        fn offset_from_utc_date(&self, utc: &NaiveDate) -> FixedOffset {
+            self.offset_from_utc_datetime(&utc.and_hms_opt(12, 0, 0).unwrap())
+        }
+
+        fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> FixedOffset {
+            if naive("2025-03-30 01:00") <= *utc && *utc < naive("2025-10-26 01:00") {
+                Cet2025::summer()
+            } else {
+                Cet2025::winter()
+            }
+        }
+    }
+
+    #[test]
+    fn cron_fields() {
+        let every_quarter = cron("*/15 9-17 * * 1-5");
+        assert_eq!(every_quarter.to_string(), "*/15 9-17 * * 1-5");
+        // Friday
+        let after = utc(2025, 1, 3, 17, 45);
+        assert_eq!(every_quarter.next_after(after), Some(utc(2025, 1, 6, 9, 0)));
+        let after = utc(2025, 1, 6, 9, 0);
+        assert_eq!(
+            every_quarter.next_after(after),
+            Some(utc(2025, 1, 6, 9, 15))
+        );
+
+        // lists, and steps from a value
+        let listed = cron("5/20 0,12 1 1,7 *");
+        let after = utc(2025, 1, 1, 0, 25);
+        assert_eq!(listed.next_after(after), Some(utc(2025, 1, 1, 0, 45)));
+        let after = utc(2025, 1, 1, 12, 45);
+        assert_eq!(listed.next_after(after), Some(utc(2025, 7, 1, 0, 5)));
+
+        // 7 is Sunday, as 0
+        let after = utc(2025, 1, 1, 0, 0);
+        assert_eq!(
+            cron("0 9 * * 7").next_after(after),
+            Some(utc(2025, 1, 5, 9, 0))
+        );
+        assert_eq!(
+            cron("0 9 * * 0").next_after(after),
+            Some(utc(2025, 1, 5, 9, 0))
+        );
+    }
+
+    #[test]
+    fn invalid_cron_expressions() {
+        for expression in [
+            "* * * *",
+            "* * * * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "5-1 * * * *",
+            "*/0 * * * *",
+            "a * * * *",
+            "1,,2 * * * *",
+        ] {
+            assert!(expression.parse::<Cron>().is_err(), "{expression}");
+        }
+    }
+
+    #[test]
+    fn next_is_strictly_after() {
+        let daily = cron("30 7 * * *");
+        let at = utc(2025, 1, 1, 7, 30);
+        assert_eq!(daily.next_after(at), Some(utc(2025, 1, 2, 7, 30)));
+        let just_before = at - ChronoDuration::seconds(1);
+        assert_eq!(daily.next_after(just_before), Some(at));
+    }
+
+    #[test]
+    fn day_of_month_or_of_week() {
+        // both restricted: the 13th, or any Friday
+        let after = utc(2025, 1, 1, 12, 0);
+        assert_eq!(
+            cron("0 0 13 * 5").next_after(after),
+            Some(utc(2025, 1, 3, 0, 0))
+        );
+        assert_eq!(
+            cron("0 0 13 * 5").next_after(utc(2025, 1, 10, 12, 0)),
+            Some(utc(2025, 1, 13, 0, 0))
+        );
+        // a step over all days isn't a restriction: odd days that are Mondays
+        assert_eq!(
+            cron("0 0 */2 * 1").next_after(after),
+            Some(utc(2025, 1, 13, 0, 0))
+        );
+        // and the 13th falling on an even weekday, Thursday
+        assert_eq!(
+            cron("0 0 13 * */2").next_after(after),
+            Some(utc(2025, 2, 13, 0, 0))
+        );
+    }
+
+    #[test]
+    fn impossible_date_never_comes() {
+        assert_eq!(cron("0 0 30 2 *").next_after(utc(2025, 1, 1, 0, 0)), None);
+        assert_eq!(
+            cron("0 0 29 2 *").next_after(utc(2025, 1, 1, 0, 0)),
+            Some(utc(2028, 2, 29, 0, 0))
+        );
+    }
+
+    #[test]
+    fn time_skipped_by_daylight_saving() {
+        let nightly = cron("30 2 * * *");
+        let after = Cet2025.with_ymd_and_hms(2025, 3, 29, 12, 0, 0).unwrap();
+        // there's no 02:30 on March 30
+        let next = nightly.next_after(after).unwrap();
+        assert_eq!(
+            next,
+            Cet2025.with_ymd_and_hms(2025, 3, 31, 2, 30, 0).unwrap()
+        );
+        assert_eq!(next.offset(), &Cet2025::summer());
+    }
+
+    #[test]
+    fn time_repeated_by_daylight_saving_comes_once() {
+        let nightly = cron("30 2 * * *");
+        let after = Cet2025.with_ymd_and_hms(2025, 10, 25, 12, 0, 0).unwrap();
+        // the first of the two 02:30 of October 26
+        let next = nightly.next_after(after).unwrap();
+        assert_eq!(next.naive_local(), naive("2025-10-26 02:30"));
+        assert_eq!(next.offset(), &Cet2025::summer());
+        let next = nightly.next_after(next).unwrap();
+        assert_eq!(next.naive_local(), naive("2025-10-27 02:30"));
+        assert_eq!(next.offset(), &Cet2025::winter());
+    }
+
+    #[test]
+    fn one_shot_schedule() {
+        let at = Local::now().with_nanosecond(0).unwrap() + ChronoDuration::hours(1);
+        let once = Recurrence::Once(at);
+        let minute = ChronoDuration::minutes(1);
+        assert_eq!(once.due(at - minute * 2, at - minute), Due::Later);
+        assert_eq!(once.due(at - minute, at + minute), Due::Start);
+        // started at the last check, it's done
+        assert_eq!(once.due(at + minute, at + minute * 2), Due::Over);
+        // missed while the server was down: the first check is when it starts
+        assert_eq!(once.due(at + minute * 60, at + minute * 60), Due::Over);
+    }
+
+    #[test]
+    fn cron_schedule() {
+        let every_minute = Recurrence::Cron(cron("* * * * *"));
+        let now = Local::now();
+        assert_eq!(every_minute.due(now, now), Due::Later);
+        assert_eq!(
+            every_minute.due(now - ChronoDuration::minutes(1), now),
+            Due::Start
+        );
+        let never = Recurrence::Cron(cron("0 0 31 4 *"));
+        assert_eq!(never.due(now, now), Due::Over);
+    }
+
+    #[test]
+    fn recurrence_from_text() {
+        match "0 7 * * 1-5".parse::<Recurrence>() {
+            Ok(Recurrence::Cron(cron)) => assert_eq!(cron.to_string(), "0 7 * * 1-5"),
+            other => panic!("parsed {other:?}"),
+        }
+        match "2024-05-01 07:30".parse::<Recurrence>() {
+            Ok(Recurrence::Once(time)) => assert_eq!(time.naive_local(), naive("2024-05-01 07:30")),
+            other => panic!("parsed {other:?}"),
+        }
+        // the next 07:30 to come
+        match "07:30".parse::<Recurrence>() {
+            Ok(Recurrence::Once(time)) => {
+                let now = Local::now();
+                assert!(time > now && time <= now + ChronoDuration::days(1));
+                assert_eq!((time.hour(), time.minute()), (7, 30));
+            }
+            other => panic!("parsed {other:?}"),
+        }
+        assert!("tomorrow".parse::<Recurrence>().is_err());
+        assert!("25:00".parse::<Recurrence>().is_err());
+    }
+
+    #[test]
+    fn stored_recurrences() {
+        for recurrence in [
+            Recurrence::Cron(cron("*/10 6-8 * * 1-5")),
+            Recurrence::Once(Local::now().with_nanosecond(0).unwrap()),
+        ] {
+            let stored: String = recurrence.clone().into();
+            assert_eq!(Recurrence::try_from(stored), Ok(recurrence));
+        }
+        assert!(Recurrence::try_from("daily".to_string()).is_err());
+    }
+
+    #[test]
+    fn m3u_playlist() {
+        let contents = "#EXTM3U\n#EXTINF:123,Artist - Song\nsong.mp3\n\n  /abs/other.flac  \n";
+        let songs = read_m3u(Path::new("/music/lists/morning.m3u"), contents);
+        let sources: Vec<_> = (songs.iter())
+            .map(|song| match &song.source {
+                Some(SongSource::FilePath(path)) => path.clone(),
+                source => panic!("not a file: {source:?}"),
+            })
+            .collect();
+        assert_eq!(
+            sources,
+            [
+                PathBuf::from("/music/lists/song.mp3"),
+                PathBuf::from("/abs/other.flac")
+            ]
+        );
+        assert_eq!(songs[0].title.as_deref(), Some("song"));
+    }
+}
@@ -8,14 +8,25 @@ use std::sync::{Arc, Mutex};
 use strum_macros::{Display, EnumIter, EnumString};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::Notify;
 
 use crate::config::Config;
+use crate::database::{add_db, add_schedule, list_schedules, remove_schedule};
+use crate::database::{list_podcasts, remove_podcast, save_podcast, set_episode_played};
+use crate::download::{queue_download, run_downloads, Download, DownloadQueue};
 use crate::error::ServerError;
+use crate::library::watch::run_watcher;
 use crate::library::*;
-use crate::music::song::Song;
+use crate::music::song::{Song, SongSource};
+use crate::podcast::{episodes, run_podcasts, Episode, Podcast};
+use crate::schedule::{run_scheduler, Schedule};
 use color_eyre::Result;
 
-use crate::audio::AudioState;
+use crate::audio::{
+    waveform, AudioState, CapturedOutput, Equalizer, Levels, PlaybackStatus, QueueListing,
+    RepeatMode, ShuffleMode, SleepTimer, Waveform,
+};
 
 use log::{debug, error, info, trace, warn};
 use tokio::runtime::Runtime;
@@ -23,8 +34,11 @@ use tokio::runtime::Runtime;
 use crate::api_router::{start_router, RouterTask};
 use crate::audio::AudioTask;
 
-// magic number to identify ouverture protocol on the wire
-const MAGIC_ID_OUVERTURE_PROTOCOL: u64 = 0xACDE314152960000;
+// magic number to identify ouverture protocol on the wire, in the high 32 bits of the
+// 8-bytes prefix of every message, the low 32 bits being the length of the payload
+const MAGIC_ID_OUVERTURE_PROTOCOL: u64 = 0xACDE314100000000;
+// the longest payload, not to allocate whatever a corrupted prefix tells
+const MAX_MESSAGE_BYTES: u64 = 64 * 1024 * 1024;
 
 pub struct Server {
     config: Config,
@@ -37,12 +51,30 @@ pub struct Server {
 /// unique shareable / queryable state
 #[derive(Clone)]
 struct ServerState {
-    stop: bool, // stop-the-server flag
+    stop: bool,                             // stop-the-server flag
+    stopped: Arc<Notify>,                   // wakes up the server when the flag is set
+    events: broadcast::Sender<ServerEvent>, // to the subscribed clients
+    schedules_changed: Arc<Notify>,         // wakes up the scheduler
+    downloads: Arc<Mutex<DownloadQueue>>,
+    downloads_changed: Arc<Notify>, // wakes up the download runner
+    podcasts_changed: Arc<Notify>,  // wakes up the podcasts refresher
 }
 
+// events not yet sent to a slow subscriber before it misses some
+const SERVER_EVENTS_CAPACITY: usize = 64;
+
 impl Server {
     pub fn new(config: &Config) -> Self {
-        let state = Arc::new(Mutex::new(ServerState { stop: false }));
+        let (events, _) = broadcast::channel(SERVER_EVENTS_CAPACITY);
+        let state = Arc::new(Mutex::new(ServerState {
+            stop: false,
+            stopped: Arc::new(Notify::new()),
+            events,
+            schedules_changed: Arc::new(Notify::new()),
+            downloads: Arc::new(Mutex::new(DownloadQueue::default())),
+            downloads_changed: Arc::new(Notify::new()),
+            podcasts_changed: Arc::new(Notify::new()),
+        }));
         Self {
             config: config.clone(),
             audio_task: None,
@@ -58,15 +90,58 @@ impl Server {
         let listener = TcpListener::bind(&address).await?;
         trace!("Server bound to tcp port");
 
-        self.audio_task = Some(AudioTask::run());
+        let (
+            server_events,
+            stopped,
+            schedules_changed,
+            downloads,
+            downloads_changed,
+            podcasts_changed,
+        ) = {
+            let state = self.state.lock().unwrap();
+            (
+                state.events.clone(),
+                state.stopped.clone(),
+                state.schedules_changed.clone(),
+                state.downloads.clone(),
+                state.downloads_changed.clone(),
+                state.podcasts_changed.clone(),
+            )
+        };
+        self.audio_task = Some(AudioTask::run(&self.config, server_events.clone()));
         let audio_state = self.audio_task.as_ref().unwrap().state.clone();
 
-        self.router_task = Some(start_router().await);
+        let scheduler_task = tokio::spawn(run_scheduler(
+            self.config.clone(),
+            audio_state.clone(),
+            schedules_changed,
+        ));
+        let downloads_task = tokio::spawn(run_downloads(
+            self.config.clone(),
+            downloads.clone(),
+            downloads_changed.clone(),
+            server_events.clone(),
+        ));
+        let watcher_task =
+            (self.config.watch_library).then(|| tokio::spawn(run_watcher(self.config.clone())));
+        let podcasts_task = tokio::spawn(run_podcasts(
+            self.config.clone(),
+            podcasts_changed,
+            downloads.clone(),
+            downloads_changed,
+            server_events,
+        ));
+
+        self.router_task = Some(start_router(self.config.clone()).await);
         let internal_router_address = self.router_task.unwrap().addr;
 
         // accept many clients at the same time
         let res = loop {
-            let (mut socket, _) = listener.accept().await?;
+            let (mut socket, _) = tokio::select! {
+                accepted = listener.accept() => accepted?,
+                // the binded address is released at 'listener' drop
+                _ = stopped.notified() => break Ok(()),
+            };
             let client_address = socket.peer_addr()?;
             let client_address = format!("{}", client_address);
             debug!("New client: {}", client_address);
@@ -77,7 +152,7 @@ impl Server {
             let mut internal_stream = TcpStream::connect(internal_router_address).await?;
 
             let config = self.config.clone();
-            let handle = tokio::spawn(async move {
+            tokio::spawn(async move {
                 let mut buf = [0u8; 8];
 
                 // In a loop, read all the data from the socket
@@ -131,7 +206,9 @@ impl Server {
                                 Err(e) => warn!("Failed to send 'done' to client: {:?}", e),
                             }
 
-                            if state.lock().unwrap().stop {
+                            let state = state.lock().unwrap();
+                            if state.stop {
+                                state.stopped.notify_one();
                                 break;
                             }
                         }
@@ -141,17 +218,16 @@ impl Server {
 
                 trace!("Terminating tokio thread allocated to this request");
             });
-
-            trace!("Waiting on tokio thread join for shutdown...");
-            tokio::join!(handle).0?;
-
-            // in case the Stop command was received, exit the loop.
-            // The binded address is released at 'listener' drop
-            if self.state.lock().unwrap().stop {
-                break Ok(());
-            }
         };
 
+        scheduler_task.abort();
+        downloads_task.abort();
+        podcasts_task.abort();
+        if let Some(watcher_task) = watcher_task {
+            watcher_task.abort();
+        }
+        downloads.lock().unwrap().cancel_all();
+        AudioState::save_position(audio_state).await;
         self.audio_task.unwrap().stop();
 
         return res;
@@ -170,13 +246,136 @@ impl Server {
 
             Command::Pause => audio_state.lock().unwrap().pause(),
             Command::Enqueue(song) => audio_state.lock().unwrap().enqueue(song),
+            Command::EnqueueMany(songs) => audio_state.lock().unwrap().enqueue_many(songs),
+            Command::Insert(position, song) => audio_state.lock().unwrap().insert(position, song),
+            Command::PlayNext(song) => audio_state.lock().unwrap().play_next(song),
+            Command::Move(from, to) => audio_state.lock().unwrap().move_song(from, to),
+            Command::Remove(position) => audio_state.lock().unwrap().remove(position),
+            Command::ClearQueue => audio_state.lock().unwrap().clear_queue(),
+            Command::JumpTo(position) => audio_state.lock().unwrap().jump_to(position),
 
             Command::Next => audio_state.lock().unwrap().next(),
             Command::Previous => audio_state.lock().unwrap().previous(),
+            Command::SetShuffle(shuffle) => audio_state.lock().unwrap().set_shuffle(shuffle),
+            Command::SetRepeat(repeat) => audio_state.lock().unwrap().set_repeat(repeat),
+            Command::SetSleepTimer(timer) => AudioState::set_sleep_timer(audio_state, timer),
+            Command::CancelSleepTimer => audio_state.lock().unwrap().cancel_sleep_timer(),
+            Command::SetLoopStart(at_ms) => {
+                let set = AudioState::set_loop_start(audio_state, at_ms).await;
+                Self::reply_if_failed(set, socket).await
+            }
+            Command::SetLoopEnd(at_ms) => {
+                let set = AudioState::set_loop_end(audio_state, at_ms).await;
+                Self::reply_if_failed(set, socket).await
+            }
+            Command::ClearLoop => audio_state.lock().unwrap().clear_loop(),
+
+            Command::AddSchedule(schedule) => match add_schedule(&config, schedule).await {
+                Ok(id) => {
+                    info!("schedule {id} added");
+                    state.lock().unwrap().schedules_changed.notify_one();
+                }
+                Err(e) => warn!("failed to add schedule: {e:?}"),
+            },
+            Command::RemoveSchedule(id) => match remove_schedule(&config, id).await {
+                Ok(true) => state.lock().unwrap().schedules_changed.notify_one(),
+                Ok(false) => warn!("no schedule {id} to remove"),
+                Err(e) => warn!("failed to remove schedule {id}: {e:?}"),
+            },
+            Command::GetSchedules => {
+                let schedules = list_schedules(&config).await.unwrap_or_else(|e| {
+                    warn!("failed to read the schedules: {e:?}");
+                    vec![]
+                });
+                match Self::reply(Reply::Schedules(schedules), socket).await {
+                    Ok(_) => trace!("Replied 'schedules' successfully"),
+                    Err(e) => {
+                        warn!("Failed to send 'schedules' reply to client: {:?}", e)
+                    }
+                }
+            }
 
             Command::Scan => scan(&config).await,
+            Command::AddSong(song) => {
+                if let Err(e) = add_db(&config, song).await {
+                    warn!("failed to add the song to the library: {e:?}")
+                }
+            }
+
+            Command::Download(song) => {
+                let state = state.lock().unwrap();
+                queue_download(
+                    &state.downloads,
+                    &state.downloads_changed,
+                    &state.events,
+                    song,
+                );
+            }
+            Command::CancelDownload(id) => {
+                let state = state.lock().unwrap();
+                let cancelled = state.downloads.lock().unwrap().cancel(id);
+                if let Some(download) = cancelled {
+                    let _ = state.events.send(ServerEvent::Download(Box::new(download)));
+                }
+            }
+            Command::GetDownloads => {
+                let downloads = state.lock().unwrap().downloads.lock().unwrap().list();
+                match Self::reply(Reply::Downloads(downloads), socket).await {
+                    Ok(_) => trace!("Replied 'downloads' successfully"),
+                    Err(e) => {
+                        warn!("Failed to send 'downloads' reply to client: {:?}", e)
+                    }
+                }
+            }
+
+            Command::AddPodcast(podcast) => match save_podcast(&config, podcast).await {
+                Ok(_) => state.lock().unwrap().podcasts_changed.notify_one(),
+                Err(e) => warn!("failed to add the podcast: {e:?}"),
+            },
+            Command::RemovePodcast(url) => match remove_podcast(&config, url.clone()).await {
+                Ok(true) => info!("unsubscribed from {url}"),
+                Ok(false) => warn!("no podcast {url} to remove"),
+                Err(e) => warn!("failed to remove the podcast {url}: {e:?}"),
+            },
+            Command::RefreshPodcasts => state.lock().unwrap().podcasts_changed.notify_one(),
+            Command::GetPodcasts => {
+                let podcasts = list_podcasts(&config).await.unwrap_or_else(|e| {
+                    warn!("failed to read the podcasts: {e:?}");
+                    vec![]
+                });
+                match Self::reply(Reply::Podcasts(podcasts), socket).await {
+                    Ok(_) => trace!("Replied 'podcasts' successfully"),
+                    Err(e) => {
+                        warn!("Failed to send 'podcasts' reply to client: {:?}", e)
+                    }
+                }
+            }
+            Command::GetEpisodes(podcast) => {
+                let episodes = episodes(&config, podcast).await.unwrap_or_else(|e| {
+                    warn!("{e}");
+                    vec![]
+                });
+                match Self::reply(Reply::Episodes(episodes), socket).await {
+                    Ok(_) => trace!("Replied 'episodes' successfully"),
+                    Err(e) => {
+                        warn!("Failed to send 'episodes' reply to client: {:?}", e)
+                    }
+                }
+            }
+            Command::SetPlayed(song, played) => {
+                let source: String = song.source.unwrap_or(SongSource::Unknown).into();
+                match set_episode_played(&config, source.clone(), played).await {
+                    Ok(true) => (),
+                    Ok(false) => warn!("no episode {source}"),
+                    Err(e) => warn!("failed to mark the episode {source}: {e:?}"),
+                }
+            }
+
             Command::GetList(i) => {
-                let list = list(&config, i).await;
+                let list = list(&config, i).await.unwrap_or_else(|e| {
+                    warn!("failed to list the library: {e:?}");
+                    vec![]
+                });
                 match Self::reply(Reply::List(list), &mut socket).await {
                     Ok(_) => trace!("Replied 'list' successfully"),
                     Err(e) => {
@@ -186,10 +385,11 @@ impl Server {
             }
 
             Command::GetCurrentSong => {
-                let current_song = audio_state.lock().unwrap().current_song.clone();
-                let current_seek = AudioState::get_seek(audio_state).await;
+                let current_song = audio_state.lock().unwrap().current_song();
+                let (current_seek, current_seek_ms) =
+                    AudioState::get_seek(audio_state).await.unwrap_or_default();
                 match Self::reply(
-                    Reply::CurrentSong(current_song.clone(), current_seek),
+                    Reply::CurrentSong(current_song.clone(), current_seek, current_seek_ms),
                     &mut socket,
                 )
                 .await
@@ -205,6 +405,90 @@ impl Server {
             }
 
             Command::Seek(seek) => audio_state.lock().unwrap().set_seek(seek),
+            Command::SeekTo(seek_ms) => audio_state.lock().unwrap().set_seek_ms(seek_ms),
+            Command::SeekBy(delta_ms) => audio_state.lock().unwrap().seek_by(delta_ms),
+
+            Command::SetEqualizer(equalizer) => {
+                audio_state.lock().unwrap().set_equalizer(equalizer)
+            }
+            Command::SetEqualizerPreset(name) => match config.equalizer_presets.get(&name) {
+                Some(equalizer) => audio_state.lock().unwrap().set_equalizer(equalizer.clone()),
+                None => warn!("unknown equalizer preset '{name}'"),
+            },
+            Command::SetSpeed(speed) => audio_state.lock().unwrap().set_speed(speed),
+            Command::GetEqualizer => {
+                let equalizer = audio_state.lock().unwrap().equalizer.clone();
+                let presets = config.equalizer_presets.keys().cloned().collect();
+                match Self::reply(Reply::Equalizer(equalizer, presets), socket).await {
+                    Ok(_) => trace!("Replied 'equalizer' successfully"),
+                    Err(e) => {
+                        warn!("Failed to send 'equalizer' reply to client: {:?}", e)
+                    }
+                }
+            }
+
+            Command::GetQueue => {
+                let queue = audio_state.lock().unwrap().queue();
+                match Self::reply(Reply::Queue(queue), socket).await {
+                    Ok(_) => trace!("Replied 'queue' successfully"),
+                    Err(e) => {
+                        warn!("Failed to send 'queue' reply to client: {:?}", e)
+                    }
+                }
+            }
+
+            Command::GetStatus => {
+                let status = audio_state.lock().unwrap().status();
+                match Self::reply(Reply::Status(status), socket).await {
+                    Ok(_) => trace!("Replied 'status' successfully"),
+                    Err(e) => {
+                        warn!("Failed to send 'status' reply to client: {:?}", e)
+                    }
+                }
+            }
+
+            Command::GetWaveform(song) => {
+                let waveform = waveform(&config, song)
+                    .await
+                    .map_err(|e| warn!("failed to get the waveform: {e:?}"))
+                    .ok();
+                match Self::reply(Reply::Waveform(waveform), socket).await {
+                    Ok(_) => trace!("Replied 'waveform' successfully"),
+                    Err(e) => {
+                        warn!("Failed to send 'waveform' reply to client: {:?}", e)
+                    }
+                }
+            }
+
+            Command::GetCapture => {
+                let capture = audio_state.lock().unwrap().capture.report();
+                match Self::reply(Reply::Capture(capture), socket).await {
+                    Ok(_) => trace!("Replied 'capture' successfully"),
+                    Err(e) => {
+                        warn!("Failed to send 'capture' reply to client: {:?}", e)
+                    }
+                }
+            }
+            Command::ClearCapture => audio_state.lock().unwrap().capture.clear(),
+
+            Command::Subscribe => {
+                let mut events = state.lock().unwrap().events.subscribe();
+                // until the client disconnects
+                loop {
+                    let event = match events.recv().await {
+                        Ok(event) => event,
+                        Err(RecvError::Lagged(n)) => {
+                            warn!("subscribed client lagged, {n} events lost");
+                            continue;
+                        }
+                        Err(RecvError::Closed) => break,
+                    };
+                    if let Err(e) = Self::reply(Reply::Event(event), socket).await {
+                        debug!("subscribed client left: {e:?}");
+                        break;
+                    }
+                }
+            }
 
             Command::Ping => (),
             Command::Restart => (),
@@ -323,6 +607,18 @@ impl Server {
         stream.write_all(&encoded_reply).await?;
         Ok(())
     }
+
+    /// Tell the client why its command failed, if it did
+    async fn reply_if_failed(result: Result<()>, stream: &mut TcpStream) {
+        if let Err(e) = result {
+            warn!("{e}");
+            match Self::reply(Reply::Error(e.to_string()), stream).await {
+                Ok(_) => trace!("Replied 'error' successfully"),
+                Err(e) => warn!("Failed to send 'error' reply to client: {:?}", e),
+            }
+        }
+    }
+
     // async fn reply(reply: Reply, address: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
     //        let encoded_reply: Vec<u8> = reply.prepare_query()?;
     //        let mut stream = TcpStream::connect(address).await?;
@@ -341,16 +637,65 @@ pub enum Command {
     Next,
     Previous,
     Enqueue(Song),
-    Seek(f32),
+    EnqueueMany(Vec<Song>),
+    // positions are among the upcoming songs, 0 being the next one
+    Insert(usize, Song),
+    PlayNext(Song),
+    Move(usize, usize), // from, to
+    Remove(usize),
+    ClearQueue, // all but the current song
+    JumpTo(usize),
+    Seek(f32),   // fraction of the song, between 0 and 1
+    SeekTo(u64), // absolute position in milliseconds
+    SeekBy(i64), // relative to the current position, in milliseconds
+    SetEqualizer(Equalizer),
+    SetEqualizerPreset(String),
+    SetSpeed(f32), // playback rate, between 0.5 and 3, the pitch is kept
+    SetShuffle(ShuffleMode),
+    SetRepeat(RepeatMode),
+    SetSleepTimer(SleepTimer), // shows in the status
+    CancelSleepTimer,
+    // loop points, in milliseconds or at the current position if none
+    SetLoopStart(Option<u64>),
+    SetLoopEnd(Option<u64>),
+    ClearLoop,
 
     // "Library" commands
     Scan,
+    AddSong(Song), // one that isn't found by scanning, e.g. a station
+
+    // "Download" commands
+    Download(Song),      // an online song, into the library, in the background
+    CancelDownload(u32), // by id
+    GetDownloads,
+
+    // "Podcast" commands
+    AddPodcast(Podcast),   // subscribe to a feed, refreshed at once
+    RemovePodcast(String), // by feed url, with its episodes
+    RefreshPodcasts,
+    GetPodcasts,
+    GetEpisodes(Option<String>), // of a podcast, or of all of them
+    SetPlayed(Song, bool),       // an episode
+
+    // "Schedule" commands
+    AddSchedule(Schedule), // to play a playlist or query at given times
+    RemoveSchedule(i32),   // by id
+    GetSchedules,
 
     // "Get info" commands
     GetList(Option<String>),
     GetCurrentSong,
+    GetEqualizer,
+    GetStatus,
+    GetQueue,
+    GetCapture,        // what the capture audio output recorded
+    GetWaveform(Song), // computed on the first request if not at scan time
+
+    // "Test" commands
+    ClearCapture,
 
     // "Server" commands
+    Subscribe, // keep the connection open, and receive a Reply::Event for each ServerEvent
     Ping,
     Restart,
     Stop,
@@ -361,62 +706,125 @@ pub enum Command {
 pub enum Reply {
     Received(String),
     List(Vec<Song>),
-    CurrentSong(Option<Song>, f32), // current song and current seek
+    CurrentSong(Option<Song>, f32, u64), // current song and current seek (fraction, milliseconds)
+    Equalizer(Equalizer, Vec<String>),   // current equalizer and available presets
+    Status(PlaybackStatus),
+    Queue(QueueListing),
+    Schedules(Vec<Schedule>),
+    Waveform(Option<Waveform>),   // none if the song can't be decoded
+    Capture(Vec<CapturedOutput>), // one per audio output opened
+    Downloads(Vec<Download>),     // the queued, running and last finished ones
+    Podcasts(Vec<Podcast>),
+    Episodes(Vec<Episode>), // the last published ones
+    Event(ServerEvent),
+    Error(String), // the command failed, and why
     Done,
 }
 
+/// Happenings pushed to the clients that sent Command::Subscribe
+#[non_exhaustive]
+#[derive(Display, Debug, Serialize, Deserialize, Clone)]
+pub enum ServerEvent {
+    PlaybackFailed(Option<Song>, String), // the song was skipped, and why
+    Levels(Levels),                       // of the audio being played, at the configured rate
+    StreamTitle(String),                  // what the stream being played plays now
+    Download(Box<Download>),              // a download queued, progressing or over
+}
+
+// needed by the EnumString and EnumIter derives of Reply
+impl Default for ServerEvent {
+    fn default() -> Self {
+        ServerEvent::PlaybackFailed(None, String::new())
+    }
+}
+
 impl Command {
     fn prepare_query(&self) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
-        // create a 8-bytes prefix: the length of the whole (prefix+message)
-        match bincode::serialized_size(self) {
-            Ok(size) => {
-                if size > std::u32::MAX as u64 {
-                    return Err(Box::new(ServerError::MessageTooBig));
-                }
-
-                let mut message: Vec<u8> =
-                    (MAGIC_ID_OUVERTURE_PROTOCOL + size).to_ne_bytes().to_vec();
-                // add the serialized content to the message
-                message.extend(bincode::serialize(self).unwrap());
-                Ok(message)
-            }
-            Err(e) => Err(Box::new(e)),
-        }
+        prepare_message(self)
     }
-    fn decode_size(buf: [u8; 8]) -> Result<u16, Box<dyn Error + Send + Sync>> {
-        let size = u64::from_ne_bytes(buf);
-        if size >> 16 == MAGIC_ID_OUVERTURE_PROTOCOL >> 16 {
-            return Ok((size - MAGIC_ID_OUVERTURE_PROTOCOL) as u16);
-        } else {
-            return Err(Box::new(ServerError::NotNativeProtocol));
-        }
+    fn decode_size(buf: [u8; 8]) -> Result<u32, Box<dyn Error + Send + Sync>> {
+        decode_size(buf)
     }
 }
 
 impl Reply {
     fn prepare_query(&self) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
-        // create a 8-bytes prefix: the length of the whole (prefix+message)
-        match bincode::serialized_size(self) {
-            Ok(size) => {
-                if size > std::u16::MAX as u64 {
-                    return Err(Box::new(ServerError::MessageTooBig));
-                }
+        prepare_message(self)
+    }
+    fn decode_size(buf: [u8; 8]) -> Result<u32, Box<dyn Error + Send + Sync>> {
+        decode_size(buf)
+    }
+}
 
-                let mut message: Vec<u8> =
-                    (MAGIC_ID_OUVERTURE_PROTOCOL + size).to_ne_bytes().to_vec();
-                // add the serialized content to the message
-                message.extend(bincode::serialize(self).unwrap());
-                Ok(message)
-            }
-            Err(e) => Err(Box::new(e)),
-        }
+/// The 8-bytes prefix (magic number and length of the payload), then the serialized message
+fn prepare_message<T: Serialize>(message: &T) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let size = bincode::serialized_size(message)?;
+    if size > MAX_MESSAGE_BYTES {
+        return Err(Box::new(ServerError::MessageTooBig));
     }
-    fn decode_size(buf: [u8; 8]) -> Result<u16, Box<dyn Error + Send + Sync>> {
-        let size = u64::from_ne_bytes(buf);
-        if size >> 16 == MAGIC_ID_OUVERTURE_PROTOCOL >> 16 {
-            return Ok((size - MAGIC_ID_OUVERTURE_PROTOCOL) as u16);
-        } else {
-            return Err(Box::new(ServerError::NotNativeProtocol));
+    let mut encoded: Vec<u8> = (MAGIC_ID_OUVERTURE_PROTOCOL + size).to_ne_bytes().to_vec();
+    encoded.extend(bincode::serialize(message)?);
+    Ok(encoded)
+}
+
+/// The length of the payload following the prefix
+fn decode_size(buf: [u8; 8]) -> Result<u32, Box<dyn Error + Send + Sync>> {
+    let prefix = u64::from_ne_bytes(buf);
+    if prefix >> 32 != MAGIC_ID_OUVERTURE_PROTOCOL >> 32 {
+        return Err(Box::new(ServerError::NotNativeProtocol));
+    }
+    let size = prefix - MAGIC_ID_OUVERTURE_PROTOCOL;
+    if size > MAX_MESSAGE_BYTES {
+        return Err(Box::new(ServerError::MessageTooBig));
+    }
+    Ok(size as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn songs(count: usize) -> Vec<Song> {
+        (0..count)
+            .map(|i| Song {
+                title: Some(format!("song number {i} with a rather long title")),
+                source: Some(SongSource::FilePath(PathBuf::from(format!(
+                    "/music/some artist/some album/{i:04} song.flac"
+                )))),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn prefix(encoded: &[u8]) -> [u8; 8] {
+        encoded[..8].try_into().unwrap()
+    }
+
+    #[test]
+    fn large_command_keeps_its_length() {
+        let command = Command::EnqueueMany(songs(2000));
+        let encoded = command.prepare_query().unwrap();
+        assert!(encoded.len() > u16::MAX as usize);
+        let size = Command::decode_size(prefix(&encoded)).unwrap() as usize;
+        assert_eq!(size, encoded.len() - 8);
+        match bincode::deserialize::<Command>(&encoded[8..]).unwrap() {
+            Command::EnqueueMany(decoded) => assert_eq!(decoded.len(), 2000),
+            other => panic!("decoded {other}"),
         }
     }
+
+    #[test]
+    fn large_reply_keeps_its_length() {
+        let encoded = Reply::List(songs(2000)).prepare_query().unwrap();
+        let size = Reply::decode_size(prefix(&encoded)).unwrap() as usize;
+        assert_eq!(size, encoded.len() - 8);
+    }
+
+    #[test]
+    fn foreign_or_oversized_prefix_is_rejected() {
+        assert!(decode_size(*b"GET / HT").is_err());
+        let oversized = MAGIC_ID_OUVERTURE_PROTOCOL + MAX_MESSAGE_BYTES + 1;
+        assert!(decode_size(oversized.to_ne_bytes()).is_err());
+    }
 }
@@ -0,0 +1,215 @@
+//! The whole audio path, from a command sent to the server to the audio output,
+//! played into the capture output. No sound server nor database is needed
+#![cfg(feature = "null")]
+
+use std::f32::consts::PI;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use ouverture_core::audio::CapturedOutput;
+use ouverture_core::config::{AudioBackend, Config};
+use ouverture_core::music::song::{AudioFormat, Song, SongSource};
+use ouverture_core::server::{Command, Reply, Server};
+
+const RATE: u32 = 44100;
+const FRAMES: usize = 22050; // half a second
+
+/// A stereo 16-bit WAV file, a 440 Hz sine on the left and a 660 Hz one on the right.
+/// Returns the samples, interleaved
+fn write_wav(path: &Path) -> Vec<i16> {
+    let samples: Vec<i16> = (0..FRAMES)
+        .flat_map(|i| {
+            let t = i as f32 / RATE as f32;
+            [440.0, 660.0].map(|freq| ((2.0 * PI * freq * t).sin() * 16000.0) as i16)
+        })
+        .collect();
+    let data_len = samples.len() as u32 * 2;
+    let mut bytes = vec![];
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data_len).to_le_bytes());
+    bytes.extend_from_slice(b"WAVEfmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    bytes.extend_from_slice(&2u16.to_le_bytes());
+    bytes.extend_from_slice(&RATE.to_le_bytes());
+    bytes.extend_from_slice(&(RATE * 4).to_le_bytes());
+    bytes.extend_from_slice(&4u16.to_le_bytes());
+    bytes.extend_from_slice(&16u16.to_le_bytes());
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data_len.to_le_bytes());
+    for sample in &samples {
+        bytes.extend_from_slice(&sample.to_le_bytes());
+    }
+    std::fs::write(path, bytes).unwrap();
+    samples
+}
+
+fn song(path: PathBuf) -> Song {
+    Song {
+        title: path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned()),
+        source: Some(SongSource::FilePath(path)),
+        duration: Duration::from_millis(FRAMES as u64 * 1000 / RATE as u64),
+        format: AudioFormat::wav,
+        ..Default::default()
+    }
+}
+
+/// A server playing into the capture output as fast as it can, on a free port
+fn config(dir: &Path) -> Config {
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    Config {
+        server_port: port as usize,
+        database_port: 1, // none, what needs it fails and is logged
+        audio_output: AudioBackend::Capture {
+            pace: 0.0,
+            samples: true,
+        },
+        meter_rate: 0,
+        resume_min_duration: None,
+        resume_genres: vec![],
+        resolver_cache_dir: dir.join("online"),
+        download_dir: dir.join("downloads"),
+        podcast_dir: dir.join("podcasts"),
+        podcast_refresh: None,
+        ..Default::default()
+    }
+}
+
+struct Running {
+    address: String,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl Running {
+    async fn start(config: Config) -> Running {
+        let address = format!("{}:{}", config.server_address, config.server_port);
+        let task = tokio::spawn(async move {
+            Server::new(&config).run().await.unwrap();
+        });
+        for _ in 0..100 {
+            if Server::send_wait(&Command::Ping, &address).await.is_ok() {
+                return Running { address, task };
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("the server didn't start");
+    }
+
+    async fn send(&self, command: Command) -> Reply {
+        Server::send_wait(&command, &self.address).await.unwrap()
+    }
+
+    /// What was captured once the queue is done playing, the output being flushed then
+    async fn captured(&self) -> Vec<CapturedOutput> {
+        for _ in 0..200 {
+            match self.send(Command::GetCapture).await {
+                Reply::Capture(outputs) if outputs.iter().any(|output| output.flushes > 0) => {
+                    return outputs
+                }
+                _ => tokio::time::sleep(Duration::from_millis(25)).await,
+            }
+        }
+        panic!("nothing was played");
+    }
+
+    async fn stop(self) {
+        self.send(Command::Stop).await;
+        tokio::time::timeout(Duration::from_secs(10), self.task)
+            .await
+            .expect("the server didn't stop")
+            .unwrap();
+    }
+}
+
+fn assert_same_audio(captured: &[f32], expected: &[i16]) {
+    assert_eq!(captured.len(), expected.len());
+    for (i, (captured, expected)) in captured.iter().zip(expected).enumerate() {
+        let expected = *expected as f32 / 32768.0;
+        assert!(
+            (captured - expected).abs() < 1e-3,
+            "sample {i}: played {captured} instead of {expected}"
+        );
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn played_song_reaches_the_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("sine.wav");
+    let samples = write_wav(&path);
+    let server = Running::start(config(dir.path())).await;
+
+    server.send(Command::Play(Some(song(path)))).await;
+    let outputs = server.captured().await;
+
+    assert_eq!(outputs.len(), 1);
+    let output = &outputs[0];
+    assert_eq!((output.rate, output.channels), (RATE, 2));
+    assert_eq!(output.frames, FRAMES as u64);
+    assert_eq!(output.duration_ms(), 500);
+    assert_same_audio(output.samples.as_deref().unwrap(), &samples);
+
+    match server.send(Command::GetStatus).await {
+        Reply::Status(status) => assert!(status.paused),
+        reply => panic!("got {reply:?}"),
+    }
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn queued_songs_follow_each_other_in_one_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let first = dir.path().join("first.wav");
+    let second = dir.path().join("second.wav");
+    let samples = write_wav(&first);
+    write_wav(&second);
+    let server = Running::start(config(dir.path())).await;
+
+    // enqueued first, so that it's preloaded and chained without gap
+    server.send(Command::Enqueue(song(second))).await;
+    server.send(Command::Play(Some(song(first)))).await;
+    let outputs = server.captured().await;
+
+    // kept open from a song to the next, without gap nor overlap
+    assert_eq!(outputs.len(), 1);
+    let output = &outputs[0];
+    assert_eq!(output.frames, 2 * FRAMES as u64);
+    let twice = [samples.as_slice(), samples.as_slice()].concat();
+    assert_same_audio(output.samples.as_deref().unwrap(), &twice);
+
+    match server.send(Command::GetQueue).await {
+        // both played, the queue is done
+        Reply::Queue(queue) => {
+            assert_eq!(queue.past.len(), 2);
+            assert!(queue.current.is_none() && queue.future.is_empty());
+        }
+        reply => panic!("got {reply:?}"),
+    }
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unplayable_song_is_skipped() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("sine.wav");
+    write_wav(&path);
+    let server = Running::start(config(dir.path())).await;
+
+    server
+        .send(Command::Enqueue(song(dir.path().join("missing.wav"))))
+        .await;
+    server.send(Command::Enqueue(song(path))).await;
+    server.send(Command::Next).await;
+    let outputs = server.captured().await;
+
+    // the missing file is skipped, the next song is played
+    assert_eq!(outputs.len(), 1);
+    assert_eq!(outputs[0].frames, FRAMES as u64);
+    server.stop().await;
+}
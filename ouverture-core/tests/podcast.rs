@@ -0,0 +1,157 @@
+//! Podcast feeds and subscription lists, as other players and hosts write them
+
+use chrono::DateTime;
+use std::time::Duration;
+
+use ouverture_core::music::song::{AudioFormat, Song, SongSource};
+use ouverture_core::podcast::{opml, Feed, Podcast, PODCAST_GENRE};
+
+const RSS: &str = include_str!("fixtures/podcast/rss.xml");
+const ATOM: &str = include_str!("fixtures/podcast/atom.xml");
+const OPML: &str = include_str!("fixtures/podcast/subscriptions.opml");
+
+fn enclosure(song: &Song) -> &str {
+    match &song.source {
+        Some(SongSource::EnclosureUrl(url)) => url,
+        source => panic!("not an enclosure: {source:?}"),
+    }
+}
+
+#[test]
+fn rss_feed() {
+    let feed = Feed::parse(RSS).unwrap();
+    assert_eq!(feed.title.as_deref(), Some("The Test Cast"));
+    assert_eq!(feed.author.as_deref(), Some("Jane Doe"));
+    assert_eq!(feed.episodes.len(), 3);
+
+    let episode = &feed.episodes[0];
+    let song = &episode.song;
+    assert_eq!(song.title.as_deref(), Some("Episode 3 & the guests"));
+    assert_eq!(song.artist.as_deref(), Some("Guest Host"));
+    assert_eq!(song.album.as_deref(), Some("The Test Cast"));
+    assert_eq!(song.genre.as_deref(), Some(PODCAST_GENRE));
+    assert_eq!(song.duration, Duration::from_secs(3723));
+    assert_eq!(song.format, AudioFormat::mp3);
+    assert_eq!(enclosure(song), "https://cast.test/ep3.mp3");
+    let published = DateTime::parse_from_rfc3339("2025-06-10T04:00:00Z").unwrap();
+    assert_eq!(episode.published, Some(published.into()));
+
+    // an e-mail as author, the feed's is taken instead. No type, guessed from the URL
+    let episode = &feed.episodes[1];
+    let song = &episode.song;
+    assert_eq!(song.title.as_deref(), Some("Episode 2 <live>"));
+    assert_eq!(song.artist.as_deref(), Some("Jane Doe"));
+    assert_eq!(song.duration, Duration::from_secs(95));
+    assert_eq!(song.format, AudioFormat::m4a);
+    assert_eq!(enclosure(song), "https://cdn.cast.test/ep2.m4a?token=abc");
+    let published = DateTime::parse_from_rfc3339("2025-06-09T04:30:00Z").unwrap();
+    assert_eq!(episode.published, Some(published.into()));
+
+    // untitled, named after its audio
+    let episode = &feed.episodes[2];
+    assert_eq!(
+        episode.song.title.as_deref(),
+        Some("https://cast.test/bonus.ogg")
+    );
+    assert_eq!(episode.song.duration, Duration::from_secs(750));
+    assert_eq!(episode.song.format, AudioFormat::ogg);
+    assert_eq!(episode.published, None);
+}
+
+#[test]
+fn items_without_audio_are_left_out() {
+    for feed in [RSS, ATOM] {
+        let feed = Feed::parse(feed).unwrap();
+        let titles: Vec<_> = (feed.episodes.iter())
+            .filter_map(|episode| episode.song.title.as_deref())
+            .collect();
+        for without_audio in ["Show notes only", "Video episode", "A blog post"] {
+            assert!(!titles.contains(&without_audio), "{titles:?}");
+        }
+    }
+}
+
+#[test]
+fn atom_feed() {
+    let feed = Feed::parse(ATOM).unwrap();
+    assert_eq!(feed.title.as_deref(), Some("Atom Cast"));
+    assert_eq!(feed.author.as_deref(), Some("Bob Smith"));
+    assert_eq!(feed.episodes.len(), 2);
+
+    let episode = &feed.episodes[0];
+    assert_eq!(episode.song.title.as_deref(), Some("First light"));
+    assert_eq!(episode.song.artist.as_deref(), Some("Bob Smith"));
+    assert_eq!(episode.song.format, AudioFormat::ogg);
+    assert_eq!(enclosure(&episode.song), "https://atom.test/first.ogg");
+    let published = DateTime::parse_from_rfc3339("2025-01-02T03:04:05Z").unwrap();
+    assert_eq!(episode.published, Some(published.into()));
+
+    // dated by its last update when not published
+    let episode = &feed.episodes[1];
+    assert_eq!(episode.song.format, AudioFormat::flac);
+    let updated = DateTime::parse_from_rfc3339("2025-01-09T09:00:00Z").unwrap();
+    assert_eq!(episode.published, Some(updated.into()));
+}
+
+#[test]
+fn not_a_feed() {
+    assert!(Feed::parse("<html><body>Not found</body></html>").is_err());
+    assert!(Feed::parse("<rss version=\"2.0\"></rss>").is_err());
+    assert!(Feed::parse("<rss><channel><title>cut</rss>").is_err());
+}
+
+#[test]
+fn opml_import() {
+    let podcasts = opml::parse(OPML).unwrap();
+    let listed: Vec<_> = (podcasts.iter())
+        .map(|podcast| (podcast.url.as_str(), podcast.title.as_deref()))
+        .collect();
+    assert_eq!(
+        listed,
+        [
+            ("https://news.test/feed.xml", Some("Morning & Evening")),
+            ("https://deep.test/rss", Some("Title name")),
+            ("https://untitled.test/feed", None),
+        ]
+    );
+    assert!(podcasts.iter().all(|podcast| !podcast.auto_download));
+
+    assert!(opml::parse(RSS).is_err());
+    assert!(opml::parse("<opml><body>").is_err());
+}
+
+#[test]
+fn opml_export_round_trip() {
+    let podcasts = vec![
+        Podcast {
+            url: "https://cast.test/feed?a=1&b=2".to_string(),
+            title: Some("Quotes \"&\" <brackets>".to_string()),
+            ..Default::default()
+        },
+        Podcast {
+            url: "/home/me/feeds/local.xml".to_string(),
+            title: None,
+            ..Default::default()
+        },
+    ];
+    let exported = opml::to_opml(&podcasts);
+    let imported = opml::parse(&exported).unwrap();
+    assert_eq!(imported.len(), 2);
+    assert_eq!(imported[0].url, podcasts[0].url);
+    assert_eq!(imported[0].title, podcasts[0].title);
+    // named after its feed, as players need a name
+    assert_eq!(imported[1].url, podcasts[1].url);
+    assert_eq!(
+        imported[1].title.as_deref(),
+        Some("/home/me/feeds/local.xml")
+    );
+
+    // an exported list imports as it was
+    let again = opml::parse(&opml::to_opml(&imported)).unwrap();
+    let urls_and_titles = |podcasts: &[Podcast]| -> Vec<(String, Option<String>)> {
+        (podcasts.iter())
+            .map(|podcast| (podcast.url.clone(), podcast.title.clone()))
+            .collect()
+    };
+    assert_eq!(urls_and_titles(&again), urls_and_titles(&imported));
+}
@@ -4,7 +4,7 @@ use color_eyre::Result;
 use log::LevelFilter::*;
 use log::{debug, error, info};
 use opt::Opt;
-use ouverture_core::config::Config;
+use ouverture_core::config::{AudioBackend, Config};
 use ouverture_core::logger::{setup_logger, LogDestination::*};
 use structopt::StructOpt;
 
@@ -41,6 +41,13 @@ fn main() -> Result<()> {
 
     config.background = opts.background;
 
+    if let Some(pace) = opts.capture {
+        config.audio_output = AudioBackend::Capture {
+            pace,
+            samples: opts.capture_samples,
+        };
+    }
+
     if opts.background {
         let daemonize =
             Daemonize::new().working_directory(std::env::current_dir().unwrap_or("/tmp".into()));
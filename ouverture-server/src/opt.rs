@@ -19,6 +19,15 @@ pub struct Opt {
     #[structopt(short, long)]
     pub config: Option<PathBuf>,
 
+    /// Don't play audio but record what would have been played, at PACE times real-time
+    /// (0 for as fast as possible). Clients can get the record with the GetCapture command
+    #[structopt(long = "capture", value_name = "PACE")]
+    pub capture: Option<f32>,
+
+    /// Also keep the captured samples, not only their count
+    #[structopt(long = "capture-samples", requires = "capture")]
+    pub capture_samples: bool,
+
     /// Config path
     #[structopt(short = "d", long = "daemonize")]
     pub background: bool,
@@ -2,7 +2,7 @@ use iced::{Application, Command, Element, Length, Settings};
 
 use iced::theme::Theme;
 
-// use iced::Subscription;
+use iced::Subscription;
 // use iced::time;
 
 use iced::widget::{container, pane_grid};
@@ -19,6 +19,7 @@ use panes::Panes;
 use std::rc::Rc;
 use std::time::Instant;
 
+use ouverture_core::audio::{PlaybackStatus, RepeatMode, ShuffleMode, Waveform};
 use ouverture_core::music::song::Song;
 
 use panes::list;
@@ -179,6 +180,13 @@ pub enum Message {
     SliderChanged(u32),     // changed by user, seek song to new position
     SliderChangedAuto(u32), // updated by server, don't seek new position
     RefreshControl(Instant),
+    SetShuffle(ShuffleMode),
+    SetRepeat(RepeatMode),
+    SetLoopStart, // at the current position
+    SetLoopEnd,
+    ClearLoop,
+    ReceivedStatus(PlaybackStatus),
+    ReceivedWaveform(String, Box<Waveform>), // of the song with this source
 
     //Menu messages
     Home,
@@ -190,6 +198,11 @@ pub enum Message {
     IntoControlBar(pane_grid::Pane),
     IntoSearchBar(pane_grid::Pane),
     IntoList(pane_grid::Pane),
+    IntoEqualizer(pane_grid::Pane),
+    IntoQueue(pane_grid::Pane),
+    IntoVisualizer(pane_grid::Pane),
+    IntoDownloads(pane_grid::Pane),
+    IntoPodcasts(pane_grid::Pane),
 
     // List message
     AskRefreshList(pane_grid::Pane),
@@ -197,6 +210,21 @@ pub enum Message {
     ListMessage(list::ListMessage),
     ReceivedNewCurrentSong(Option<Song>, f32),
 
+    // Equalizer messages
+    EqualizerMessage(panes::equalizer::EqualizerMessage),
+
+    // Queue messages
+    QueueMessage(panes::queue::QueueMessage),
+
+    // Visualizer messages
+    VisualizerMessage(panes::visualizer::VisualizerMessage),
+
+    // Downloads messages
+    DownloadsMessage(panes::downloads::DownloadsMessage),
+
+    // Podcasts messages
+    PodcastsMessage(panes::podcasts::PodcastsMessage),
+
     // Misc
     ServerReply(pane_grid::Pane),
     Refresh(pane_grid::Pane),
@@ -270,10 +298,18 @@ impl<'a> Application for Ouverture {
         }
     }
 
-    // fn subscription(&self) -> Subscription<Message> {
-    // TODO reactivate poll or do thing in another way to reflect server changes
-    // time::every(Duration::from_millis(1000)).map(|i| Message::RefreshControl(i))
-    // }
+    fn subscription(&self) -> Subscription<Message> {
+        // TODO reactivate poll or do thing in another way to reflect server changes
+        // time::every(Duration::from_millis(1000)).map(|i| Message::RefreshControl(i))
+        let mut subscriptions = vec![];
+        if self.panes.shows_visualizer() {
+            subscriptions.push(panes::visualizer::levels(&self.config));
+        }
+        if self.panes.shows_downloads() {
+            subscriptions.push(panes::downloads::changes(&self.config));
+        }
+        Subscription::batch(subscriptions)
+    }
 
     fn view(&self) -> Element<Message> {
         let panes = self.panes.view();
@@ -19,9 +19,14 @@ use log::{debug, trace, warn};
 use std::any::Any;
 
 mod control_bar;
+pub mod downloads;
+pub mod equalizer;
 // pub mod list;
 pub mod list;
 mod menu;
+pub mod podcasts;
+pub mod queue;
+pub mod visualizer;
 
 impl Panes {
     pub fn new(conf: &Config) -> Self {
@@ -49,6 +54,22 @@ impl Panes {
     }
 }
 
+impl Panes {
+    /// Whether a visualizer pane is open, to receive the audio levels
+    pub fn shows_visualizer(&self) -> bool {
+        self.panes
+            .iter()
+            .any(|(_, content)| content.as_any().is::<visualizer::VisualizerPane>())
+    }
+
+    /// Whether a downloads pane is open, to receive the progress of the downloads
+    pub fn shows_downloads(&self) -> bool {
+        self.panes
+            .iter()
+            .any(|(_, content)| content.as_any().is::<downloads::DownloadsPane>())
+    }
+}
+
 impl Application for Panes {
     type Message = Message;
     type Executor = executor::Default;
@@ -149,6 +170,84 @@ impl Application for Panes {
                 };
             }
 
+            IntoEqualizer(pane) => {
+                let equalizer = equalizer::EqualizerPane::new(self.config.clone());
+                let refresh = equalizer.ask_refresh();
+                let result =
+                    self.panes
+                        .split(pane_grid::Axis::Horizontal, &pane, Box::new(equalizer));
+
+                if let Some((new_pane, _)) = result {
+                    self.focus = Some(new_pane);
+                    self.panes.close(&pane);
+                    return refresh;
+                } else {
+                    warn!("failed to close pane, keeping current one");
+                };
+            }
+
+            IntoQueue(pane) => {
+                let queue = queue::QueuePane::new(self.config.clone());
+                let refresh = queue.ask_refresh();
+                let result = self
+                    .panes
+                    .split(pane_grid::Axis::Horizontal, &pane, Box::new(queue));
+
+                if let Some((new_pane, _)) = result {
+                    self.focus = Some(new_pane);
+                    self.panes.close(&pane);
+                    return refresh;
+                } else {
+                    warn!("failed to close pane, keeping current one");
+                };
+            }
+
+            IntoDownloads(pane) => {
+                let downloads = downloads::DownloadsPane::new(self.config.clone());
+                let refresh = downloads.ask_refresh();
+                let result =
+                    self.panes
+                        .split(pane_grid::Axis::Horizontal, &pane, Box::new(downloads));
+
+                if let Some((new_pane, _)) = result {
+                    self.focus = Some(new_pane);
+                    self.panes.close(&pane);
+                    return refresh;
+                } else {
+                    warn!("failed to close pane, keeping current one");
+                };
+            }
+
+            IntoPodcasts(pane) => {
+                let podcasts = podcasts::PodcastsPane::new(self.config.clone());
+                let refresh = podcasts.ask_refresh();
+                let result =
+                    self.panes
+                        .split(pane_grid::Axis::Horizontal, &pane, Box::new(podcasts));
+
+                if let Some((new_pane, _)) = result {
+                    self.focus = Some(new_pane);
+                    self.panes.close(&pane);
+                    return refresh;
+                } else {
+                    warn!("failed to close pane, keeping current one");
+                };
+            }
+
+            IntoVisualizer(pane) => {
+                let visualizer = visualizer::VisualizerPane::default();
+                let result =
+                    self.panes
+                        .split(pane_grid::Axis::Horizontal, &pane, Box::new(visualizer));
+
+                if let Some((new_pane, _)) = result {
+                    self.focus = Some(new_pane);
+                    self.panes.close(&pane);
+                } else {
+                    warn!("failed to close pane, keeping current one");
+                };
+            }
+
             IntoControlBar(pane) => {
                 let menu = control_bar::ControlBar::new(&self.config);
                 let result = self
@@ -275,6 +374,11 @@ impl Content for Editor {
             .push(button(text("Menu")).on_press(Message::IntoMenu(pane)))
             .push(button(text("ControlBar")).on_press(Message::IntoControlBar(pane)))
             .push(button(text("List")).on_press(Message::IntoList(pane)))
+            .push(button(text("Equalizer")).on_press(Message::IntoEqualizer(pane)))
+            .push(button(text("Queue")).on_press(Message::IntoQueue(pane)))
+            .push(button(text("Visualizer")).on_press(Message::IntoVisualizer(pane)))
+            .push(button(text("Downloads")).on_press(Message::IntoDownloads(pane)))
+            .push(button(text("Podcasts")).on_press(Message::IntoPodcasts(pane)))
             .push(button(text("|")).on_press(Message::Split(pane_grid::Axis::Vertical, pane)));
 
         if total_panes > 1 {
@@ -1,10 +1,11 @@
-use iced::{Command, Element, Length};
+use iced::{theme, Alignment, Command, Element, Length};
 
-use iced::widget::{button, column, container, pane_grid, row, slider, text};
+use iced::widget::{button, column, container, pane_grid, row, slider, text, Space};
 
 use super::Content;
 use crate::config::Config;
 use crate::Message;
+use ouverture_core::audio::{AbLoop, PlaybackStatus, RepeatMode, ShuffleMode, Waveform};
 use ouverture_core::music::song::Song;
 
 use ouverture_core::server::Reply;
@@ -13,8 +14,17 @@ use log::debug;
 pub struct ControlBar {
     slider_value: u32,
     current_song_length: Option<u64>, // length in milliseconds
+    shuffle: ShuffleMode,
+    repeat: RepeatMode,
+    ab_loop: AbLoop,
+    stream_title: Option<String>, // what the stream being played plays
+    waveform: Option<(String, Waveform)>, // of the song with this source
     config: Config,
 }
+
+// the waveform is drawn as wide as the slider, with bars of 2 pixels
+const WAVEFORM_WIDTH: u16 = 250;
+const WAVEFORM_HEIGHT: f32 = 40.0;
 use iced_runtime::command::Action;
 
 use ouverture_core::server::Command as ServerCommand;
@@ -25,6 +35,11 @@ impl ControlBar {
         ControlBar {
             slider_value: 0, // between 0 and 4096
             current_song_length: None,
+            shuffle: ShuffleMode::Off,
+            repeat: RepeatMode::Off,
+            ab_loop: AbLoop::default(),
+            stream_title: None,
+            waveform: None,
             config: c.clone(),
         }
     }
@@ -49,31 +64,148 @@ impl ControlBar {
             self.config.server_address.to_string() + ":" + &self.config.server_port.to_string();
         debug!("refreshing control");
 
+        let status_address = address.clone();
+        Command::batch([
+            Command::single(Action::Future(Box::pin(async move {
+                let reply = Server::send_wait(&ServerCommand::GetCurrentSong, &address)
+                    .await
+                    .unwrap();
+                debug!("asked for new current song, got {reply:?}");
+                match reply {
+                    Reply::CurrentSong(song, seek, _) => {
+                        Message::ReceivedNewCurrentSong(song, seek)
+                    }
+                    _ => Message::Nothing,
+                }
+            }))),
+            Command::single(Action::Future(Box::pin(async move {
+                let reply = Server::send_wait(&ServerCommand::GetStatus, &status_address)
+                    .await
+                    .unwrap();
+                debug!("asked for playback status, got {reply:?}");
+                match reply {
+                    Reply::Status(status) => Message::ReceivedStatus(status),
+                    _ => Message::Nothing,
+                }
+            }))),
+        ])
+    }
+
+    pub fn set_shuffle(&mut self, shuffle: ShuffleMode) -> Command<Message> {
+        let address =
+            self.config.server_address.to_string() + ":" + &self.config.server_port.to_string();
+        self.shuffle = shuffle;
+
+        Command::single(Action::Future(Box::pin(async move {
+            Server::send_wait(&ServerCommand::SetShuffle(shuffle), &address)
+                .await
+                .unwrap();
+            Message::Nothing
+        })))
+    }
+
+    pub fn set_repeat(&mut self, repeat: RepeatMode) -> Command<Message> {
+        let address =
+            self.config.server_address.to_string() + ":" + &self.config.server_port.to_string();
+        self.repeat = repeat;
+
+        Command::single(Action::Future(Box::pin(async move {
+            Server::send_wait(&ServerCommand::SetRepeat(repeat), &address)
+                .await
+                .unwrap();
+            Message::Nothing
+        })))
+    }
+
+    /// Change the loop points, and show them as the server set them
+    pub fn set_loop(&mut self, command: ServerCommand) -> Command<Message> {
+        let address =
+            self.config.server_address.to_string() + ":" + &self.config.server_port.to_string();
+
         Command::single(Action::Future(Box::pin(async move {
-            let reply = Server::send_wait(&ServerCommand::GetCurrentSong, &address)
+            Server::send_wait(&command, &address).await.unwrap();
+            let reply = Server::send_wait(&ServerCommand::GetStatus, &address)
                 .await
                 .unwrap();
-            debug!("asked for new current song, got {reply:?}");
             match reply {
-                Reply::CurrentSong(song, seek) => Message::ReceivedNewCurrentSong(song, seek),
+                Reply::Status(status) => Message::ReceivedStatus(status),
                 _ => Message::Nothing,
             }
         })))
     }
 
+    pub fn refresh_from_status(&mut self, status: PlaybackStatus) -> Command<Message> {
+        self.shuffle = status.shuffle;
+        self.repeat = status.repeat;
+        self.ab_loop = status.ab_loop;
+        self.stream_title = status.stream_title;
+        Command::none()
+    }
+
     pub fn refresh_from_song(
         &mut self,
         opt_song: Option<Song>,
         opt_seek: Option<f32>,
     ) -> Command<Message> {
+        let mut commands = vec![];
         if let Some(song) = opt_song {
             debug!("recevied song length : {:?}", song.duration);
             self.current_song_length = Some(song.duration.as_millis() as u64);
+            commands.push(self.ask_waveform(song));
         }
         if let Some(seek) = opt_seek {
             self.slider_value = (seek * 4096f32) as u32;
         }
-        Command::single(Message::SliderChangedAuto(self.slider_value).into())
+        commands.push(Command::single(
+            Message::SliderChangedAuto(self.slider_value).into(),
+        ));
+        Command::batch(commands)
+    }
+
+    /// Get the song's waveform from the server, unless it's shown already
+    fn ask_waveform(&self, song: Song) -> Command<Message> {
+        let source: String = match song.source.clone() {
+            Some(source) => source.into(),
+            None => return Command::none(),
+        };
+        if matches!(&self.waveform, Some((shown, _)) if *shown == source) {
+            return Command::none();
+        }
+        let address =
+            self.config.server_address.to_string() + ":" + &self.config.server_port.to_string();
+        Command::single(Action::Future(Box::pin(async move {
+            match Server::send_wait(&ServerCommand::GetWaveform(song), &address).await {
+                Ok(Reply::Waveform(Some(waveform))) => {
+                    Message::ReceivedWaveform(source, Box::new(waveform))
+                }
+                reply => {
+                    debug!("no waveform for the current song: {reply:?}");
+                    Message::Nothing
+                }
+            }
+        })))
+    }
+
+    /// Bars from the lowest to the highest sample of each slice of the song
+    fn waveform_view(&self) -> Element<'_, Message> {
+        let mut bars = row![]
+            .spacing(1)
+            .height(WAVEFORM_HEIGHT)
+            .align_items(Alignment::Center);
+        if let Some((_, waveform)) = &self.waveform {
+            let count = (WAVEFORM_WIDTH / 2) as usize;
+            let slices = waveform.min.len().min(waveform.max.len());
+            for bar in 0..count.min(slices) {
+                let start = bar * slices / count;
+                let end = ((bar + 1) * slices / count).max(start + 1);
+                let min = waveform.min[start..end].iter().copied().min().unwrap_or(0);
+                let max = waveform.max[start..end].iter().copied().max().unwrap_or(0);
+                let height = (max as f32 - min as f32) / 254.0 * WAVEFORM_HEIGHT;
+                bars = bars
+                    .push(container(Space::new(1, height.max(1.0))).style(theme::Container::Box));
+            }
+        }
+        container(bars).width(WAVEFORM_WIDTH).into()
     }
 }
 
@@ -83,6 +215,16 @@ impl Content for ControlBar {
             Message::RefreshControl(_) => self.refresh(),
             Message::SliderChanged(value) => self.notify_seek(value),
             Message::ReceivedNewCurrentSong(song, seek) => self.refresh_from_song(song, Some(seek)),
+            Message::SetShuffle(shuffle) => self.set_shuffle(shuffle),
+            Message::SetRepeat(repeat) => self.set_repeat(repeat),
+            Message::SetLoopStart => self.set_loop(ServerCommand::SetLoopStart(None)),
+            Message::SetLoopEnd => self.set_loop(ServerCommand::SetLoopEnd(None)),
+            Message::ClearLoop => self.set_loop(ServerCommand::ClearLoop),
+            Message::ReceivedStatus(status) => self.refresh_from_status(status),
+            Message::ReceivedWaveform(source, waveform) => {
+                self.waveform = Some((source, *waveform));
+                Command::none()
+            }
             _ => Command::none(),
         }
     }
@@ -97,7 +239,56 @@ impl Content for ControlBar {
             .push(button(text("<-")).on_press(Message::Previous))
             .push(button(text(">")).on_press(Message::Toggle))
             .push(button(text("->")).on_press(Message::Next));
-        let controls = column![].spacing(15).push(button_controls).push(slider);
+
+        // each press goes on to the next mode
+        let next_shuffle = match self.shuffle {
+            ShuffleMode::Off => ShuffleMode::Random,
+            ShuffleMode::Random => ShuffleMode::Smart,
+            ShuffleMode::Smart => ShuffleMode::Off,
+        };
+        let next_repeat = match self.repeat {
+            RepeatMode::Off => RepeatMode::All,
+            RepeatMode::All => RepeatMode::One,
+            RepeatMode::One => RepeatMode::Off,
+        };
+        let mode_controls = row![]
+            .spacing(5)
+            .push(
+                button(text(format!("shuffle: {}", self.shuffle)))
+                    .on_press(Message::SetShuffle(next_shuffle)),
+            )
+            .push(
+                button(text(format!("repeat: {}", self.repeat)))
+                    .on_press(Message::SetRepeat(next_repeat)),
+            );
+        let loop_point = |ms: Option<u64>| match ms {
+            Some(ms) => format!("{}:{:02}", ms / 60_000, ms / 1000 % 60),
+            None => "-".to_string(),
+        };
+        let mut loop_controls = row![]
+            .spacing(5)
+            .push(
+                button(text(format!("A: {}", loop_point(self.ab_loop.a))))
+                    .on_press(Message::SetLoopStart),
+            )
+            .push(
+                button(text(format!("B: {}", loop_point(self.ab_loop.b))))
+                    .on_press(Message::SetLoopEnd),
+            );
+        if self.ab_loop != AbLoop::default() {
+            loop_controls =
+                loop_controls.push(button(text("clear loop")).on_press(Message::ClearLoop));
+        }
+        let mut controls = column![].spacing(15);
+        if let Some(title) = &self.stream_title {
+            controls = controls.push(text(title));
+        }
+        let controls = controls
+            .push(button_controls)
+            .push(mode_controls)
+            .push(loop_controls)
+            .push(self.waveform_view())
+            .push(slider);
 
         container(controls)
             .width(Length::Fill)
@@ -0,0 +1,208 @@
+use iced::widget::{button, column, container, pane_grid, progress_bar, row, scrollable};
+use iced::widget::{text, text_input};
+use iced::{Alignment, Command, Element, Length, Subscription};
+
+use super::Content;
+use crate::config::Config;
+use crate::Message;
+
+use futures_util::{pin_mut, SinkExt, StreamExt};
+use iced_runtime::command::Action;
+use log::{debug, warn};
+use std::time::Duration;
+
+use ouverture_core::download::{Download, DownloadState};
+use ouverture_core::music::song::Song;
+use ouverture_core::server::Command as ServerCommand;
+use ouverture_core::server::{Reply, Server, ServerEvent};
+
+// before subscribing again, once the server is gone
+const RESUBSCRIBE_DELAY_MS: u64 = 1000;
+
+pub struct DownloadsPane {
+    downloads: Vec<Download>,
+    url: String, // of the song to download, being typed
+    config: Config,
+}
+
+#[derive(Debug, Clone)]
+pub enum DownloadsMessage {
+    AskRefresh,
+    Received(Vec<Download>),
+    Changed(Box<Download>), // pushed by the server
+    UrlChanged(String),
+    Add,
+    Cancel(u32),
+}
+
+/// The changes of the downloads pushed by the server, while a downloads pane is open
+pub fn changes(config: &Config) -> Subscription<Message> {
+    let address = config.server_address.to_string() + ":" + &config.server_port.to_string();
+    iced::subscription::channel(
+        std::any::TypeId::of::<DownloadsPane>(),
+        16,
+        |mut output| async move {
+            loop {
+                let command = ServerCommand::Subscribe;
+                let replies = Server::send(&command, &address).await;
+                pin_mut!(replies);
+                while let Some(reply) = replies.next().await {
+                    match reply {
+                        Ok(Reply::Event(ServerEvent::Download(download))) => {
+                            let message = DownloadsMessage::Changed(download);
+                            let _ = output.send(Message::DownloadsMessage(message)).await;
+                        }
+                        Ok(_) => (),
+                        Err(e) => {
+                            warn!("lost the server events: {e:?}");
+                            break;
+                        }
+                    }
+                }
+                tokio::time::sleep(Duration::from_millis(RESUBSCRIBE_DELAY_MS)).await;
+            }
+        },
+    )
+}
+
+impl DownloadsPane {
+    pub fn new(config: Config) -> Self {
+        DownloadsPane {
+            downloads: vec![],
+            url: String::new(),
+            config,
+        }
+    }
+
+    fn address(&self) -> String {
+        self.config.server_address.to_string() + ":" + &self.config.server_port.to_string()
+    }
+
+    pub fn ask_refresh(&self) -> Command<Message> {
+        self.ask_refresh_after(None)
+    }
+
+    /// Get the downloads from the server, once the given command (if any) is done
+    fn ask_refresh_after(&self, command: Option<ServerCommand>) -> Command<Message> {
+        let address = self.address();
+        Command::single(Action::Future(Box::pin(async move {
+            if let Some(command) = command {
+                let reply = Server::send_wait(&command, &address).await;
+                debug!("sent {command} to the server, got {reply:?}");
+            }
+            match Server::send_wait(&ServerCommand::GetDownloads, &address).await {
+                Ok(Reply::Downloads(downloads)) => {
+                    Message::DownloadsMessage(DownloadsMessage::Received(downloads))
+                }
+                reply => {
+                    warn!("failed to get the downloads from the server: {reply:?}");
+                    Message::Nothing
+                }
+            }
+        })))
+    }
+}
+
+fn song_title(song: &Song) -> String {
+    match (&song.artist, &song.title) {
+        (Some(artist), Some(title)) => format!("{artist} - {title}"),
+        (None, Some(title)) => title.clone(),
+        _ => "<unknown>".to_string(),
+    }
+}
+
+impl Content for DownloadsPane {
+    fn update(&mut self, message: Message) -> Command<Message> {
+        let Message::DownloadsMessage(message) = message else {
+            return Command::none();
+        };
+        match message {
+            DownloadsMessage::AskRefresh => self.ask_refresh(),
+            DownloadsMessage::Received(downloads) => {
+                self.downloads = downloads;
+                Command::none()
+            }
+            DownloadsMessage::Changed(download) => {
+                match self.downloads.iter_mut().find(|d| d.id == download.id) {
+                    Some(known) => *known = *download,
+                    None => self.downloads.push(*download),
+                }
+                Command::none()
+            }
+            DownloadsMessage::UrlChanged(url) => {
+                self.url = url;
+                Command::none()
+            }
+            DownloadsMessage::Add => {
+                let url = self.url.trim().to_string();
+                if !Song::is_youtube_url(&url) {
+                    warn!("not a YouTube URL: '{url}'");
+                    return Command::none();
+                }
+                self.url.clear();
+                let song = Song::from_youtube_url(&url);
+                self.ask_refresh_after(Some(ServerCommand::Download(song)))
+            }
+            DownloadsMessage::Cancel(id) => {
+                self.ask_refresh_after(Some(ServerCommand::CancelDownload(id)))
+            }
+        }
+    }
+
+    fn view(&self, _pane: pane_grid::Pane, _total_panes: usize) -> Element<Message> {
+        let message = Message::DownloadsMessage;
+        let mut downloads = column![].spacing(5);
+        for download in &self.downloads {
+            let title = text(song_title(&download.song));
+            let line = match &download.state {
+                DownloadState::Queued | DownloadState::Running => {
+                    let state = match download.state {
+                        DownloadState::Queued => "queued".to_string(),
+                        _ => format!("attempt {}", download.attempts),
+                    };
+                    row![
+                        button(text("x")).on_press(message(DownloadsMessage::Cancel(download.id))),
+                        title,
+                        progress_bar(0.0..=1.0, download.progress)
+                            .width(Length::Fixed(150.0))
+                            .height(Length::Fixed(10.0)),
+                        text(state).size(14),
+                    ]
+                }
+                DownloadState::Done(song) => {
+                    row![
+                        title,
+                        text(format!("done, as '{}'", song_title(song))).size(14)
+                    ]
+                }
+                DownloadState::Failed(reason) => {
+                    row![title, text(format!("failed: {reason}")).size(14)]
+                }
+                DownloadState::Cancelled => row![title, text("cancelled").size(14)],
+            };
+            downloads = downloads.push(line.spacing(10).align_items(Alignment::Center));
+        }
+
+        let controls = row![
+            text_input("YouTube URL", &self.url)
+                .on_input(|url| Message::DownloadsMessage(DownloadsMessage::UrlChanged(url)))
+                .on_submit(message(DownloadsMessage::Add)),
+            button(text("Download")).on_press(message(DownloadsMessage::Add)),
+            button(text("Refresh")).on_press(message(DownloadsMessage::AskRefresh)),
+        ]
+        .spacing(10);
+
+        container(column![controls, scrollable(downloads)].spacing(15))
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .padding(5)
+            .into()
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
+        self
+    }
+}
@@ -0,0 +1,195 @@
+use iced::widget::{button, column, container, pane_grid, pick_list, row, text, vertical_slider};
+use iced::{Alignment, Command, Element, Length};
+
+use super::Content;
+use crate::config::Config;
+use crate::Message;
+
+use iced_runtime::command::Action;
+use log::{debug, warn};
+
+use ouverture_core::audio::{Equalizer, FilterKind};
+use ouverture_core::server::Command as ServerCommand;
+use ouverture_core::server::{Reply, Server};
+
+// range of the gain sliders, in dB
+const MAX_GAIN_DB: f32 = 12.0;
+
+pub struct EqualizerPane {
+    equalizer: Equalizer,
+    presets: Vec<String>,
+    selected_preset: Option<String>,
+    config: Config,
+}
+
+#[derive(Debug, Clone)]
+pub enum EqualizerMessage {
+    AskRefresh,
+    Received(Equalizer, Vec<String>),
+    GainChanged(usize, f32), // band index and new gain
+    PreampChanged(f32),
+    PresetSelected(String),
+    Reset,
+}
+
+impl EqualizerPane {
+    pub fn new(config: Config) -> Self {
+        EqualizerPane {
+            equalizer: Equalizer::graphic(),
+            presets: vec![],
+            selected_preset: None,
+            config,
+        }
+    }
+
+    fn address(&self) -> String {
+        self.config.server_address.to_string() + ":" + &self.config.server_port.to_string()
+    }
+
+    pub fn ask_refresh(&self) -> Command<Message> {
+        self.ask_refresh_after(None)
+    }
+
+    /// Get the equalizer from the server, once the given command (if any) is done
+    fn ask_refresh_after(&self, command: Option<ServerCommand>) -> Command<Message> {
+        let address = self.address();
+        Command::single(Action::Future(Box::pin(async move {
+            if let Some(command) = command {
+                let reply = Server::send_wait(&command, &address).await;
+                debug!("sent {command} to the server, got {reply:?}");
+            }
+            match Server::send_wait(&ServerCommand::GetEqualizer, &address).await {
+                Ok(Reply::Equalizer(equalizer, presets)) => {
+                    Message::EqualizerMessage(EqualizerMessage::Received(equalizer, presets))
+                }
+                reply => {
+                    warn!("failed to get the equalizer from the server: {reply:?}");
+                    Message::Nothing
+                }
+            }
+        })))
+    }
+
+    fn send(&self, command: ServerCommand) -> Command<Message> {
+        let address = self.address();
+        Command::single(Action::Future(Box::pin(async move {
+            let reply = Server::send_wait(&command, &address).await;
+            debug!("sent {command} to the server, got {reply:?}");
+            Message::Nothing
+        })))
+    }
+
+    fn send_equalizer(&mut self) -> Command<Message> {
+        self.selected_preset = None;
+        self.send(ServerCommand::SetEqualizer(self.equalizer.clone()))
+    }
+}
+
+/// A vertical gain slider, with its value on top and its label below
+fn gain_slider<'a>(
+    label: String,
+    gain_db: f32,
+    on_change: impl Fn(f32) -> Message + 'a,
+) -> Element<'a, Message> {
+    column![
+        text(format!("{gain_db:+.1}")).size(12),
+        vertical_slider(-MAX_GAIN_DB..=MAX_GAIN_DB, gain_db, on_change)
+            .step(0.5)
+            .height(150),
+        text(label).size(12),
+    ]
+    .spacing(5)
+    .align_items(Alignment::Center)
+    .into()
+}
+
+impl Content for EqualizerPane {
+    fn update(&mut self, message: Message) -> Command<Message> {
+        let message = match message {
+            Message::EqualizerMessage(message) => message,
+            _ => return Command::none(),
+        };
+        match message {
+            EqualizerMessage::AskRefresh => self.ask_refresh(),
+            EqualizerMessage::Received(equalizer, presets) => {
+                // start from a graphic equalizer rather than nothing to edit
+                self.equalizer = if equalizer.bands.is_empty() {
+                    Equalizer {
+                        preamp_db: equalizer.preamp_db,
+                        ..Equalizer::graphic()
+                    }
+                } else {
+                    equalizer
+                };
+                self.presets = presets;
+                Command::none()
+            }
+            EqualizerMessage::GainChanged(i, gain_db) => {
+                if let Some(band) = self.equalizer.bands.get_mut(i) {
+                    band.gain_db = gain_db;
+                }
+                self.send_equalizer()
+            }
+            EqualizerMessage::PreampChanged(preamp_db) => {
+                self.equalizer.preamp_db = preamp_db;
+                self.send_equalizer()
+            }
+            EqualizerMessage::PresetSelected(name) => {
+                self.selected_preset = Some(name.clone());
+                self.ask_refresh_after(Some(ServerCommand::SetEqualizerPreset(name)))
+            }
+            EqualizerMessage::Reset => {
+                self.equalizer = Equalizer::graphic();
+                self.send_equalizer()
+            }
+        }
+    }
+
+    fn view(&self, _pane: pane_grid::Pane, _total_panes: usize) -> Element<Message> {
+        let mut bands = row![gain_slider(
+            "pre".to_string(),
+            self.equalizer.preamp_db,
+            |g| Message::EqualizerMessage(EqualizerMessage::PreampChanged(g))
+        )]
+        .spacing(10);
+        for (i, band) in self.equalizer.bands.iter().enumerate() {
+            let label = if band.frequency >= 1000.0 {
+                format!("{}k", band.frequency / 1000.0)
+            } else {
+                format!("{}", band.frequency)
+            };
+            let label = match band.kind {
+                FilterKind::Peak => label,
+                FilterKind::LowShelf => label + " ls",
+                FilterKind::HighShelf => label + " hs",
+                FilterKind::LowPass => label + " lp",
+                FilterKind::HighPass => label + " hp",
+            };
+            bands = bands.push(gain_slider(label, band.gain_db, move |g| {
+                Message::EqualizerMessage(EqualizerMessage::GainChanged(i, g))
+            }));
+        }
+
+        let presets = row![
+            pick_list(self.presets.clone(), self.selected_preset.clone(), |name| {
+                Message::EqualizerMessage(EqualizerMessage::PresetSelected(name))
+            })
+            .placeholder("Presets"),
+            button(text("Flat")).on_press(Message::EqualizerMessage(EqualizerMessage::Reset)),
+        ]
+        .spacing(10);
+
+        container(column![presets, bands].spacing(15))
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .padding(5)
+            .into()
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
+        self
+    }
+}
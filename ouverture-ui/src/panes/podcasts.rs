@@ -0,0 +1,245 @@
+use iced::widget::{button, checkbox, column, container, pane_grid, row, scrollable};
+use iced::widget::{text, text_input};
+use iced::{Alignment, Command, Element, Length};
+
+use super::Content;
+use crate::config::Config;
+use crate::Message;
+
+use iced_runtime::command::Action;
+use log::{debug, warn};
+
+use ouverture_core::music::song::Song;
+use ouverture_core::podcast::{Episode, Podcast};
+use ouverture_core::server::Command as ServerCommand;
+use ouverture_core::server::{Reply, Server};
+
+pub struct PodcastsPane {
+    podcasts: Vec<Podcast>,
+    episodes: Vec<Episode>,   // of the selected podcast, or of all of them
+    selected: Option<String>, // feed of the podcast whose episodes are shown
+    url: String,              // of the feed to subscribe to, being typed
+    auto_download: bool,      // for the podcast to subscribe to
+    config: Config,
+}
+
+#[derive(Debug, Clone)]
+pub enum PodcastsMessage {
+    AskRefresh,
+    Received(Vec<Podcast>, Vec<Episode>),
+    UrlChanged(String),
+    AutoDownloadToggled(bool),
+    Subscribe,
+    Unsubscribe(String),
+    Select(Option<String>),
+    CheckFeeds,
+    Download(Song),
+    SetPlayed(Song, bool),
+}
+
+impl PodcastsPane {
+    pub fn new(config: Config) -> Self {
+        PodcastsPane {
+            podcasts: vec![],
+            episodes: vec![],
+            selected: None,
+            url: String::new(),
+            auto_download: false,
+            config,
+        }
+    }
+
+    fn address(&self) -> String {
+        self.config.server_address.to_string() + ":" + &self.config.server_port.to_string()
+    }
+
+    pub fn ask_refresh(&self) -> Command<Message> {
+        self.ask_refresh_after(None)
+    }
+
+    /// Get the podcasts and the episodes shown from the server, once the given command
+    /// (if any) is done
+    fn ask_refresh_after(&self, command: Option<ServerCommand>) -> Command<Message> {
+        let address = self.address();
+        let selected = self.selected.clone();
+        Command::single(Action::Future(Box::pin(async move {
+            if let Some(command) = command {
+                let reply = Server::send_wait(&command, &address).await;
+                debug!("sent {command} to the server, got {reply:?}");
+            }
+            let podcasts = match Server::send_wait(&ServerCommand::GetPodcasts, &address).await {
+                Ok(Reply::Podcasts(podcasts)) => podcasts,
+                reply => {
+                    warn!("failed to get the podcasts from the server: {reply:?}");
+                    return Message::Nothing;
+                }
+            };
+            let command = ServerCommand::GetEpisodes(selected);
+            match Server::send_wait(&command, &address).await {
+                Ok(Reply::Episodes(episodes)) => {
+                    Message::PodcastsMessage(PodcastsMessage::Received(podcasts, episodes))
+                }
+                reply => {
+                    warn!("failed to get the episodes from the server: {reply:?}");
+                    Message::Nothing
+                }
+            }
+        })))
+    }
+}
+
+fn episode_title(episode: &Episode) -> String {
+    let title = episode
+        .song
+        .title
+        .clone()
+        .unwrap_or("<untitled>".to_string());
+    match episode.published {
+        Some(date) => format!("{} {title}", date.format("%Y-%m-%d")),
+        None => title,
+    }
+}
+
+impl Content for PodcastsPane {
+    fn update(&mut self, message: Message) -> Command<Message> {
+        let Message::PodcastsMessage(message) = message else {
+            return Command::none();
+        };
+        match message {
+            PodcastsMessage::AskRefresh => self.ask_refresh(),
+            PodcastsMessage::Received(podcasts, episodes) => {
+                self.podcasts = podcasts;
+                self.episodes = episodes;
+                Command::none()
+            }
+            PodcastsMessage::UrlChanged(url) => {
+                self.url = url;
+                Command::none()
+            }
+            PodcastsMessage::AutoDownloadToggled(auto_download) => {
+                self.auto_download = auto_download;
+                Command::none()
+            }
+            PodcastsMessage::Subscribe => {
+                let url = self.url.trim().to_string();
+                if url.is_empty() {
+                    return Command::none();
+                }
+                self.url.clear();
+                let podcast = Podcast {
+                    url,
+                    auto_download: self.auto_download,
+                    ..Default::default()
+                };
+                self.ask_refresh_after(Some(ServerCommand::AddPodcast(podcast)))
+            }
+            PodcastsMessage::Unsubscribe(url) => {
+                if self.selected.as_ref() == Some(&url) {
+                    self.selected = None;
+                }
+                self.ask_refresh_after(Some(ServerCommand::RemovePodcast(url)))
+            }
+            PodcastsMessage::Select(selected) => {
+                self.selected = selected;
+                self.ask_refresh()
+            }
+            PodcastsMessage::CheckFeeds => {
+                self.ask_refresh_after(Some(ServerCommand::RefreshPodcasts))
+            }
+            PodcastsMessage::Download(song) => {
+                self.ask_refresh_after(Some(ServerCommand::Download(song)))
+            }
+            PodcastsMessage::SetPlayed(song, played) => {
+                self.ask_refresh_after(Some(ServerCommand::SetPlayed(song, played)))
+            }
+        }
+    }
+
+    fn view(&self, _pane: pane_grid::Pane, _total_panes: usize) -> Element<Message> {
+        let message = Message::PodcastsMessage;
+
+        let mut podcasts =
+            column![button(text("All")).on_press(message(PodcastsMessage::Select(None)))]
+                .spacing(5);
+        for podcast in &self.podcasts {
+            let title = podcast.title.clone().unwrap_or(podcast.url.clone());
+            let selected = self.selected.as_ref() == Some(&podcast.url);
+            let title = if selected {
+                format!("> {title}")
+            } else {
+                title
+            };
+            podcasts = podcasts.push(
+                row![
+                    button(text("x"))
+                        .on_press(message(PodcastsMessage::Unsubscribe(podcast.url.clone()))),
+                    button(text(title))
+                        .on_press(message(PodcastsMessage::Select(Some(podcast.url.clone())))),
+                ]
+                .spacing(10)
+                .align_items(Alignment::Center),
+            );
+        }
+
+        let mut episodes = column![].spacing(5);
+        for episode in &self.episodes {
+            let song = &episode.song;
+            let played = if episode.played { "unplayed" } else { "played" };
+            let mut line = row![
+                button(text("Play")).on_press(Message::Play(Some(song.clone()))),
+                button(text(format!("Mark {played}"))).on_press(message(
+                    PodcastsMessage::SetPlayed(song.clone(), !episode.played)
+                )),
+            ];
+            if !episode.downloaded {
+                line = line.push(
+                    button(text("Download"))
+                        .on_press(message(PodcastsMessage::Download(song.clone()))),
+                );
+            }
+            let state = match (episode.played, episode.downloaded) {
+                (true, true) => "played, downloaded",
+                (true, false) => "played",
+                (false, true) => "downloaded",
+                (false, false) => "",
+            };
+            line = line
+                .push(text(episode_title(episode)))
+                .push(text(state).size(14));
+            episodes = episodes.push(line.spacing(10).align_items(Alignment::Center));
+        }
+
+        let controls = row![
+            text_input("Feed URL or path", &self.url)
+                .on_input(|url| Message::PodcastsMessage(PodcastsMessage::UrlChanged(url)))
+                .on_submit(message(PodcastsMessage::Subscribe)),
+            checkbox("Auto-download", self.auto_download, |auto_download| {
+                Message::PodcastsMessage(PodcastsMessage::AutoDownloadToggled(auto_download))
+            }),
+            button(text("Subscribe")).on_press(message(PodcastsMessage::Subscribe)),
+            button(text("Check feeds")).on_press(message(PodcastsMessage::CheckFeeds)),
+            button(text("Refresh")).on_press(message(PodcastsMessage::AskRefresh)),
+        ]
+        .spacing(10)
+        .align_items(Alignment::Center);
+
+        let lists = row![
+            scrollable(podcasts).width(Length::FillPortion(1)),
+            scrollable(episodes).width(Length::FillPortion(3)),
+        ]
+        .spacing(15);
+
+        container(column![controls, lists].spacing(15))
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .padding(5)
+            .into()
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
+        self
+    }
+}
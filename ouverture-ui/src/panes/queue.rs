@@ -0,0 +1,161 @@
+use iced::widget::{button, column, container, pane_grid, row, scrollable, text};
+use iced::{Alignment, Command, Element, Length};
+
+use super::Content;
+use crate::config::Config;
+use crate::Message;
+
+use iced_runtime::command::Action;
+use log::{debug, warn};
+
+use ouverture_core::audio::QueueListing;
+use ouverture_core::music::song::Song;
+use ouverture_core::server::Command as ServerCommand;
+use ouverture_core::server::{Reply, Server};
+
+pub struct QueuePane {
+    queue: QueueListing,
+    config: Config,
+}
+
+#[derive(Debug, Clone)]
+pub enum QueueMessage {
+    AskRefresh,
+    Received(Box<QueueListing>),
+    // positions among the upcoming songs
+    MoveUp(usize),
+    MoveDown(usize),
+    Remove(usize),
+    JumpTo(usize),
+    Clear,
+}
+
+impl QueuePane {
+    pub fn new(config: Config) -> Self {
+        QueuePane {
+            queue: QueueListing::default(),
+            config,
+        }
+    }
+
+    fn address(&self) -> String {
+        self.config.server_address.to_string() + ":" + &self.config.server_port.to_string()
+    }
+
+    pub fn ask_refresh(&self) -> Command<Message> {
+        self.ask_refresh_after(None)
+    }
+
+    /// Get the queue from the server, once the given command (if any) is done
+    fn ask_refresh_after(&self, command: Option<ServerCommand>) -> Command<Message> {
+        let address = self.address();
+        Command::single(Action::Future(Box::pin(async move {
+            if let Some(command) = command {
+                let reply = Server::send_wait(&command, &address).await;
+                debug!("sent {command} to the server, got {reply:?}");
+            }
+            match Server::send_wait(&ServerCommand::GetQueue, &address).await {
+                Ok(Reply::Queue(queue)) => {
+                    Message::QueueMessage(QueueMessage::Received(Box::new(queue)))
+                }
+                reply => {
+                    warn!("failed to get the queue from the server: {reply:?}");
+                    Message::Nothing
+                }
+            }
+        })))
+    }
+}
+
+fn song_title(song: &Song) -> String {
+    match (&song.artist, &song.title) {
+        (Some(artist), Some(title)) => format!("{artist} - {title}"),
+        (None, Some(title)) => title.clone(),
+        _ => "<unknown>".to_string(),
+    }
+}
+
+impl Content for QueuePane {
+    fn update(&mut self, message: Message) -> Command<Message> {
+        let message = match message {
+            Message::QueueMessage(message) => message,
+            // what's playing may have moved on
+            Message::ReceivedNewCurrentSong(..) => return self.ask_refresh(),
+            _ => return Command::none(),
+        };
+        match message {
+            QueueMessage::AskRefresh => self.ask_refresh(),
+            QueueMessage::Received(queue) => {
+                self.queue = *queue;
+                Command::none()
+            }
+            QueueMessage::MoveUp(position) => {
+                self.ask_refresh_after(Some(ServerCommand::Move(position, position - 1)))
+            }
+            QueueMessage::MoveDown(position) => {
+                self.ask_refresh_after(Some(ServerCommand::Move(position, position + 1)))
+            }
+            QueueMessage::Remove(position) => {
+                self.ask_refresh_after(Some(ServerCommand::Remove(position)))
+            }
+            QueueMessage::JumpTo(position) => {
+                self.ask_refresh_after(Some(ServerCommand::JumpTo(position)))
+            }
+            QueueMessage::Clear => self.ask_refresh_after(Some(ServerCommand::ClearQueue)),
+        }
+    }
+
+    fn view(&self, _pane: pane_grid::Pane, _total_panes: usize) -> Element<Message> {
+        let mut songs = column![].spacing(5);
+        for song in &self.queue.past {
+            songs = songs.push(text(song_title(song)).size(14));
+        }
+        songs = songs.push(match &self.queue.current {
+            Some(song) => text(format!("> {}", song_title(song))),
+            None => text("> (nothing playing)"),
+        });
+
+        let last = self.queue.future.len().saturating_sub(1);
+        for (position, song) in self.queue.future.iter().enumerate() {
+            let message = Message::QueueMessage;
+            let mut up = button(text("^"));
+            if position > 0 {
+                up = up.on_press(message(QueueMessage::MoveUp(position)));
+            }
+            let mut down = button(text("v"));
+            if position < last {
+                down = down.on_press(message(QueueMessage::MoveDown(position)));
+            }
+            songs = songs.push(
+                row![
+                    up,
+                    down,
+                    button(text(">")).on_press(message(QueueMessage::JumpTo(position))),
+                    button(text("x")).on_press(message(QueueMessage::Remove(position))),
+                    text(song_title(song)),
+                ]
+                .spacing(5)
+                .align_items(Alignment::Center),
+            );
+        }
+
+        let controls = row![
+            button(text("Refresh")).on_press(Message::QueueMessage(QueueMessage::AskRefresh)),
+            button(text("Clear")).on_press(Message::QueueMessage(QueueMessage::Clear)),
+        ]
+        .spacing(10);
+
+        container(column![controls, scrollable(songs)].spacing(15))
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .padding(5)
+            .into()
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
+        self
+    }
+}
@@ -0,0 +1,118 @@
+use iced::widget::{column, container, pane_grid, progress_bar, row, text, Space};
+use iced::{theme, Alignment, Command, Element, Length, Subscription};
+
+use super::Content;
+use crate::config::Config;
+use crate::Message;
+
+use futures_util::{pin_mut, SinkExt, StreamExt};
+use log::warn;
+use std::time::Duration;
+
+use ouverture_core::audio::{Levels, SILENCE_DB};
+use ouverture_core::server::Command as ServerCommand;
+use ouverture_core::server::{Reply, Server, ServerEvent};
+
+// lowest level shown by the meters and the spectrum, in dB
+const FLOOR_DB: f32 = -72.0;
+const SPECTRUM_HEIGHT: f32 = 150.0;
+const SPECTRUM_BAR_WIDTH: f32 = 8.0;
+// before subscribing again, once the server is gone
+const RESUBSCRIBE_DELAY_MS: u64 = 1000;
+
+#[derive(Default)]
+pub struct VisualizerPane {
+    levels: Levels,
+}
+
+#[derive(Debug, Clone)]
+pub enum VisualizerMessage {
+    Received(Levels),
+}
+
+/// The levels pushed by the server to its subscribers, while a visualizer pane is open
+pub fn levels(config: &Config) -> Subscription<Message> {
+    let address = config.server_address.to_string() + ":" + &config.server_port.to_string();
+    iced::subscription::channel(
+        std::any::TypeId::of::<VisualizerPane>(),
+        16,
+        |mut output| async move {
+            loop {
+                let command = ServerCommand::Subscribe;
+                let replies = Server::send(&command, &address).await;
+                pin_mut!(replies);
+                while let Some(reply) = replies.next().await {
+                    match reply {
+                        Ok(Reply::Event(ServerEvent::Levels(levels))) => {
+                            let message = VisualizerMessage::Received(levels);
+                            let _ = output.send(Message::VisualizerMessage(message)).await;
+                        }
+                        Ok(_) => (),
+                        Err(e) => {
+                            warn!("lost the server events: {e:?}");
+                            break;
+                        }
+                    }
+                }
+                tokio::time::sleep(Duration::from_millis(RESUBSCRIBE_DELAY_MS)).await;
+            }
+        },
+    )
+}
+
+/// Between 0 and 1, from the floor to full scale
+fn fraction(db: f32) -> f32 {
+    ((db - FLOOR_DB) / -FLOOR_DB).clamp(0.0, 1.0)
+}
+
+impl Content for VisualizerPane {
+    fn update(&mut self, message: Message) -> Command<Message> {
+        if let Message::VisualizerMessage(VisualizerMessage::Received(levels)) = message {
+            self.levels = levels;
+        }
+        Command::none()
+    }
+
+    fn view(&self, _pane: pane_grid::Pane, _total_panes: usize) -> Element<Message> {
+        let mut meters = column![].spacing(5);
+        for (channel, (peak, rms)) in self.levels.peak.iter().zip(&self.levels.rms).enumerate() {
+            let peak = match *peak {
+                peak if peak <= SILENCE_DB => "-inf dB".to_string(),
+                peak => format!("{peak:.1} dB"),
+            };
+            meters = meters.push(
+                row![
+                    text(channel + 1),
+                    progress_bar(0.0..=1.0, fraction(*rms)).width(250),
+                    text(peak),
+                ]
+                .spacing(10)
+                .align_items(Alignment::Center),
+            );
+        }
+
+        let mut spectrum = row![]
+            .spacing(2)
+            .height(SPECTRUM_HEIGHT)
+            .align_items(Alignment::End);
+        for db in &self.levels.spectrum {
+            let height = (fraction(*db) * SPECTRUM_HEIGHT).max(1.0);
+            spectrum = spectrum.push(
+                container(Space::new(SPECTRUM_BAR_WIDTH, height)).style(theme::Container::Box),
+            );
+        }
+
+        container(column![meters, spectrum].spacing(15))
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .padding(5)
+            .into()
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
+        self
+    }
+}